@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gRPC service is generated only when enabled, so the HTTP API builds without protoc.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/signing.proto")?;
+    Ok(())
+}
@@ -0,0 +1,381 @@
+//! Function key and Entra ID bearer token validation for the signing endpoints.
+use azure_core::{
+    error::ErrorKind,
+    http::{ClientOptions, Context, Method, Pipeline, RawResponse, Request, Url},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use warp::{Filter, Rejection};
+
+use crate::{ApiError, Options};
+
+// Signing keys are fetched again after this long.
+const KEYS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// Tokens signed with an unknown key fetch the keys again at most this often.
+const KEYS_MIN_REFRESH: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Deserialize)]
+struct OpenIdConfiguration {
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    scp: String,
+}
+
+struct Keys {
+    set: JwkSet,
+    fetched: Instant,
+}
+
+/// Validates the issuer, audience, signature and lifetime of bearer tokens, and that they
+/// carry one of the required app roles or delegated scopes.
+struct TokenValidator {
+    issuer: String,
+    validation: Validation,
+    roles: Vec<String>,
+    scopes: Vec<String>,
+    pipeline: Pipeline,
+    keys: Mutex<Option<Keys>>,
+}
+
+pub(crate) fn list(value: &Option<String>) -> Vec<String> {
+    value
+        .iter()
+        .flat_map(|x| x.split([',', ' ']))
+        .filter(|x| !x.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn unauthorized(message: impl ToString) -> ApiError {
+    ApiError::Unauthorized(message.to_string())
+}
+
+// Compare keys in constant time, so they can't be guessed from response times.
+pub(crate) fn same_key(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// A claim of a bearer token, which has to have been validated by the authorizer.
+pub(crate) fn claim(authorization: &str, name: &str) -> Option<String> {
+    let token = authorization.strip_prefix("Bearer ")?;
+    let payload = BASE64_URL_SAFE_NO_PAD
+        .decode(token.split('.').nth(1)?)
+        .ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    claims.get(name)?.as_str().map(str::to_owned)
+}
+
+impl TokenValidator {
+    fn new(options: &Options) -> std::io::Result<Option<Self>> {
+        let Some(audience) = &options.auth_audience else {
+            return Ok(None);
+        };
+        let Some(issuer) = &options.auth_issuer else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "AUTH_ISSUER is required with AUTH_AUDIENCE",
+            ));
+        };
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[audience]);
+        validation.set_issuer(&[issuer]);
+        Ok(Some(Self {
+            issuer: issuer.clone(),
+            validation,
+            roles: list(&options.auth_roles),
+            scopes: list(&options.auth_scopes),
+            pipeline: Pipeline::new(
+                option_env!("CARGO_PKG_NAME"),
+                option_env!("CARGO_PKG_VERSION"),
+                ClientOptions::default(),
+                vec![],
+                vec![],
+                None,
+            ),
+            keys: Mutex::default(),
+        }))
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> azure_core::Result<T> {
+        let mut request = Request::new(url, Method::Get);
+        let response: RawResponse = self
+            .pipeline
+            .send(&Context::new(), &mut request, None)
+            .await?;
+        if !response.status().is_success() {
+            return Err(azure_core::Error::with_message(
+                ErrorKind::Other,
+                format!("GET {} returned {}", request.url(), response.status()),
+            ));
+        }
+        response.into_body().json()
+    }
+
+    // The signing keys of the issuer, from the jwks_uri of its OpenID configuration.
+    async fn fetch(&self) -> azure_core::Result<JwkSet> {
+        let url = Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        ))?;
+        let configuration: OpenIdConfiguration = self.get(url).await?;
+        self.get(Url::parse(&configuration.jwks_uri)?).await
+    }
+
+    async fn key(&self, kid: &str) -> Result<Jwk, ApiError> {
+        if let Some(keys) = &*self.keys.lock().unwrap() {
+            let known = keys.set.find(kid);
+            if known.is_some() && keys.fetched.elapsed() < KEYS_TTL
+                || keys.fetched.elapsed() < KEYS_MIN_REFRESH
+            {
+                return known
+                    .cloned()
+                    .ok_or_else(|| unauthorized("the token is signed with an unknown key"));
+            }
+        }
+        let set = self.fetch().await.map_err(ApiError::Azure)?;
+        let key = set.find(kid).cloned();
+        *self.keys.lock().unwrap() = Some(Keys {
+            set,
+            fetched: Instant::now(),
+        });
+        key.ok_or_else(|| unauthorized("the token is signed with an unknown key"))
+    }
+
+    async fn authorize(&self, authorization: Option<String>) -> Result<(), ApiError> {
+        let token = authorization
+            .as_deref()
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("a bearer token is required"))?;
+        let kid = decode_header(token)
+            .map_err(unauthorized)?
+            .kid
+            .ok_or_else(|| unauthorized("the token has no key id"))?;
+        let key = DecodingKey::from_jwk(&self.key(&kid).await?).map_err(unauthorized)?;
+        let claims = decode::<Claims>(token, &key, &self.validation)
+            .map_err(unauthorized)?
+            .claims;
+        self.permits(&claims)
+    }
+
+    // Any role or scope is enough when none are required.
+    fn permits(&self, claims: &Claims) -> Result<(), ApiError> {
+        if self.roles.is_empty() && self.scopes.is_empty() {
+            return Ok(());
+        }
+        let role = claims.roles.iter().any(|x| self.roles.contains(x));
+        let scope = claims
+            .scp
+            .split(' ')
+            .any(|x| self.scopes.iter().any(|y| x == y));
+        if role || scope {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(
+                "the token has none of the required roles or scopes".to_owned(),
+            ))
+        }
+    }
+}
+
+/// Accepts requests with one of the function keys, or with a valid bearer token.
+pub(crate) struct Authorizer {
+    function_keys: Vec<String>,
+    tokens: Option<TokenValidator>,
+}
+
+impl Authorizer {
+    /// The authorizer configured by the options and the keys of the tenants, `None` when
+    /// neither function keys nor an audience are set and the API is anonymous.
+    pub(crate) fn new(
+        options: &Options,
+        tenant_keys: Vec<String>,
+    ) -> std::io::Result<Option<Self>> {
+        let mut function_keys = list(&options.function_keys);
+        function_keys.extend(tenant_keys);
+        let tokens = TokenValidator::new(options)?;
+        if function_keys.is_empty() && tokens.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            function_keys,
+            tokens,
+        }))
+    }
+
+    pub(crate) fn is_key(&self, key: &str) -> bool {
+        self.function_keys.iter().any(|x| same_key(x, key))
+    }
+
+    /// Whether requests without a valid key were authorized by a validated token.
+    pub(crate) fn validates_tokens(&self) -> bool {
+        self.tokens.is_some()
+    }
+
+    /// Who made an authorized request: the function key that authorized it, or the object id
+    /// (or client id) of its validated token.
+    pub(crate) fn caller(&self, key: Option<&str>, authorization: Option<&str>) -> Option<String> {
+        if let Some(key) = key
+            && self.is_key(key)
+        {
+            return Some(format!("key:{key}"));
+        }
+        if !self.validates_tokens() {
+            return None;
+        }
+        let authorization = authorization?;
+        let id = claim(authorization, "oid").or_else(|| claim(authorization, "azp"))?;
+        Some(format!("token:{id}"))
+    }
+
+    pub(crate) async fn authorize(
+        &self,
+        key: Option<String>,
+        authorization: Option<String>,
+    ) -> Result<(), ApiError> {
+        if let Some(key) = key
+            && self.is_key(&key)
+        {
+            return Ok(());
+        }
+        match &self.tokens {
+            Some(tokens) => tokens.authorize(authorization).await,
+            None => Err(unauthorized(
+                "a valid x-functions-key header or code parameter is required",
+            )),
+        }
+    }
+}
+
+/// Passes requests with a valid function key or token, or every request when there is no
+/// authorizer. Keys are taken from the `x-functions-key` header or the `code` parameter,
+/// like the Functions host does.
+pub(crate) fn authorize(
+    authorizer: Option<Arc<Authorizer>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-functions-key")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(
+            move |key: Option<String>,
+                  mut query: HashMap<String, String>,
+                  authorization: Option<String>| {
+                let authorizer = authorizer.clone();
+                async move {
+                    if let Some(authorizer) = authorizer {
+                        let key = key.or_else(|| query.remove("code"));
+                        authorizer
+                            .authorize(key, authorization)
+                            .await
+                            .map_err(warp::reject::custom)?;
+                    }
+                    Ok::<_, Rejection>(())
+                }
+            },
+        )
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(roles: Option<&str>, scopes: Option<&str>) -> Options {
+        Options {
+            function_keys: Some("key1,key2".to_owned()),
+            auth_issuer: Some("https://login.microsoftonline.com/tenant/v2.0".to_owned()),
+            auth_audience: Some("api://c2pa".to_owned()),
+            auth_roles: roles.map(str::to_owned),
+            auth_scopes: scopes.map(str::to_owned),
+            ..Options::default()
+        }
+    }
+
+    fn claims(roles: &[&str], scp: &str) -> Claims {
+        Claims {
+            roles: roles.iter().map(|x| (*x).to_owned()).collect(),
+            scp: scp.to_owned(),
+        }
+    }
+
+    // A token with the claims, unsigned since only validated tokens are read.
+    fn bearer(claims: &str) -> String {
+        format!("Bearer e30.{}.", BASE64_URL_SAFE_NO_PAD.encode(claims))
+    }
+
+    #[test]
+    fn test_same_key() {
+        assert!(same_key("secret", "secret"));
+        assert!(!same_key("secret", "secreT"));
+        assert!(!same_key("secret", "secret2"));
+        assert!(!same_key("", "secret"));
+    }
+
+    #[test]
+    fn test_roles_and_scopes() {
+        let validator = TokenValidator::new(&options(Some("Sign"), Some("sign.all")))
+            .unwrap()
+            .unwrap();
+        assert!(validator.permits(&claims(&["Read", "Sign"], "")).is_ok());
+        assert!(validator.permits(&claims(&[], "openid sign.all")).is_ok());
+        assert!(matches!(
+            validator.permits(&claims(&["Read"], "sign")),
+            Err(ApiError::Forbidden(_))
+        ));
+
+        let validator = TokenValidator::new(&options(None, None)).unwrap().unwrap();
+        assert!(validator.permits(&claims(&[], "")).is_ok());
+    }
+
+    #[test]
+    fn test_issuer_is_required() {
+        let options = Options {
+            auth_issuer: None,
+            ..options(None, None)
+        };
+        assert!(TokenValidator::new(&options).is_err());
+    }
+
+    #[test]
+    fn test_caller() {
+        let authorizer = Authorizer::new(&options(None, None), vec!["tenant".to_owned()])
+            .unwrap()
+            .unwrap();
+        assert!(authorizer.is_key("key2"));
+        assert!(authorizer.is_key("tenant"));
+        let token = bearer(r#"{"oid":"object","azp":"client"}"#);
+        assert_eq!(
+            authorizer.caller(Some("key1"), Some(&token)).as_deref(),
+            Some("key:key1")
+        );
+        assert_eq!(
+            authorizer.caller(Some("guess"), Some(&token)).as_deref(),
+            Some("token:object")
+        );
+        let token = bearer(r#"{"azp":"client"}"#);
+        assert_eq!(
+            authorizer.caller(None, Some(&token)).as_deref(),
+            Some("token:client")
+        );
+        assert_eq!(authorizer.caller(Some("guess"), None), None);
+    }
+}
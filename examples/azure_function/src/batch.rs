@@ -0,0 +1,240 @@
+//! `POST /api/sign/batch`: sign every file of a zip, or of a `multipart/form-data` upload, and
+//! return a zip of the signed files with a `report.json` of the per-file results. Every file
+//! being signed takes a permit of the concurrency limit, like a single sign request.
+use c2pa::Context;
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek},
+    path::Path,
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use warp::{Buf, Rejection, Reply, Stream, multipart::FormData, reply::Response};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    ApiError, chunks, copy_to_file, manifest_override, media_type, part_type, sign_to, streamed,
+};
+
+// Files signed at the same time.
+const CONCURRENCY: usize = 4;
+
+const REPORT: &str = "report.json";
+
+struct Entry {
+    name: String,
+    content_type: String,
+    file: NamedTempFile,
+}
+
+#[derive(Serialize)]
+struct Outcome {
+    name: String,
+    signed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// The format is taken from the extension, or from the content when there is none.
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+fn bad_zip(err: zip::result::ZipError) -> ApiError {
+    ApiError::BadRequest(format!("Invalid zip: {err}"))
+}
+
+// Extract the files of the archive, failing once they add up to more than `limit` bytes so
+// a small zip can't fill the disk.
+fn read_zip(file: &std::fs::File, limit: u64) -> Result<Vec<Entry>, ApiError> {
+    let mut archive = ZipArchive::new(file).map_err(bad_zip)?;
+    let mut entries = Vec::new();
+    let mut total = 0;
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(bad_zip)?;
+        if entry.is_dir() {
+            continue;
+        }
+        if entry.enclosed_name().is_none() {
+            return Err(ApiError::BadRequest(format!(
+                "Invalid file name in zip: {}",
+                entry.name()
+            )));
+        }
+        let name = entry.name().to_owned();
+        let mut temp = NamedTempFile::new().map_err(ApiError::Io)?;
+        total += io::copy(&mut entry.take(limit - total + 1), &mut temp).map_err(ApiError::Io)?;
+        if total > limit {
+            return Err(ApiError::TooLarge(limit));
+        }
+        temp.rewind().map_err(ApiError::Io)?;
+        entries.push(Entry {
+            content_type: extension(&name),
+            name,
+            file: temp,
+        });
+    }
+    Ok(entries)
+}
+
+// Every part with a file name is signed, a `manifest` part overrides the manifest definition.
+async fn read_form(
+    mut form: FormData,
+    limit: u64,
+) -> Result<(Vec<Entry>, Option<String>), ApiError> {
+    let mut entries = Vec::new();
+    let mut manifest_definition = None;
+    while let Some(part) = form.next().await {
+        let part = part.map_err(ApiError::Warp)?;
+        if part.name() == "manifest" {
+            let mut data = Vec::new();
+            crate::copy(&mut data, Box::pin(part.stream()), limit).await?;
+            let text = String::from_utf8(data)
+                .map_err(|_| ApiError::BadRequest("The manifest part is not UTF-8".to_owned()))?;
+            manifest_definition = Some(manifest_override(text)?);
+            continue;
+        }
+        let Some(name) = part.filename().map(str::to_owned) else {
+            log::debug!("Ignoring the form part {}", part.name());
+            continue;
+        };
+        let content_type = part_type(&part).unwrap_or_else(|| extension(&name));
+        let file = NamedTempFile::new().map_err(ApiError::Io)?;
+        copy_to_file(file.as_file(), Box::pin(part.stream()), limit).await?;
+        entries.push(Entry {
+            name,
+            content_type,
+            file,
+        });
+    }
+    Ok((entries, manifest_definition))
+}
+
+async fn sign_entry(
+    context: &Arc<Context>,
+    manifest_definition: &str,
+    mut entry: Entry,
+) -> Result<NamedTempFile, ApiError> {
+    let content_type = media_type(Some(&entry.content_type), entry.file.as_file_mut())?;
+    let mut output = NamedTempFile::new().map_err(ApiError::Io)?;
+    sign_to(
+        context,
+        manifest_definition,
+        &content_type,
+        entry.file.as_file_mut(),
+        output.as_file_mut(),
+    )
+    .await?;
+    output.rewind().map_err(ApiError::Io)?;
+    Ok(output)
+}
+
+// Sign the entries a few at a time and zip the signed files, in the order of the upload. The
+// permit of the request is given back once the upload is read and every entry waits for its
+// own, so batches waiting for permits don't hold any and can't starve each other. Entries
+// named like an earlier one fail, since a zip can only have one of them.
+async fn sign_all(
+    permit: OwnedSemaphorePermit,
+    semaphore: Arc<Semaphore>,
+    context: Arc<Context>,
+    manifest_definition: &str,
+    entries: Vec<Entry>,
+) -> Result<Response, ApiError> {
+    if entries.is_empty() {
+        return Err(ApiError::BadRequest("The upload has no files".to_owned()));
+    }
+    drop(permit);
+    let mut names = HashSet::new();
+    let results: Vec<_> = stream::iter(entries)
+        .map(|entry| {
+            let unique = names.insert(entry.name.clone());
+            let (context, semaphore) = (context.clone(), semaphore.clone());
+            async move {
+                let name = entry.name.clone();
+                if !unique {
+                    let error = ApiError::BadRequest(format!("Another file is named {name}"));
+                    return (name, Err(error));
+                }
+                let result = match semaphore.acquire_owned().await {
+                    Ok(_permit) => sign_entry(&context, manifest_definition, entry).await,
+                    Err(_) => Err(ApiError::Busy),
+                };
+                (name, result)
+            }
+        })
+        .buffered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut zip = ZipWriter::new(NamedTempFile::new().map_err(ApiError::Io)?);
+    let options = SimpleFileOptions::default();
+    let mut report = Vec::new();
+    for (name, result) in results {
+        match result {
+            Ok(mut output) => {
+                zip.start_file(name.as_str(), options)
+                    .map_err(|x| ApiError::Io(x.into()))?;
+                io::copy(&mut output, &mut zip).map_err(ApiError::Io)?;
+                report.push(Outcome {
+                    name,
+                    signed: true,
+                    error: None,
+                });
+            }
+            Err(err) => {
+                log::warn!("Failed to sign {name}: {err:?}");
+                report.push(Outcome {
+                    name,
+                    signed: false,
+                    error: Some(err.message()),
+                });
+            }
+        }
+    }
+    zip.start_file(REPORT, options)
+        .map_err(|x| ApiError::Io(x.into()))?;
+    serde_json::to_writer_pretty(&mut zip, &report).map_err(|x| ApiError::Io(x.into()))?;
+    let mut file = zip.finish().map_err(|x| ApiError::Io(x.into()))?;
+    file.rewind().map_err(ApiError::Io)?;
+    Ok(streamed(chunks(file.into_file()), "application/zip"))
+}
+
+pub(crate) async fn sign_zip(
+    permit: OwnedSemaphorePermit,
+    semaphore: Arc<Semaphore>,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    limit: u64,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
+) -> Result<impl Reply, Rejection> {
+    let archive = NamedTempFile::new().map_err(|x| warp::reject::custom(ApiError::Io(x)))?;
+    copy_to_file(archive.as_file(), stream, limit)
+        .await
+        .map_err(warp::reject::custom)?;
+    let entries = read_zip(archive.as_file(), limit).map_err(warp::reject::custom)?;
+    sign_all(permit, semaphore, context, &manifest_definition, entries)
+        .await
+        .map_err(warp::reject::custom)
+}
+
+pub(crate) async fn sign_form(
+    permit: OwnedSemaphorePermit,
+    semaphore: Arc<Semaphore>,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    limit: u64,
+    form: FormData,
+) -> Result<impl Reply, Rejection> {
+    let (entries, manifest) = read_form(form, limit).await.map_err(warp::reject::custom)?;
+    let manifest_definition = manifest.as_deref().unwrap_or(&manifest_definition);
+    sign_all(permit, semaphore, context, manifest_definition, entries)
+        .await
+        .map_err(warp::reject::custom)
+}
@@ -0,0 +1,522 @@
+//! `POST /api/sign/blob`: download the input blob, sign it and upload the output blob from the
+//! function, so large files never go through the HTTP front end. Uploads too large for the
+//! temporary directory of the function are spilled to a blob and signed from there.
+use azure_core::{
+    credentials::TokenCredential,
+    http::{
+        ClientOptions, Context as HttpContext, Method, Pipeline, Request, Url,
+        headers::{CONTENT_LENGTH, CONTENT_TYPE},
+    },
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use c2pa::Context;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+use tokio::{runtime::Handle, sync::OwnedSemaphorePermit};
+use uuid::Uuid;
+use warp::{Buf, Rejection, Reply, Stream, reply::json};
+
+use crate::{ApiError, Options, allowed_host, media_type, sign_to};
+
+const STORAGE_SCOPE: &str = "https://storage.azure.com/.default";
+const STORAGE_VERSION: &str = "2021-08-06";
+// Spilled uploads are written and read back in blocks of this size.
+const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The blobs to sign, given as SAS URLs or as URLs the function's identity can access.
+#[derive(Deserialize)]
+pub(crate) struct BlobRequest {
+    input: String,
+    output: String,
+    /// The format of the input, by default the content type of the blob or the extension.
+    content_type: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BlobResponse {
+    output: String,
+    content_type: String,
+}
+
+/// Reads and writes blobs of the allowed storage hosts.
+pub(crate) struct Blobs {
+    pipeline: Pipeline,
+    credential: Option<Arc<dyn TokenCredential>>,
+    allowed_hosts: Vec<String>,
+    /// The accounts and containers the function's identity is used for.
+    identity_containers: Vec<(String, String)>,
+}
+
+// The URL without its query, so SAS tokens don't end up in logs or responses.
+fn redact(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_query(None);
+    url
+}
+
+fn upstream(url: &Url, status: impl std::fmt::Display) -> ApiError {
+    ApiError::Upstream(format!("{} returned {status}", redact(url)))
+}
+
+// Block ids are base64 and must all have the same length.
+fn block_id(index: usize) -> String {
+    BASE64_STANDARD.encode(format!("{index:08}"))
+}
+
+// The account host and container of a blob or container URL.
+fn container(url: &Url) -> Option<(String, String)> {
+    let container = url.path_segments()?.next().filter(|x| !x.is_empty())?;
+    Some((url.host_str()?.to_owned(), container.to_owned()))
+}
+
+impl Blobs {
+    /// The blobs of the options. The identity is used for the `BLOB_IDENTITY_CONTAINERS` and
+    /// the spill container, which have to be on the allowed hosts.
+    pub(crate) fn new(
+        credential: Option<Arc<dyn TokenCredential>>,
+        options: &Options,
+    ) -> io::Result<Self> {
+        let mut blobs = Self {
+            pipeline: Pipeline::new(
+                option_env!("CARGO_PKG_NAME"),
+                option_env!("CARGO_PKG_VERSION"),
+                ClientOptions::default(),
+                vec![],
+                vec![],
+                None,
+            ),
+            credential,
+            allowed_hosts: options
+                .blob_allowed_hosts
+                .split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            identity_containers: Vec::new(),
+        };
+        let containers = options
+            .blob_identity_containers
+            .iter()
+            .flat_map(|x| x.split(','))
+            .chain(options.spill_container_url.as_deref())
+            .map(str::trim)
+            .filter(|x| !x.is_empty());
+        for url in containers {
+            let container = blobs.check(url).ok().as_ref().and_then(container);
+            let container = container.ok_or_else(|| {
+                io::Error::other(format!(
+                    "{url} is not a container URL of an allowed storage host"
+                ))
+            })?;
+            blobs.identity_containers.push(container);
+        }
+        Ok(blobs)
+    }
+
+    // Only storage hosts are fetched, so callers can't make the function request other URLs.
+    fn check(&self, url: &str) -> Result<Url, ApiError> {
+        let url = Url::parse(url).map_err(|x| ApiError::BadRequest(format!("Invalid URL: {x}")))?;
+        if url.scheme() == "https"
+            && allowed_host(url.host_str().unwrap_or_default(), &self.allowed_hosts)
+        {
+            return Ok(url);
+        }
+        Err(ApiError::BadRequest(format!(
+            "{} is not an https URL of an allowed storage host",
+            redact(&url)
+        )))
+    }
+
+    // SAS URLs carry their own authorization. Others get a token of the function's identity
+    // when they are in one of its containers, so callers can't have the function copy any
+    // blob its identity can read.
+    async fn request(&self, url: &Url, method: Method) -> Result<Request, ApiError> {
+        let mut request = Request::new(url.clone(), method);
+        request.insert_header("x-ms-version", STORAGE_VERSION);
+        if !url.query_pairs().any(|(key, _)| key == "sig") {
+            if !container(url).is_some_and(|x| self.identity_containers.contains(&x)) {
+                return Err(ApiError::BadRequest(format!(
+                    "{} has no SAS token and is not in one of the BLOB_IDENTITY_CONTAINERS",
+                    redact(url)
+                )));
+            }
+            let credential = self.credential.as_ref().ok_or_else(|| {
+                ApiError::BadRequest(format!(
+                    "{} has no SAS token and the function has no identity",
+                    redact(url)
+                ))
+            })?;
+            let token = credential
+                .get_token(&[STORAGE_SCOPE], None)
+                .await
+                .map_err(ApiError::Azure)?;
+            request.insert_header("authorization", format!("Bearer {}", token.token.secret()));
+        }
+        Ok(request)
+    }
+
+    /// Stream a blob to `file`, returning its content type.
+    async fn download(
+        &self,
+        url: &Url,
+        mut file: &File,
+        limit: u64,
+    ) -> Result<Option<String>, ApiError> {
+        let mut request = self.request(url, Method::Get).await?;
+        let response = self
+            .pipeline
+            .stream(&HttpContext::new(), &mut request, None)
+            .await
+            .map_err(ApiError::Azure)?;
+        if !response.status().is_success() {
+            return Err(upstream(url, response.status()));
+        }
+        let length = response
+            .headers()
+            .get_optional_str(&CONTENT_LENGTH)
+            .and_then(|x| x.parse::<u64>().ok());
+        if length.is_some_and(|x| x > limit) {
+            return Err(ApiError::TooLarge(limit));
+        }
+        let content_type = response
+            .headers()
+            .get_optional_str(&CONTENT_TYPE)
+            .filter(|x| *x != "application/octet-stream")
+            .map(str::to_owned);
+
+        let mut body = response.into_body();
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(ApiError::Azure)?;
+            size += chunk.len() as u64;
+            if size > limit {
+                return Err(ApiError::TooLarge(limit));
+            }
+            file.write_all(&chunk).map_err(ApiError::Io)?;
+        }
+        file.rewind().map_err(ApiError::Io)?;
+        Ok(content_type)
+    }
+
+    async fn send(&self, url: &Url, request: &mut Request) -> Result<(), ApiError> {
+        let response = self
+            .pipeline
+            .send(&HttpContext::new(), request, None)
+            .await
+            .map_err(ApiError::Azure)?;
+        if !response.status().is_success() {
+            return Err(upstream(url, response.status()));
+        }
+        Ok(())
+    }
+
+    /// The bytes `first` to `last` of a blob.
+    async fn range(&self, url: &Url, first: u64, last: u64) -> Result<Vec<u8>, ApiError> {
+        let mut request = self.request(url, Method::Get).await?;
+        request.insert_header("x-ms-range", format!("bytes={first}-{last}"));
+        let response = self
+            .pipeline
+            .stream(&HttpContext::new(), &mut request, None)
+            .await
+            .map_err(ApiError::Azure)?;
+        if !response.status().is_success() {
+            return Err(upstream(url, response.status()));
+        }
+        let mut block = Vec::with_capacity((last - first + 1) as usize);
+        let mut body = response.into_body();
+        while let Some(chunk) = body.next().await {
+            block.extend_from_slice(&chunk.map_err(ApiError::Azure)?);
+        }
+        Ok(block)
+    }
+
+    async fn put_block(&self, url: &Url, id: &str, data: Vec<u8>) -> Result<(), ApiError> {
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .append_pair("comp", "block")
+            .append_pair("blockid", id);
+        let mut request = self.request(&url, Method::Put).await?;
+        request.set_body(data);
+        self.send(&url, &mut request).await
+    }
+
+    async fn put_block_list(
+        &self,
+        url: &Url,
+        ids: &[String],
+        content_type: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let mut url = url.clone();
+        url.query_pairs_mut().append_pair("comp", "blocklist");
+        let mut body = "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>".to_owned();
+        for id in ids {
+            _ = write!(body, "<Latest>{id}</Latest>");
+        }
+        body.push_str("</BlockList>");
+        let mut request = self.request(&url, Method::Put).await?;
+        request.insert_header(CONTENT_TYPE, "application/xml");
+        if let Some(content_type) = content_type {
+            request.insert_header("x-ms-blob-content-type", content_type.to_owned());
+        }
+        request.set_body(body.into_bytes());
+        self.send(&url, &mut request).await
+    }
+
+    async fn delete(&self, url: &Url) -> Result<(), ApiError> {
+        let mut request = self.request(url, Method::Delete).await?;
+        self.send(url, &mut request).await
+    }
+
+    // The file is uploaded a block at a time, so outputs larger than memory can be written.
+    async fn upload(&self, url: &Url, path: &Path, content_type: &str) -> Result<(), ApiError> {
+        let mut file = File::open(path).map_err(ApiError::Io)?;
+        let mut ids = Vec::new();
+        loop {
+            let mut block = Vec::with_capacity(BLOCK_SIZE);
+            (&mut file)
+                .take(BLOCK_SIZE as u64)
+                .read_to_end(&mut block)
+                .map_err(ApiError::Io)?;
+            if block.is_empty() {
+                break;
+            }
+            let last = block.len() < BLOCK_SIZE;
+            let id = block_id(ids.len());
+            self.put_block(url, &id, block).await?;
+            ids.push(id);
+            if last {
+                break;
+            }
+        }
+        self.put_block_list(url, &ids, Some(content_type)).await
+    }
+}
+
+/// The container uploads larger than `SPILL_THRESHOLD_BYTES` are written to instead of the
+/// temporary directory, which is small on the Consumption and Flex Consumption plans.
+pub(crate) struct Spill {
+    blobs: Arc<Blobs>,
+    container: Url,
+    threshold: u64,
+}
+
+impl Spill {
+    /// The spill container of the options, none when `SPILL_CONTAINER_URL` is unset.
+    pub(crate) fn new(blobs: Arc<Blobs>, options: &Options) -> io::Result<Option<Self>> {
+        let Some(container) = options.spill_container_url.as_deref() else {
+            return Ok(None);
+        };
+        let container = blobs.check(container).map_err(|_| {
+            io::Error::other("SPILL_CONTAINER_URL is not an https URL of an allowed storage host")
+        })?;
+        Ok(Some(Self {
+            blobs,
+            container,
+            threshold: options.spill_threshold,
+        }))
+    }
+
+    /// Whether a body of this `content-length` is spilled.
+    pub(crate) fn spills(&self, length: u64) -> bool {
+        length > self.threshold
+    }
+
+    /// Write the body to a new blob of the container in blocks, which are only kept in memory
+    /// one at a time.
+    pub(crate) async fn write(
+        &self,
+        mut stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+        limit: u64,
+    ) -> Result<SpilledBlob, ApiError> {
+        let mut url = self.container.clone();
+        url.path_segments_mut()
+            .map_err(|_| ApiError::BadRequest("Invalid spill container URL".to_owned()))?
+            .pop_if_empty()
+            .push(&Uuid::new_v4().to_string());
+        // The blob is deleted when dropped, also when the upload fails half way.
+        let mut blob = SpilledBlob {
+            blobs: self.blobs.clone(),
+            url,
+            length: 0,
+            position: 0,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            block_start: 0,
+            fetches: 0,
+        };
+        let mut ids = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let mut chunk = chunk.map_err(ApiError::Warp)?;
+            blob.length += chunk.remaining() as u64;
+            if blob.length > limit {
+                return Err(ApiError::TooLarge(limit));
+            }
+            while chunk.has_remaining() {
+                let count = chunk.chunk().len().min(BLOCK_SIZE - blob.block.len());
+                blob.block.extend_from_slice(&chunk.chunk()[..count]);
+                chunk.advance(count);
+                if blob.block.len() == BLOCK_SIZE {
+                    ids.push(blob.put_block(ids.len()).await?);
+                }
+            }
+        }
+        if !blob.block.is_empty() {
+            ids.push(blob.put_block(ids.len()).await?);
+        }
+        self.blobs.put_block_list(&blob.url, &ids, None).await?;
+        log::info!("Spilled {} bytes to {}", blob.length, redact(&blob.url));
+        Ok(blob)
+    }
+}
+
+/// An upload spilled to a blob, read in blocks by ranged requests while it is signed and
+/// deleted when dropped.
+///
+/// Every read outside the block last fetched is a ranged GET of up to `BLOCK_SIZE` bytes.
+/// Signing reads the input through a few times, hashing it and copying it to the output, and
+/// seeks back to the boxes or segments it parses in between, so a blob of n blocks takes a
+/// small multiple of n requests. Reads fail after `MAX_FETCHES_PER_BLOCK` requests per block,
+/// so an access pattern that goes back and forth can't make unbounded requests.
+pub(crate) struct SpilledBlob {
+    blobs: Arc<Blobs>,
+    url: Url,
+    length: u64,
+    position: u64,
+    /// The block being written, and once the blob is complete the block last read.
+    block: Vec<u8>,
+    block_start: u64,
+    fetches: u64,
+}
+
+// The requests a spilled blob may take per block, and besides them for small blobs.
+const MAX_FETCHES_PER_BLOCK: u64 = 8;
+const MAX_EXTRA_FETCHES: u64 = 64;
+
+impl SpilledBlob {
+    async fn put_block(&mut self, index: usize) -> Result<String, ApiError> {
+        let id = block_id(index);
+        let data = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_SIZE));
+        self.blobs.put_block(&self.url, &id, data).await?;
+        Ok(id)
+    }
+
+    // c2pa reads its input synchronously, so the block is fetched on this worker thread, which
+    // block_in_place hands its other tasks off from for the duration of the request.
+    fn fetch(&mut self) -> io::Result<()> {
+        let blocks = self.length.div_ceil(BLOCK_SIZE as u64);
+        if self.fetches >= blocks * MAX_FETCHES_PER_BLOCK + MAX_EXTRA_FETCHES {
+            return Err(io::Error::other(format!(
+                "Reading {} took more than {} requests",
+                redact(&self.url),
+                self.fetches
+            )));
+        }
+        self.fetches += 1;
+        let last = (self.position + BLOCK_SIZE as u64).min(self.length) - 1;
+        let block = tokio::task::block_in_place(|| {
+            Handle::current().block_on(self.blobs.range(&self.url, self.position, last))
+        });
+        self.block = block.map_err(|x| io::Error::other(format!("{x:?}")))?;
+        self.block_start = self.position;
+        Ok(())
+    }
+}
+
+impl Read for SpilledBlob {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.length {
+            return Ok(0);
+        }
+        let offset = self
+            .position
+            .checked_sub(self.block_start)
+            .filter(|x| *x < self.block.len() as u64);
+        let offset = match offset {
+            Some(offset) => offset as usize,
+            None => {
+                self.fetch()?;
+                0
+            }
+        };
+        let count = buf.len().min(self.block.len() - offset);
+        buf[..count].copy_from_slice(&self.block[offset..offset + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for SpilledBlob {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.length.checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the blob",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Drop for SpilledBlob {
+    fn drop(&mut self) {
+        let (blobs, url) = (self.blobs.clone(), self.url.clone());
+        tokio::spawn(async move {
+            if let Err(err) = blobs.delete(&url).await {
+                log::warn!("Can't delete the spilled upload {}: {err:?}", redact(&url));
+            }
+        });
+    }
+}
+
+pub(crate) async fn sign_blob(
+    _permit: OwnedSemaphorePermit,
+    blobs: Arc<Blobs>,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    limit: u64,
+    request: BlobRequest,
+) -> Result<impl Reply, Rejection> {
+    let result = async {
+        let source = blobs.check(&request.input)?;
+        let destination = blobs.check(&request.output)?;
+        let mut input = NamedTempFile::new().map_err(ApiError::Io)?;
+        let blob_type = blobs.download(&source, input.as_file(), limit).await?;
+        let extension = || {
+            let name = source.path_segments()?.next_back()?;
+            Some(Path::new(name).extension()?.to_str()?.to_owned())
+        };
+        let declared = request.content_type.or(blob_type).or_else(extension);
+        let content_type = media_type(declared.as_deref(), input.as_file_mut())?;
+
+        let mut output = NamedTempFile::new().map_err(ApiError::Io)?;
+        sign_to(
+            &context,
+            &manifest_definition,
+            &content_type,
+            input.as_file_mut(),
+            output.as_file_mut(),
+        )
+        .await?;
+        blobs
+            .upload(&destination, output.path(), &content_type)
+            .await?;
+        log::info!("Signed {} to {}", redact(&source), redact(&destination));
+        Ok::<_, ApiError>(json(&BlobResponse {
+            output: redact(&destination).to_string(),
+            content_type,
+        }))
+    };
+    result.await.map_err(warp::reject::custom)
+}
@@ -0,0 +1,241 @@
+//! Verification results cached by the SHA-256 of the file, so verifying the same asset again
+//! doesn't redo the manifest validation. Results are kept in memory, and with the `redis`
+//! feature also in a Redis cache shared by the instances.
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Read, Seek},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{Config, Options, metrics};
+
+struct Entry {
+    value: Arc<[u8]>,
+    stored: Instant,
+    /// When the entry was last read, the least recent one is evicted first.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Memory {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+impl Memory {
+    fn get(&mut self, key: &str, ttl: Duration) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        if entry.stored.elapsed() > ttl {
+            self.entries.remove(key);
+            return None;
+        }
+        entry.used = self.clock;
+        Some(entry.value.clone())
+    }
+
+    fn put(&mut self, key: String, value: Arc<[u8]>, capacity: usize) {
+        self.clock += 1;
+        if !self.entries.contains_key(&key) && self.entries.len() >= capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                stored: Instant::now(),
+                used: self.clock,
+            },
+        );
+    }
+}
+
+/// The verify replies of recent files, by the hash of the file and the kind of reply.
+pub(crate) struct VerifyCache {
+    // Its c2pa settings, reloaded while the function runs, are part of the key.
+    config: Arc<Config>,
+    memory: Mutex<Memory>,
+    capacity: usize,
+    ttl: Duration,
+    #[cfg(feature = "redis")]
+    redis: Option<redis::Client>,
+    #[cfg(feature = "redis")]
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+}
+
+impl VerifyCache {
+    /// The cache of the options, none when `VERIFY_CACHE_ENTRIES` is 0 and there is no Redis.
+    pub(crate) fn new(options: &Options, config: Arc<Config>) -> io::Result<Option<Self>> {
+        #[cfg(feature = "redis")]
+        let redis = options
+            .verify_cache_redis_url
+            .as_deref()
+            .map(redis::Client::open)
+            .transpose()
+            .map_err(io::Error::other)?;
+        #[cfg(not(feature = "redis"))]
+        if options.verify_cache_redis_url.is_some() {
+            log::warn!("VERIFY_CACHE_REDIS_URL is ignored, the function was built without redis");
+        }
+        #[cfg(feature = "redis")]
+        let shared = redis.is_some();
+        #[cfg(not(feature = "redis"))]
+        let shared = false;
+        if (options.verify_cache_entries == 0 && !shared) || options.verify_cache_ttl_secs == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            config,
+            memory: Mutex::default(),
+            capacity: options.verify_cache_entries,
+            ttl: Duration::from_secs(options.verify_cache_ttl_secs),
+            #[cfg(feature = "redis")]
+            redis,
+            #[cfg(feature = "redis")]
+            connection: tokio::sync::OnceCell::new(),
+        }))
+    }
+
+    /// The key of the file: the hash of its content with the media type, the kind of reply and
+    /// the hash of the current c2pa settings, since all of them change the result. Hashing the
+    /// settings rather than counting reloads keeps keys shared by instances with the same
+    /// settings in Redis.
+    pub(crate) fn key(
+        &self,
+        file: &mut (impl Read + Seek),
+        content_type: &str,
+        raw: bool,
+    ) -> io::Result<String> {
+        let settings = Sha256::digest(self.config.settings().unwrap_or_default());
+        let mut hasher = Sha256::new();
+        io::copy(file, &mut hasher)?;
+        file.rewind()?;
+        let mut key = format!("c2pa:verify:{}:{content_type}:", u8::from(raw));
+        for x in &settings[..8] {
+            _ = write!(key, "{x:02x}");
+        }
+        key.push(':');
+        for x in hasher.finalize() {
+            _ = write!(key, "{x:02x}");
+        }
+        Ok(key)
+    }
+
+    pub(crate) async fn get(&self, key: &str) -> Option<Arc<[u8]>> {
+        let value = self.memory.lock().unwrap().get(key, self.ttl);
+        #[cfg(feature = "redis")]
+        let value = match value {
+            None => self
+                .get_shared(key)
+                .await
+                .inspect(|x| self.remember(key.to_owned(), x.clone())),
+            value => value,
+        };
+        metrics::verify_cache(value.is_some());
+        value
+    }
+
+    pub(crate) async fn put(&self, key: String, value: Arc<[u8]>) {
+        #[cfg(feature = "redis")]
+        self.put_shared(&key, &value).await;
+        self.remember(key, value);
+    }
+
+    fn remember(&self, key: String, value: Arc<[u8]>) {
+        if self.capacity > 0 {
+            self.memory.lock().unwrap().put(key, value, self.capacity);
+        }
+    }
+
+    // Redis errors are logged and the request carries on as a cache miss.
+    #[cfg(feature = "redis")]
+    async fn shared(&self) -> Option<redis::aio::ConnectionManager> {
+        let client = self.redis.as_ref()?;
+        self.connection
+            .get_or_try_init(|| redis::aio::ConnectionManager::new(client.clone()))
+            .await
+            .inspect_err(|err| log::warn!("Can't connect to the verify cache: {err}"))
+            .ok()
+            .cloned()
+    }
+
+    #[cfg(feature = "redis")]
+    async fn get_shared(&self, key: &str) -> Option<Arc<[u8]>> {
+        let mut connection = self.shared().await?;
+        redis::AsyncCommands::get::<_, Option<Vec<u8>>>(&mut connection, key)
+            .await
+            .inspect_err(|err| log::warn!("Can't read the verify cache: {err}"))
+            .ok()
+            .flatten()
+            .map(Arc::from)
+    }
+
+    #[cfg(feature = "redis")]
+    async fn put_shared(&self, key: &str, value: &[u8]) {
+        let Some(mut connection) = self.shared().await else {
+            return;
+        };
+        if let Err(err) = redis::AsyncCommands::set_ex::<_, _, ()>(
+            &mut connection,
+            key,
+            value,
+            self.ttl.as_secs(),
+        )
+        .await
+        {
+            log::warn!("Can't write the verify cache: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn value(text: &str) -> Arc<[u8]> {
+        Arc::from(text.as_bytes())
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut memory = Memory::default();
+        memory.put("a".to_owned(), value("1"), 2);
+        memory.put("b".to_owned(), value("2"), 2);
+        assert!(memory.get("a", TTL).is_some());
+        memory.put("c".to_owned(), value("3"), 2);
+        assert!(memory.get("b", TTL).is_none());
+        assert_eq!(memory.get("a", TTL).as_deref(), Some(&b"1"[..]));
+        assert_eq!(memory.get("c", TTL).as_deref(), Some(&b"3"[..]));
+    }
+
+    #[test]
+    fn test_replacing_keeps_others() {
+        let mut memory = Memory::default();
+        memory.put("a".to_owned(), value("1"), 2);
+        memory.put("b".to_owned(), value("2"), 2);
+        memory.put("a".to_owned(), value("3"), 2);
+        assert_eq!(memory.entries.len(), 2);
+        assert_eq!(memory.get("a", TTL).as_deref(), Some(&b"3"[..]));
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut memory = Memory::default();
+        memory.put("a".to_owned(), value("1"), 2);
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(memory.get("a", Duration::from_millis(1)).is_none());
+        assert!(memory.entries.is_empty());
+    }
+}
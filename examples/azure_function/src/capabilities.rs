@@ -0,0 +1,79 @@
+//! `GET /api/capabilities`: the media types the service signs, the algorithm and certificate
+//! it signs with and the versions it writes, so clients can feature-detect before uploading.
+use azure_core::time::{OffsetDateTime, to_rfc3339};
+use c2pa::Context;
+use c2pa_azure::{CertificateChain, SIGNABLE_MEDIA_TYPES};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use warp::{Filter, Rejection, Reply, reply::json};
+
+use crate::{
+    ApiError,
+    auth::{Authorizer, authorize},
+    tenants::{Tenants, configuration},
+};
+
+// The claim version c2pa writes when the manifest definition doesn't set one.
+const CLAIM_VERSION: u64 = 2;
+
+#[derive(Serialize)]
+struct Certificate {
+    subject: String,
+    issuer: String,
+    /// The earliest expiry in the chain, after which signing fails.
+    not_after: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Capabilities {
+    media_types: &'static [&'static str],
+    algorithm: String,
+    certificate: Option<Certificate>,
+    claim_version: u64,
+    version: &'static str,
+}
+
+fn capabilities(context: &Context, manifest_definition: &str) -> Result<Capabilities, ApiError> {
+    let signer = context.async_signer().map_err(ApiError::C2pa)?;
+    let certs = signer.certs().map_err(ApiError::C2pa)?;
+    let chain = CertificateChain::from_der(certs)
+        .and_then(|x| x.info())
+        .map_err(ApiError::C2pa)?;
+    let certificate = chain.leaf().map(|leaf| Certificate {
+        subject: leaf.subject.clone(),
+        issuer: leaf.issuer.clone(),
+        not_after: chain
+            .not_after()
+            .map(|x| to_rfc3339(&OffsetDateTime::from(x))),
+    });
+    let claim_version = serde_json::from_str::<Value>(manifest_definition)
+        .ok()
+        .and_then(|x| x.get("claim_version")?.as_u64())
+        .unwrap_or(CLAIM_VERSION);
+    Ok(Capabilities {
+        media_types: &SIGNABLE_MEDIA_TYPES,
+        algorithm: signer.alg().to_string(),
+        certificate,
+        claim_version,
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// `GET /api/capabilities` of the tenant of the request, requiring a token when signing does.
+pub(crate) fn routes(
+    authorizer: Option<Arc<Authorizer>>,
+    tenants: Arc<Tenants>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("api" / "capabilities"))
+        .and(authorize(authorizer.clone()))
+        .and(configuration(authorizer, tenants))
+        .and_then(
+            |context: Arc<Context>, manifest_definition: Arc<String>| async move {
+                capabilities(&context, &manifest_definition)
+                    .map(|x| json(&x))
+                    .map_err(warp::reject::custom)
+            },
+        )
+}
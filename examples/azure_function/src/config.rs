@@ -0,0 +1,174 @@
+//! The c2pa settings and the default manifest definition, reloaded from their files while the
+//! function runs so policy updates don't need a redeployment.
+use async_trait::async_trait;
+use c2pa::{AsyncSigner, Context, SigningAlg};
+use c2pa_azure::TraceParent;
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+// The signer is kept across reloads, every new context signs with it, in the trace of the
+// request when it has one.
+struct SharedSigner(Arc<dyn AsyncSigner + Send + Sync>, Option<TraceParent>);
+
+#[async_trait]
+impl AsyncSigner for SharedSigner {
+    async fn sign(&self, data: Vec<u8>) -> c2pa::Result<Vec<u8>> {
+        match &self.1 {
+            Some(trace) => trace.clone().scope(self.0.sign(data)).await,
+            None => self.0.sign(data).await,
+        }
+    }
+
+    fn alg(&self) -> SigningAlg {
+        self.0.alg()
+    }
+
+    fn certs(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.0.certs()
+    }
+
+    fn reserve_size(&self) -> usize {
+        self.0.reserve_size()
+    }
+
+    fn time_authority_url(&self) -> Option<String> {
+        self.0.time_authority_url()
+    }
+}
+
+struct Current {
+    settings: Option<String>,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+}
+
+/// The context requests sign with and the manifest definition they default to, swapped as a
+/// whole on reload so a request never sees the settings of one version and the manifest of
+/// another.
+pub struct Config {
+    signer: Arc<dyn AsyncSigner + Send + Sync>,
+    current: RwLock<Current>,
+}
+
+fn context(
+    signer: &Arc<dyn AsyncSigner + Send + Sync>,
+    settings: Option<&str>,
+    trace: Option<TraceParent>,
+) -> c2pa::Result<Arc<Context>> {
+    let mut context = Context::new();
+    if let Some(settings) = settings {
+        context = context.with_settings(settings)?;
+    }
+    Ok(context
+        .with_async_signer(SharedSigner(signer.clone(), trace))
+        .into_shared())
+}
+
+fn modified(path: Option<&Path>) -> Option<SystemTime> {
+    fs::metadata(path?).and_then(|x| x.modified()).ok()
+}
+
+impl Config {
+    /// `settings` are c2pa TOML settings applied to every signing context.
+    pub fn new(
+        signer: Arc<dyn AsyncSigner + Send + Sync>,
+        settings: Option<&str>,
+        manifest_definition: String,
+    ) -> c2pa::Result<Self> {
+        let context = context(&signer, settings, None)?;
+        Ok(Self {
+            signer,
+            current: RwLock::new(Current {
+                settings: settings.map(str::to_owned),
+                context,
+                manifest_definition: Arc::new(manifest_definition),
+            }),
+        })
+    }
+
+    pub(crate) fn context(&self) -> Arc<Context> {
+        self.current.read().unwrap().context.clone()
+    }
+
+    /// A context like the current one whose requests to Trusted Signing carry the trace of the
+    /// caller. It is built for the request, so only traced requests pay for it.
+    pub(crate) fn traced_context(&self, trace: TraceParent) -> c2pa::Result<Arc<Context>> {
+        let settings = self.current.read().unwrap().settings.clone();
+        context(&self.signer, settings.as_deref(), Some(trace))
+    }
+
+    /// The current c2pa settings.
+    pub(crate) fn settings(&self) -> Option<String> {
+        self.current.read().unwrap().settings.clone()
+    }
+
+    pub(crate) fn manifest_definition(&self) -> Arc<String> {
+        self.current.read().unwrap().manifest_definition.clone()
+    }
+
+    /// Replace the settings and the manifest definition, keeping the current ones when either
+    /// is invalid.
+    pub fn reload(&self, settings: Option<&str>, manifest_definition: String) -> c2pa::Result<()> {
+        serde_json::from_str::<Value>(&manifest_definition)?;
+        let context = context(&self.signer, settings, None)?;
+        *self.current.write().unwrap() = Current {
+            settings: settings.map(str::to_owned),
+            context,
+            manifest_definition: Arc::new(manifest_definition),
+        };
+        Ok(())
+    }
+
+    fn load(
+        &self,
+        settings: Option<&Path>,
+        manifest_definition: Option<&Path>,
+    ) -> c2pa::Result<()> {
+        let settings = settings.map(fs::read_to_string).transpose()?;
+        let manifest_definition = match manifest_definition {
+            Some(path) => fs::read_to_string(path)?,
+            None => self.manifest_definition().to_string(),
+        };
+        self.reload(settings.as_deref(), manifest_definition)
+    }
+
+    /// Check the modification time of the files every `interval` and reload both when either
+    /// changed. A file that is missing or invalid is logged and the current configuration kept.
+    pub fn watch(
+        self: &Arc<Self>,
+        settings: Option<PathBuf>,
+        manifest_definition: Option<PathBuf>,
+        interval: Duration,
+    ) {
+        let config = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let stamp = || {
+                (
+                    modified(settings.as_deref()),
+                    modified(manifest_definition.as_deref()),
+                )
+            };
+            let mut seen = stamp();
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(config) = config.upgrade() else {
+                    return;
+                };
+                let now = stamp();
+                if now == seen {
+                    continue;
+                }
+                seen = now;
+                match config.load(settings.as_deref(), manifest_definition.as_deref()) {
+                    Ok(()) => log::info!("Reloaded the settings and manifest definition"),
+                    Err(err) => log::error!("Keeping the current configuration: {err}"),
+                }
+            }
+        });
+    }
+}
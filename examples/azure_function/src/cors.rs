@@ -0,0 +1,99 @@
+//! CORS for browser front ends that upload to the sign and verify endpoints directly.
+use azure_core::http::Url;
+use std::io::{Error, ErrorKind, Result};
+use warp::{cors::Builder, http::HeaderName};
+
+use crate::{Options, auth::list};
+
+// Request headers the API reads, allowed in addition to CORS_ALLOWED_HEADERS.
+const HEADERS: [&str; 9] = [
+    "accept",
+    "authorization",
+    "content-range",
+    "content-type",
+    "traceparent",
+    "x-c2pa-tenant",
+    "x-callback-url",
+    "x-functions-key",
+    "x-manifest-definition",
+];
+
+// Response headers scripts need, the job location and when to retry.
+const EXPOSED: [&str; 2] = ["location", "retry-after"];
+
+// warp panics on invalid origins and headers, so they are checked when the service starts.
+fn header(value: &str) -> Result<&str> {
+    HeaderName::from_bytes(value.as_bytes())
+        .map(|_| value)
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{value} is not a header name"),
+            )
+        })
+}
+
+fn origin(value: &str) -> Result<&str> {
+    let origin = value.trim_end_matches('/');
+    match Url::parse(origin) {
+        Ok(url)
+            if matches!(url.scheme(), "http" | "https")
+                && url.origin().ascii_serialization() == origin =>
+        {
+            Ok(origin)
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{value} is not an origin like https://app.contoso.com"),
+        )),
+    }
+}
+
+/// The CORS policy of the options, `None` when no origin is allowed and browsers get no CORS
+/// headers.
+pub(crate) fn cors(options: &Options) -> Result<Option<Builder>> {
+    let origins = list(&options.cors_allowed_origins);
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let headers = list(&options.cors_allowed_headers);
+    let headers = headers
+        .iter()
+        .map(|x| header(x))
+        .collect::<Result<Vec<_>>>()?;
+    let mut cors = warp::cors()
+        .allow_methods(["GET", "POST", "PUT"])
+        .allow_headers(HEADERS)
+        .allow_headers(headers)
+        .expose_headers(EXPOSED)
+        .max_age(options.cors_max_age_secs);
+    if origins.iter().any(|x| x == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        for value in &origins {
+            cors = cors.allow_origin(origin(value)?);
+        }
+    }
+    Ok(Some(cors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(
+            origin("https://app.contoso.com").unwrap(),
+            "https://app.contoso.com"
+        );
+        assert_eq!(
+            origin("http://localhost:3000/").unwrap(),
+            "http://localhost:3000"
+        );
+        assert!(origin("https://app.contoso.com/path").is_err());
+        assert!(origin("https://APP.contoso.com").is_err());
+        assert!(origin("ftp://contoso.com").is_err());
+        assert!(origin("app.contoso.com").is_err());
+    }
+}
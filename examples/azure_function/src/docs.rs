@@ -0,0 +1,128 @@
+//! `GET /api/docs`: the OpenAPI document of the API and a Swagger UI page to try it, so client
+//! teams can generate SDKs from `openapi.json`.
+use warp::{Filter, Rejection, Reply, reply::with_header};
+
+use crate::Options;
+
+// Kept next to the routes, the tests check it lists every one of them.
+const OPENAPI: &str = include_str!("../openapi.json");
+
+// Swagger UI is loaded from this exact version, and only with the subresource integrity of
+// its files, so a change on the CDN can't run scripts on the page.
+const SWAGGER_UI: &str = "https://unpkg.com/swagger-ui-dist@5.17.14";
+
+fn swagger_ui(css_integrity: &str, js_integrity: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>C2PA signing API</title>
+  <link rel="stylesheet" href="{SWAGGER_UI}/swagger-ui.css" integrity="{css_integrity}" crossorigin="anonymous" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="{SWAGGER_UI}/swagger-ui-bundle.js" integrity="{js_integrity}" crossorigin="anonymous"></script>
+  <script>
+    window.onload = () => {{
+      window.ui = SwaggerUIBundle({{ url: "/api/docs/openapi.json", dom_id: "#swagger-ui" }});
+    }};
+  </script>
+</body>
+</html>
+"##
+    )
+}
+
+const NO_UI: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>C2PA signing API</title>
+</head>
+<body>
+  <p>The API is described by <a href="/api/docs/openapi.json">openapi.json</a>.</p>
+</body>
+</html>
+"#;
+
+/// `GET /api/docs` and `GET /api/docs/openapi.json`, served without authorization. The page
+/// only loads Swagger UI when `SWAGGER_UI_INTEGRITY` has the hashes of its files.
+pub(crate) fn routes(
+    options: &Options,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let page = match options
+        .swagger_ui_integrity
+        .as_deref()
+        .and_then(|x| x.split_once(','))
+    {
+        Some((css, js)) => swagger_ui(css.trim(), js.trim()),
+        None => NO_UI.to_owned(),
+    };
+    let ui = warp::path!("api" / "docs").map(move || warp::reply::html(page.clone()));
+    let document = warp::path!("api" / "docs" / "openapi.json")
+        .map(|| with_header(OPENAPI, "content-type", "application/json"));
+    warp::get().and(ui.or(document))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ROUTES;
+    use serde_json::Value;
+
+    fn paths() -> Vec<String> {
+        let document: Value = serde_json::from_str(OPENAPI).unwrap();
+        document["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    // The routes of the metrics are those `routes()` serves, so a route added without being
+    // documented, or documented without being served, fails here.
+    #[test]
+    fn test_documents_every_route() {
+        let paths = paths();
+        for route in ROUTES.into_iter().filter(|x| !x.starts_with("/api/docs")) {
+            assert!(
+                paths.iter().any(|x| x.starts_with(route)),
+                "{route} is not in openapi.json"
+            );
+        }
+        for path in &paths {
+            assert!(
+                ROUTES.iter().any(|x| path.starts_with(x)),
+                "{path} is not a route"
+            );
+        }
+    }
+
+    #[test]
+    fn test_operations_have_responses() {
+        let document: Value = serde_json::from_str(OPENAPI).unwrap();
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                assert!(
+                    ["get", "put", "post", "delete"].contains(&method.as_str()),
+                    "{method} {path}"
+                );
+                assert!(
+                    operation["responses"].is_object(),
+                    "{method} {path} has no responses"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_swagger_ui_needs_integrity() {
+        let page = swagger_ui("sha384-css", "sha384-js");
+        assert!(page.contains(r#"integrity="sha384-css""#));
+        assert!(page.contains(r#"integrity="sha384-js""#));
+        assert!(!page.contains("@5/"));
+        assert!(!NO_UI.contains("<script"));
+    }
+}
@@ -0,0 +1,257 @@
+//! The `Signing` gRPC service of `proto/signing.proto`, for internal callers that stream files
+//! over HTTP/2 instead of uploading them to the HTTP API. Calls are authorized and sign for a
+//! tenant like the HTTP API, with `authorization`, `x-functions-key` and `x-c2pa-tenant`
+//! metadata. The service is plaintext only: serve it on an internal network, or behind a
+//! proxy or service mesh that terminates TLS.
+use c2pa_azure::TraceParent;
+use futures::{Stream, stream};
+use std::{
+    io::{Read, Seek, Write},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+use tokio::sync::Semaphore;
+use tonic::{Request, Response, Status, Streaming, metadata::MetadataMap, transport::Server};
+
+use crate::{
+    ApiError, Config, Options, Tenants, auth::Authorizer, manifest_override, media_type,
+    ratelimit::RateLimiter, shutdown, sign_to, tenants::TENANT_HEADER, verification,
+};
+
+mod proto {
+    tonic::include_proto!("c2pa.signing.v1");
+}
+
+use proto::{
+    Certificate, Chunk, GetCertificatesRequest, SignRequest, VerifyRequest, VerifyResponse,
+    signing_server::{Signing, SigningServer},
+};
+
+// Size of the chunks the signed file is streamed back in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        match error {
+            ApiError::BadRequest(message) => Status::invalid_argument(message),
+            ApiError::TooLarge(limit) => {
+                Status::resource_exhausted(format!("The file is larger than {limit} bytes"))
+            }
+            ApiError::Busy => Status::resource_exhausted("Signing is at capacity, retry later"),
+            ApiError::RateLimited(wait) => Status::resource_exhausted(format!(
+                "Too many sign requests from this caller, retry in {}s",
+                wait.as_secs_f64().ceil()
+            )),
+            ApiError::Unauthorized(message) => Status::unauthenticated(message),
+            ApiError::Forbidden(message) => Status::permission_denied(message),
+            ApiError::Upstream(message) => Status::unavailable(message),
+            ApiError::Unsupported(message) => Status::invalid_argument(message),
+            error => {
+                log::error!("{error:?}");
+                Status::internal(error.message())
+            }
+        }
+    }
+}
+
+fn value<'a>(metadata: &'a MetadataMap, name: &str) -> Option<&'a str> {
+    metadata.get(name).and_then(|x| x.to_str().ok())
+}
+
+struct Service {
+    tenants: Arc<Tenants>,
+    max_upload_size: u64,
+    authorizer: Option<Arc<Authorizer>>,
+    limiter: Option<RateLimiter>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Service {
+    async fn authorize(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(());
+        };
+        let value = |name| value(metadata, name).map(str::to_owned);
+        Ok(authorizer
+            .authorize(value("x-functions-key"), value("authorization"))
+            .await?)
+    }
+
+    // The configuration of the tenant of the caller, like the HTTP API. Runs after `authorize`.
+    fn config(&self, metadata: &MetadataMap) -> Result<Arc<Config>, Status> {
+        Ok(self.tenants.select(
+            self.authorizer.as_deref(),
+            value(metadata, "x-functions-key"),
+            value(metadata, "authorization"),
+            value(metadata, TENANT_HEADER),
+        )?)
+    }
+
+    // Callers are told apart by function key, token or peer address, like on the HTTP API.
+    fn rate_limit<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
+        };
+        let client = request.remote_addr().map(|x| x.ip().to_string());
+        limiter
+            .acquire(&limiter.caller(
+                value(request.metadata(), "x-functions-key"),
+                value(request.metadata(), "authorization"),
+                client.as_deref(),
+            ))
+            .map_err(|x| ApiError::RateLimited(x).into())
+    }
+}
+
+// Write the data of the messages to a temporary file, returning the first message for its
+// other fields.
+async fn receive<T>(
+    mut messages: Streaming<T>,
+    limit: u64,
+    data: impl Fn(&mut T) -> Vec<u8>,
+) -> Result<(T, NamedTempFile), Status> {
+    let mut file = NamedTempFile::new().map_err(ApiError::Io)?;
+    let mut first = None;
+    let mut size = 0;
+    while let Some(mut message) = messages.message().await? {
+        let chunk = data(&mut message);
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(ApiError::TooLarge(limit).into());
+        }
+        file.write_all(&chunk).map_err(ApiError::Io)?;
+        first.get_or_insert(message);
+    }
+    let first = first.ok_or_else(|| Status::invalid_argument("No file was sent"))?;
+    file.rewind().map_err(ApiError::Io)?;
+    Ok((first, file))
+}
+
+#[tonic::async_trait]
+impl Signing for Service {
+    type SignStream = ResponseStream<Chunk>;
+    type GetCertificatesStream = ResponseStream<Certificate>;
+
+    async fn sign(
+        &self,
+        request: Request<Streaming<SignRequest>>,
+    ) -> Result<Response<Self::SignStream>, Status> {
+        self.authorize(request.metadata()).await?;
+        self.rate_limit(&request)?;
+        let config = self.config(request.metadata())?;
+        let trace = request
+            .metadata()
+            .get("traceparent")
+            .and_then(|x| x.to_str().ok())
+            .and_then(TraceParent::parse);
+        let _permit = self
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ApiError::Busy)?;
+        let (first, mut input) = receive(
+            request.into_inner(),
+            self.max_upload_size,
+            |x: &mut SignRequest| std::mem::take(&mut x.data),
+        )
+        .await?;
+        let manifest_definition = if first.manifest_definition.is_empty() {
+            config.manifest_definition()
+        } else {
+            Arc::new(manifest_override(first.manifest_definition)?)
+        };
+
+        let content_type = media_type(Some(&first.content_type), input.as_file_mut())?;
+        let context = match trace {
+            Some(trace) => config.traced_context(trace).map_err(ApiError::C2pa)?,
+            None => config.context(),
+        };
+        let mut output = NamedTempFile::new().map_err(ApiError::Io)?;
+        sign_to(
+            &context,
+            &manifest_definition,
+            &content_type,
+            input.as_file_mut(),
+            output.as_file_mut(),
+        )
+        .await?;
+        output.rewind().map_err(ApiError::Io)?;
+
+        let chunks = stream::unfold(Some(output), |file| async move {
+            let mut file = file?;
+            let mut data = vec![0; CHUNK_SIZE];
+            match file.read(&mut data) {
+                Ok(0) => None,
+                Ok(size) => {
+                    data.truncate(size);
+                    Some((Ok(Chunk { data }), Some(file)))
+                }
+                Err(err) => Some((Err(Status::internal(err.to_string())), None)),
+            }
+        });
+        Ok(Response::new(Box::pin(chunks)))
+    }
+
+    async fn verify(
+        &self,
+        request: Request<Streaming<VerifyRequest>>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        let (first, mut input) = receive(
+            request.into_inner(),
+            self.max_upload_size,
+            |x: &mut VerifyRequest| std::mem::take(&mut x.data),
+        )
+        .await?;
+        let content_type = media_type(Some(&first.content_type), input.as_file_mut())?;
+        let result = verification(&content_type, input.as_file_mut(), first.raw).await?;
+        let result = String::from_utf8(result).map_err(|x| Status::internal(x.to_string()))?;
+        Ok(Response::new(VerifyResponse { result }))
+    }
+
+    async fn get_certificates(
+        &self,
+        request: Request<GetCertificatesRequest>,
+    ) -> Result<Response<Self::GetCertificatesStream>, Status> {
+        let certs = self
+            .config(request.metadata())?
+            .context()
+            .async_signer()
+            .and_then(|x| x.certs())
+            .map_err(ApiError::C2pa)?;
+        let certificates = certs.into_iter().map(|der| Ok(Certificate { der }));
+        Ok(Response::new(Box::pin(stream::iter(certificates))))
+    }
+}
+
+/// Serve the `Signing` gRPC service in plaintext on `address` until SIGTERM or Ctrl+C, with the
+/// tenants, upload limit, authorization, rate limit and concurrency limit of the options. The
+/// limits are separate from those of the HTTP API, and `TLS_CERT_FILE` doesn't apply.
+pub async fn serve(
+    tenants: Arc<Tenants>,
+    options: Options,
+    address: impl Into<SocketAddr>,
+) -> anyhow::Result<()> {
+    if options.tls_cert_file.is_some() {
+        log::warn!("The gRPC service is served without TLS, only the HTTP API uses TLS_CERT_FILE");
+    }
+    let authorizer = Authorizer::new(&options, tenants.keys())?.map(Arc::new);
+    let service = Service {
+        tenants,
+        max_upload_size: options.max_upload_size,
+        limiter: RateLimiter::new(&options, authorizer.clone()),
+        authorizer,
+        semaphore: Arc::new(Semaphore::new(match options.max_concurrent_signs {
+            0 => Semaphore::MAX_PERMITS,
+            permits => permits,
+        })),
+    };
+    Server::builder()
+        .add_service(SigningServer::new(service))
+        .serve_with_shutdown(address.into(), shutdown::signal())
+        .await?;
+    Ok(())
+}
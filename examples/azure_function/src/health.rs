@@ -0,0 +1,190 @@
+//! `GET /healthz` and `GET /readyz` for the App Service health check and Kubernetes probes.
+use async_trait::async_trait;
+use azure_core::credentials::{AccessToken, TokenCredential, TokenRequestOptions};
+use c2pa::Context;
+use c2pa_azure::CertificateChain;
+use serde::Serialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use warp::{
+    Filter, Rejection, Reply,
+    http::StatusCode,
+    reply::{json, with_status},
+};
+
+use crate::Config;
+
+// `GET /readyz` gets a token itself when the signer hasn't got one for this long, and at most
+// this often.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The credential of the signer and the scope it gets tokens for.
+#[derive(Debug, Clone)]
+struct Probe {
+    credential: Arc<dyn TokenCredential>,
+    scope: String,
+}
+
+/// The outcome of the last token acquisition of the signer, reported by `GET /readyz`.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    token: Mutex<Option<(SystemTime, Result<(), String>)>>,
+    probe: Mutex<Option<Probe>>,
+}
+
+impl Readiness {
+    /// Wrap the credential of the signer to record whether getting tokens for `scope`
+    /// succeeds. Only the signer should use the returned credential, so failures of other
+    /// services don't make the instance unready.
+    pub fn track(
+        self: &Arc<Self>,
+        credential: Arc<dyn TokenCredential>,
+        scope: &str,
+    ) -> Arc<dyn TokenCredential> {
+        *self.probe.lock().unwrap() = Some(Probe {
+            credential: credential.clone(),
+            scope: scope.to_owned(),
+        });
+        Arc::new(TrackedCredential {
+            credential,
+            readiness: self.clone(),
+        })
+    }
+
+    fn record<T>(&self, result: &azure_core::Result<T>) {
+        let outcome = result.as_ref().map(|_| ()).map_err(|x| x.to_string());
+        *self.token.lock().unwrap() = Some((SystemTime::now(), outcome));
+    }
+
+    // Gets a token when the last outcome is older than PROBE_INTERVAL, so an idle instance or
+    // one whose last attempt failed finds out whether it can sign now. Credentials cache
+    // their tokens, so this rarely goes to Entra ID.
+    async fn probe(&self) {
+        let stale = self
+            .token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|(time, _)| time.elapsed().unwrap_or_default() >= PROBE_INTERVAL);
+        let probe = self.probe.lock().unwrap().clone();
+        if let Some(probe) = probe.filter(|_| stale) {
+            let result = probe.credential.get_token(&[&probe.scope], None).await;
+            self.record(&result);
+        }
+    }
+
+    fn token(&self) -> Result<String, String> {
+        match &*self.token.lock().unwrap() {
+            None => Ok("no token acquired yet".to_owned()),
+            Some((time, result)) => {
+                let age = time.elapsed().unwrap_or_default().as_secs();
+                match result {
+                    Ok(()) => Ok(format!("acquired {age}s ago")),
+                    Err(err) => Err(format!("failed {age}s ago: {err}")),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TrackedCredential {
+    credential: Arc<dyn TokenCredential>,
+    readiness: Arc<Readiness>,
+}
+
+#[async_trait]
+impl TokenCredential for TrackedCredential {
+    async fn get_token(
+        &self,
+        scopes: &[&str],
+        options: Option<TokenRequestOptions<'_>>,
+    ) -> azure_core::Result<AccessToken> {
+        let result = self.credential.get_token(scopes, options).await;
+        self.readiness.record(&result);
+        result
+    }
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    detail: String,
+}
+
+impl From<Result<String, String>> for Check {
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self { ok: true, detail },
+            Err(detail) => Self { ok: false, detail },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    ready: bool,
+    signer: Check,
+    certificate: Check,
+    token: Check,
+}
+
+// The leaf or an intermediate expiring within `window` fails the check, so the instance is
+// taken out of rotation before signing starts to fail.
+fn certificate(context: &Context, window: Duration) -> Result<String, String> {
+    let signer = context.async_signer().map_err(|x| x.to_string())?;
+    let certs = signer.certs().map_err(|x| x.to_string())?;
+    let chain = CertificateChain::from_der(certs).map_err(|x| x.to_string())?;
+    let not_after = chain
+        .earliest_not_after()
+        .map_err(|x| x.to_string())?
+        .ok_or("the certificate chain is empty")?;
+    match not_after.duration_since(SystemTime::now()) {
+        Ok(left) if left > window => Ok(format!("expires in {}s", left.as_secs())),
+        Ok(left) => Err(format!("expires in {}s", left.as_secs())),
+        Err(_) => Err("expired".to_owned()),
+    }
+}
+
+fn report(context: &Context, readiness: &Readiness, window: Duration) -> Report {
+    let signer = context
+        .async_signer()
+        .map(|x| format!("{} signer", x.alg()))
+        .map_err(|x| x.to_string());
+    let report = Report {
+        ready: false,
+        signer: signer.into(),
+        certificate: certificate(context, window).into(),
+        token: readiness.token().into(),
+    };
+    Report {
+        ready: report.signer.ok && report.certificate.ok && report.token.ok,
+        ..report
+    }
+}
+
+/// `GET /healthz` answers while the process runs, `GET /readyz` returns 503 with the failed
+/// checks while the instance can't sign.
+pub(crate) fn routes(
+    config: Arc<Config>,
+    readiness: Arc<Readiness>,
+    window: Duration,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::path!("healthz").map(|| "ok");
+    let readyz = warp::path!("readyz").then(move || {
+        let (config, readiness) = (config.clone(), readiness.clone());
+        async move {
+            readiness.probe().await;
+            let report = report(&config.context(), &readiness, window);
+            let status = if report.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            with_status(json(&report), status)
+        }
+    });
+    warp::get().and(healthz.or(readyz))
+}
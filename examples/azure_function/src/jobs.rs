@@ -0,0 +1,336 @@
+//! Asynchronous signing for large files: `POST /api/jobs` uploads the file and returns at
+//! once, `GET /api/jobs/{id}` reports the status and `GET /api/results/{id}` downloads the
+//! signed file. Jobs created with an `x-callback-url` header also report their status to it.
+use azure_core::http::Url;
+use c2pa::Context;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tempfile::{NamedTempFile, TempDir};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+use warp::{
+    Buf, Filter, Rejection, Reply, Stream,
+    http::StatusCode,
+    reply::{json, with_header, with_status},
+};
+
+use crate::{
+    ApiError,
+    auth::{Authorizer, authorize},
+    copy_to_file, limit, media_type, permit,
+    ratelimit::{RateLimiter, rate_limit},
+    sign_to,
+    tenants::{Tenants, configuration},
+    webhooks::Webhooks,
+};
+
+// Finished jobs and their results are removed after this long.
+const RETENTION: Duration = Duration::from_secs(60 * 60);
+// How often expired jobs are looked for.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum State {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct Job {
+    state: State,
+    error: Option<String>,
+    content_type: String,
+    finished: Option<Instant>,
+}
+
+/// The status returned by `POST /api/jobs` and `GET /api/jobs/{id}`.
+#[derive(Serialize)]
+struct Status<'a> {
+    id: &'a str,
+    status: State,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+}
+
+/// What `POST /api/jobs` takes besides the file.
+struct Upload {
+    content_type: Option<String>,
+    callback: Option<Url>,
+}
+
+/// Jobs of this instance. Uploads and results are kept in a temporary directory, the
+/// results in `results` so they can be served as files.
+pub(crate) struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    dir: TempDir,
+    webhooks: Option<Webhooks>,
+}
+
+impl Jobs {
+    pub(crate) fn new(webhooks: Option<Webhooks>) -> io::Result<Self> {
+        let dir = TempDir::new()?;
+        std::fs::create_dir(dir.path().join("results"))?;
+        Ok(Self {
+            jobs: Mutex::default(),
+            dir,
+            webhooks,
+        })
+    }
+
+    fn results(&self) -> PathBuf {
+        self.dir.path().join("results")
+    }
+
+    fn status<'a>(id: &'a str, job: &'a Job) -> Status<'a> {
+        Status {
+            id,
+            status: job.state,
+            error: job.error.as_deref(),
+            result: (job.state == State::Succeeded).then(|| format!("/api/results/{id}")),
+        }
+    }
+
+    fn finish(&self, id: &str, result: Result<(), ApiError>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.finished = Some(Instant::now());
+        match result {
+            Ok(()) => job.state = State::Succeeded,
+            Err(err) => {
+                log::error!("Job {id} failed: {err:?}");
+                job.state = State::Failed;
+                job.error = Some(err.message());
+            }
+        }
+    }
+
+    // Forget jobs that finished more than RETENTION ago and delete their results.
+    fn expire(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|id, job| {
+            let expired = job.finished.is_some_and(|x| x.elapsed() > RETENTION);
+            if expired {
+                _ = std::fs::remove_file(self.results().join(id));
+            }
+            !expired
+        });
+    }
+
+    /// Expire finished jobs every `EXPIRY_INTERVAL` until the jobs are dropped, so results are
+    /// deleted even when no new jobs are started.
+    pub(crate) fn expire_periodically(self: &Arc<Self>) {
+        let jobs = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(EXPIRY_INTERVAL).await;
+                let Some(jobs) = jobs.upgrade() else {
+                    return;
+                };
+                jobs.expire();
+            }
+        });
+    }
+
+    // POST the status of the finished job to the callback URL of the upload.
+    async fn report(&self, id: &str, callback: &Url) {
+        let Some(webhooks) = &self.webhooks else {
+            return;
+        };
+        let body = {
+            let jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.get(id) else {
+                return;
+            };
+            serde_json::to_vec(&Self::status(id, job)).unwrap_or_default()
+        };
+        webhooks.notify(callback, body).await;
+    }
+
+    /// Sign the input in the background and reply `202 Accepted` with the status of the new
+    /// job, holding the permit until it is signed.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        permit: OwnedSemaphorePermit,
+        context: Arc<Context>,
+        manifest_definition: Arc<String>,
+        mut input: NamedTempFile,
+        content_type: String,
+        callback: Option<Url>,
+    ) -> impl Reply + use<> {
+        self.expire();
+        let id = Uuid::new_v4().to_string();
+        let job = Job {
+            state: State::Running,
+            error: None,
+            content_type: content_type.clone(),
+            finished: None,
+        };
+        let reply = json(&Self::status(&id, &job));
+        self.jobs.lock().unwrap().insert(id.clone(), job);
+
+        let location = format!("/api/jobs/{id}");
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let mut output = NamedTempFile::new_in(jobs.dir.path()).map_err(ApiError::Io)?;
+                sign_to(
+                    &context,
+                    &manifest_definition,
+                    &content_type,
+                    input.as_file_mut(),
+                    output.as_file_mut(),
+                )
+                .await?;
+                output
+                    .persist(jobs.results().join(&id))
+                    .map_err(|x| ApiError::Io(x.error))?;
+                Ok::<_, ApiError>(())
+            }
+            .await;
+            jobs.finish(&id, result);
+            drop(permit);
+            if let Some(callback) = callback {
+                jobs.report(&id, &callback).await;
+            }
+        });
+        with_status(
+            with_header(reply, "location", location),
+            StatusCode::ACCEPTED,
+        )
+    }
+
+    fn content_type(&self, id: &str) -> Option<String> {
+        let jobs = self.jobs.lock().unwrap();
+        Some(jobs.get(id)?.content_type.clone())
+    }
+}
+
+async fn create(
+    permit: OwnedSemaphorePermit,
+    jobs: Arc<Jobs>,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    limit: u64,
+    upload: Upload,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+) -> Result<impl Reply, Rejection> {
+    let mut input = NamedTempFile::new_in(jobs.dir.path())
+        .map_err(|x| warp::reject::custom(ApiError::Io(x)))?;
+    copy_to_file(input.as_file_mut(), stream, limit)
+        .await
+        .map_err(warp::reject::custom)?;
+    let content_type = media_type(upload.content_type.as_deref(), input.as_file_mut())
+        .map_err(warp::reject::custom)?;
+    Ok(jobs.start(
+        permit,
+        context,
+        manifest_definition,
+        input,
+        content_type,
+        upload.callback,
+    ))
+}
+
+async fn status(jobs: Arc<Jobs>, id: String) -> Result<impl Reply, Rejection> {
+    let all = jobs.jobs.lock().unwrap();
+    let Some(job) = all.get(&id) else {
+        return Err(warp::reject::not_found());
+    };
+    Ok(json(&Jobs::status(&id, job)))
+}
+
+/// The checked `x-callback-url` of the request that starts a job.
+pub(crate) fn callback(
+    jobs: Arc<Jobs>,
+) -> impl Filter<Extract = (Option<Url>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-callback-url").and_then(move |callback: Option<String>| {
+        let jobs = jobs.clone();
+        async move {
+            match (callback, &jobs.webhooks) {
+                (None, _) => Ok(None),
+                (Some(url), Some(webhooks)) => {
+                    Ok(Some(webhooks.check(&url).map_err(warp::reject::custom)?))
+                }
+                (Some(_), None) => Err(warp::reject::custom(ApiError::BadRequest(
+                    "Callbacks are not enabled, WEBHOOK_SECRET is not set".to_owned(),
+                ))),
+            }
+        }
+    })
+}
+
+// The content type and the checked `x-callback-url` of the upload.
+fn upload(jobs: Arc<Jobs>) -> impl Filter<Extract = (Upload,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(callback(jobs))
+        .map(|content_type, callback| Upload {
+            content_type,
+            callback,
+        })
+}
+
+/// `POST /api/jobs`, `GET /api/jobs/{id}` and `GET /api/results/{id}`, all requiring a token
+/// when signing does.
+pub(crate) fn routes(
+    jobs: Arc<Jobs>,
+    tenants: Arc<Tenants>,
+    max_upload_size: u64,
+    authorizer: Option<Arc<Authorizer>>,
+    limiter: Option<Arc<RateLimiter>>,
+    semaphore: Arc<Semaphore>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let results = jobs.results();
+    let with_jobs = {
+        let jobs = jobs.clone();
+        warp::any().map(move || jobs.clone())
+    };
+
+    let create = warp::post()
+        .and(warp::path!("api" / "jobs"))
+        .and(authorize(authorizer.clone()))
+        .and(rate_limit(limiter))
+        .and(permit(semaphore))
+        .and(with_jobs.clone())
+        .and(configuration(authorizer.clone(), tenants))
+        .and(limit(max_upload_size))
+        .and(upload(jobs.clone()))
+        .and(warp::filters::body::stream())
+        .and_then(create);
+
+    let status = warp::get()
+        .and(with_jobs)
+        .and(warp::path!("api" / "jobs" / String))
+        .and(authorize(authorizer.clone()))
+        .and_then(status);
+
+    // Results are named by job id, the content type is the one of the upload.
+    let result = warp::get()
+        .and(warp::path("api"))
+        .and(warp::path("results"))
+        .and(authorize(authorizer))
+        .and(warp::fs::dir(results))
+        .map(move |file: warp::fs::File| {
+            let id = file
+                .path()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let content_type = jobs
+                .content_type(&id)
+                .unwrap_or_else(|| "application/octet-stream".to_owned());
+            with_header(file, "content-type", content_type)
+        });
+
+    create.or(status).or(result)
+}
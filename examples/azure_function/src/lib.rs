@@ -0,0 +1,1189 @@
+//! The sign and verify HTTP API of the Azure Function custom handler, also served by
+//! `c2pa-acs serve`.
+use azure_core::credentials::TokenCredential;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use c2pa::{Builder, Context};
+use c2pa_azure::{
+    MediaTypeError, SIGNABLE_MEDIA_TYPES, SNIFF_LENGTH, ValidationSummary, VerificationResult,
+    resolve_media_type, verify_file as verify, verify_file_detailed,
+};
+use envconfig::Envconfig;
+use futures::{StreamExt, stream};
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+use warp::{
+    Buf, Error, Filter, Rejection, Reply, Stream,
+    http::{
+        HeaderValue, StatusCode,
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    },
+    multipart::{FormData, Part},
+    reject::Reject,
+};
+
+mod auth;
+mod batch;
+mod blob;
+mod cache;
+mod capabilities;
+mod config;
+mod cors;
+mod docs;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod jobs;
+mod logging;
+mod metrics;
+mod ratelimit;
+mod shutdown;
+mod tenants;
+mod tls;
+mod uploads;
+mod webhooks;
+
+use auth::{Authorizer, authorize};
+use blob::{Blobs, Spill};
+use cache::VerifyCache;
+use jobs::Jobs;
+use ratelimit::{RateLimiter, rate_limit};
+use webhooks::Webhooks;
+
+pub use config::Config;
+#[cfg(feature = "grpc")]
+pub use grpc::serve as serve_grpc;
+pub use health::Readiness;
+pub use logging::init_logging;
+pub use metrics::telemetry;
+pub use shutdown::serve;
+pub use tenants::Tenants;
+pub use tls::Tls;
+
+// Seconds clients are asked to wait when signing is at capacity.
+const RETRY_AFTER_SECS: &str = "5";
+
+/// Options of the service, read from the environment by the function. With the `clap`
+/// feature they are also command line arguments, for `c2pa-acs serve`.
+#[derive(Clone, Debug, Envconfig)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct Options {
+    /// Largest upload in bytes, larger bodies are rejected with 413 Payload Too Large.
+    #[envconfig(from = "MAX_UPLOAD_BYTES", default = "4294967296")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "MAX_UPLOAD_BYTES", default_value_t = 4 * 1024 * 1024 * 1024)
+    )]
+    pub max_upload_size: u64,
+    /// `GET /readyz` fails once the certificate chain expires within this many seconds.
+    #[envconfig(from = "READY_CERTIFICATE_WINDOW_SECS", default = "3600")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "READY_CERTIFICATE_WINDOW_SECS", default_value_t = 3600)
+    )]
+    pub certificate_window_secs: u64,
+    /// Issuer of the accepted tokens, `https://login.microsoftonline.com/{tenant}/v2.0`.
+    #[envconfig(from = "AUTH_ISSUER")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "AUTH_ISSUER", requires = "auth_audience")
+    )]
+    pub auth_issuer: Option<String>,
+    /// Audience of the accepted tokens, signing is anonymous when unset.
+    #[envconfig(from = "AUTH_AUDIENCE")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "AUTH_AUDIENCE", requires = "auth_issuer")
+    )]
+    pub auth_audience: Option<String>,
+    /// App roles, separated by commas, of which tokens must have one.
+    #[envconfig(from = "AUTH_REQUIRED_ROLES")]
+    #[cfg_attr(feature = "clap", arg(long, env = "AUTH_REQUIRED_ROLES"))]
+    pub auth_roles: Option<String>,
+    /// Delegated scopes, separated by commas, of which tokens must have one.
+    #[envconfig(from = "AUTH_REQUIRED_SCOPES")]
+    #[cfg_attr(feature = "clap", arg(long, env = "AUTH_REQUIRED_SCOPES"))]
+    pub auth_scopes: Option<String>,
+    /// Function keys, separated by commas, accepted in `x-functions-key` or `code`.
+    #[envconfig(from = "FUNCTIONS_KEYS")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "FUNCTIONS_KEYS", hide_env_values = true)
+    )]
+    pub function_keys: Option<String>,
+    /// Host suffixes, separated by commas, that `POST /api/sign/blob` reads and writes.
+    #[envconfig(from = "BLOB_ALLOWED_HOSTS", default = ".blob.core.windows.net")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "BLOB_ALLOWED_HOSTS", default_value_t = ".blob.core.windows.net".to_owned())
+    )]
+    pub blob_allowed_hosts: String,
+    /// Container URLs, separated by commas, whose blobs `POST /api/sign/blob` reads and writes
+    /// with the function's identity. Other blobs need a SAS URL.
+    #[envconfig(from = "BLOB_IDENTITY_CONTAINERS")]
+    #[cfg_attr(feature = "clap", arg(long, env = "BLOB_IDENTITY_CONTAINERS"))]
+    pub blob_identity_containers: Option<String>,
+    /// Sign requests handled at the same time, more are rejected with 429 Too Many Requests.
+    /// 0 is unlimited.
+    #[envconfig(from = "MAX_CONCURRENT_SIGNS", default = "16")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "MAX_CONCURRENT_SIGNS", default_value_t = 16)
+    )]
+    pub max_concurrent_signs: usize,
+    /// Seconds in-flight signing gets to finish after SIGTERM.
+    #[envconfig(from = "SHUTDOWN_GRACE_SECS", default = "30")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "SHUTDOWN_GRACE_SECS", default_value_t = 30)
+    )]
+    pub shutdown_grace_secs: u64,
+    /// c2pa settings TOML file, reloaded when it changes.
+    #[envconfig(from = "C2PA_SETTINGS")]
+    #[cfg_attr(feature = "clap", arg(skip))]
+    pub settings_file: Option<String>,
+    /// Seconds between checks of the settings and manifest definition files, 0 disables reloading.
+    #[envconfig(from = "RELOAD_INTERVAL_SECS", default = "10")]
+    #[cfg_attr(feature = "clap", arg(skip))]
+    pub reload_interval_secs: u64,
+    /// Origins, separated by commas, browsers may call the API from, `*` for any. CORS is off
+    /// when unset.
+    #[envconfig(from = "CORS_ALLOWED_ORIGINS")]
+    #[cfg_attr(feature = "clap", arg(long, env = "CORS_ALLOWED_ORIGINS"))]
+    pub cors_allowed_origins: Option<String>,
+    /// Request headers, separated by commas, allowed besides those the API reads.
+    #[envconfig(from = "CORS_ALLOWED_HEADERS")]
+    #[cfg_attr(feature = "clap", arg(long, env = "CORS_ALLOWED_HEADERS"))]
+    pub cors_allowed_headers: Option<String>,
+    /// Seconds browsers may cache the answer to a preflight request.
+    #[envconfig(from = "CORS_MAX_AGE_SECS", default = "600")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "CORS_MAX_AGE_SECS", default_value_t = 600)
+    )]
+    pub cors_max_age_secs: u64,
+    /// Port of the gRPC service, which is only served when set and built with the `grpc`
+    /// feature.
+    #[envconfig(from = "GRPC_PORT")]
+    #[cfg_attr(feature = "clap", arg(skip))]
+    pub grpc_port: Option<u16>,
+    /// Address the HTTP and gRPC services listen on, all interfaces by default.
+    #[envconfig(from = "BIND_ADDRESS", default = "0.0.0.0")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long = "bind", env = "BIND_ADDRESS", default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    )]
+    pub bind_address: IpAddr,
+    /// PEM certificate chain the HTTP service terminates TLS with, set with `TLS_KEY_FILE`.
+    #[envconfig(from = "TLS_CERT_FILE")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long = "tls-cert", env = "TLS_CERT_FILE", requires = "tls_key_file")
+    )]
+    pub tls_cert_file: Option<String>,
+    /// PEM private key of `TLS_CERT_FILE`.
+    #[envconfig(from = "TLS_KEY_FILE")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long = "tls-key", env = "TLS_KEY_FILE", requires = "tls_cert_file")
+    )]
+    pub tls_key_file: Option<String>,
+    /// JSON file of the tenants signing with their own Trusted Signing account.
+    #[envconfig(from = "TENANTS_FILE")]
+    #[cfg_attr(feature = "clap", arg(skip))]
+    pub tenants_file: Option<String>,
+    /// Uploads to `POST /api/sign` of at most this many bytes are signed in memory instead of
+    /// from a temporary file. 0 always uses a temporary file.
+    #[envconfig(from = "MEMORY_SIGN_BYTES", default = "16777216")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "MEMORY_SIGN_BYTES", default_value_t = 16 * 1024 * 1024)
+    )]
+    pub memory_sign_limit: u64,
+    /// Sign requests a caller, told apart by function key, token or address, may make a minute,
+    /// more are rejected with 429 Too Many Requests. 0 is unlimited.
+    #[envconfig(from = "RATE_LIMIT_PER_MINUTE", default = "0")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "RATE_LIMIT_PER_MINUTE", default_value_t = 0)
+    )]
+    pub rate_limit_per_minute: u32,
+    /// Sign requests a caller may make at once before the per-minute rate applies.
+    #[envconfig(from = "RATE_LIMIT_BURST", default = "10")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "RATE_LIMIT_BURST", default_value_t = 10)
+    )]
+    pub rate_limit_burst: u32,
+    /// Proxies in front of the handler that append the client address to `x-forwarded-for`,
+    /// like the front end of the Functions host. 0 takes the client from the connection, the
+    /// default of `c2pa-acs serve`, which usually runs without a proxy.
+    #[envconfig(from = "TRUSTED_PROXY_HOPS", default = "1")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "TRUSTED_PROXY_HOPS", default_value_t = 0)
+    )]
+    pub trusted_proxy_hops: usize,
+    /// Key of the HMAC-SHA256 signature of job callbacks, which are only sent when it is set.
+    #[envconfig(from = "WEBHOOK_SECRET")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "WEBHOOK_SECRET", hide_env_values = true)
+    )]
+    pub webhook_secret: Option<String>,
+    /// Domains, separated by commas, job callbacks may go to, with their subdomains. Required
+    /// with `WEBHOOK_SECRET`.
+    #[envconfig(from = "WEBHOOK_ALLOWED_HOSTS")]
+    #[cfg_attr(feature = "clap", arg(long, env = "WEBHOOK_ALLOWED_HOSTS"))]
+    pub webhook_allowed_hosts: Option<String>,
+    /// The subresource integrity of `swagger-ui.css` and `swagger-ui-bundle.js` of the Swagger
+    /// UI version `GET /api/docs` loads, separated by a comma. The page doesn't load Swagger UI
+    /// without them.
+    #[envconfig(from = "SWAGGER_UI_INTEGRITY")]
+    #[cfg_attr(feature = "clap", arg(long, env = "SWAGGER_UI_INTEGRITY"))]
+    pub swagger_ui_integrity: Option<String>,
+    /// Verify results kept in memory, by the hash of the file. 0 keeps none.
+    #[envconfig(from = "VERIFY_CACHE_ENTRIES", default = "1000")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "VERIFY_CACHE_ENTRIES", default_value_t = 1000)
+    )]
+    pub verify_cache_entries: usize,
+    /// Seconds verify results are kept for. 0 disables the cache.
+    #[envconfig(from = "VERIFY_CACHE_TTL_SECS", default = "3600")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "VERIFY_CACHE_TTL_SECS", default_value_t = 3600)
+    )]
+    pub verify_cache_ttl_secs: u64,
+    /// Redis shared by the instances for verify results, with the `redis` feature.
+    #[envconfig(from = "VERIFY_CACHE_REDIS_URL")]
+    #[cfg_attr(feature = "clap", arg(long, env = "VERIFY_CACHE_REDIS_URL"))]
+    pub verify_cache_redis_url: Option<String>,
+    /// Blob container uploads to `POST /api/sign` larger than `SPILL_THRESHOLD_BYTES` are
+    /// written to instead of a temporary file, as a SAS URL or one the identity can write to.
+    #[envconfig(from = "SPILL_CONTAINER_URL")]
+    #[cfg_attr(feature = "clap", arg(long, env = "SPILL_CONTAINER_URL"))]
+    pub spill_container_url: Option<String>,
+    /// Uploads with a `content-length` above this many bytes are spilled to the container.
+    #[envconfig(from = "SPILL_THRESHOLD_BYTES", default = "268435456")]
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, env = "SPILL_THRESHOLD_BYTES", default_value_t = 256 * 1024 * 1024)
+    )]
+    pub spill_threshold: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_upload_size: 4 * 1024 * 1024 * 1024,
+            certificate_window_secs: 3600,
+            auth_issuer: None,
+            auth_audience: None,
+            auth_roles: None,
+            auth_scopes: None,
+            function_keys: None,
+            blob_allowed_hosts: ".blob.core.windows.net".to_owned(),
+            blob_identity_containers: None,
+            max_concurrent_signs: 16,
+            shutdown_grace_secs: 30,
+            settings_file: None,
+            reload_interval_secs: 10,
+            cors_allowed_origins: None,
+            cors_allowed_headers: None,
+            cors_max_age_secs: 600,
+            grpc_port: None,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tls_cert_file: None,
+            tls_key_file: None,
+            tenants_file: None,
+            memory_sign_limit: 16 * 1024 * 1024,
+            rate_limit_per_minute: 0,
+            rate_limit_burst: 10,
+            trusted_proxy_hops: 1,
+            webhook_secret: None,
+            webhook_allowed_hosts: None,
+            swagger_ui_integrity: None,
+            verify_cache_entries: 1000,
+            verify_cache_ttl_secs: 3600,
+            verify_cache_redis_url: None,
+            spill_container_url: None,
+            spill_threshold: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl Options {
+    /// The certificate and key of `TLS_CERT_FILE` and `TLS_KEY_FILE`, none when neither is set.
+    pub fn tls(&self) -> io::Result<Option<Tls>> {
+        match (&self.tls_cert_file, &self.tls_key_file) {
+            (Some(certificate), Some(key)) => {
+                Tls::load(Path::new(certificate), Path::new(key)).map(Some)
+            }
+            (None, None) => Ok(None),
+            _ => Err(io::Error::other(
+                "TLS_CERT_FILE and TLS_KEY_FILE must be set together",
+            )),
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    Azure(azure_core::Error),
+    Io(std::io::Error),
+    C2pa(c2pa_azure::Error),
+    Warp(Error),
+    BadRequest(String),
+    TooLarge(u64),
+    Unauthorized(String),
+    Forbidden(String),
+    Upstream(String),
+    Conflict(String),
+    Busy,
+    Unsupported(String),
+    /// The caller is over its rate limit until the duration passed.
+    RateLimited(Duration),
+}
+
+impl Reject for ApiError {}
+
+impl ApiError {
+    /// The message for the caller. Azure and I/O errors are summarized, their details
+    /// (URLs, paths, upstream responses) are for the logs only.
+    pub(crate) fn message(&self) -> String {
+        match self {
+            Self::TooLarge(limit) => format!("The upload is larger than {limit} bytes"),
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::Upstream(message)
+            | Self::Conflict(message) => message.clone(),
+            Self::Busy => "Too many files are being signed, retry later".to_owned(),
+            Self::RateLimited(_) => {
+                "Too many sign requests from this caller, retry later".to_owned()
+            }
+            Self::Unsupported(message) => format!(
+                "{message}, the supported types are {}",
+                SIGNABLE_MEDIA_TYPES.join(", ")
+            ),
+            Self::C2pa(err) => err.to_string(),
+            Self::Warp(err) => format!("Reading the request failed: {err}"),
+            Self::Azure(_) => "A request to Azure failed".to_owned(),
+            Self::Io(_) => "Reading or writing a temporary file failed".to_owned(),
+        }
+    }
+}
+
+// Stop copying as soon as the body is larger than `limit`, before it fills the disk.
+async fn copy(
+    mut writer: impl Write,
+    mut stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    limit: u64,
+) -> Result<(), ApiError> {
+    let mut size = 0;
+    while let Some(value) = stream.next().await {
+        match value {
+            Ok(mut buf) => {
+                size += buf.remaining() as u64;
+                if size > limit {
+                    return Err(ApiError::TooLarge(limit));
+                }
+                while buf.has_remaining() {
+                    let chunk = buf.chunk();
+                    writer.write_all(chunk).map_err(ApiError::Io)?;
+                    buf.advance(chunk.len());
+                }
+            }
+            Err(e) => {
+                log::error!("Error copying the body to file: {e:?}");
+                return Err(ApiError::Warp(e));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn copy_to_file(
+    mut file: &File,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    limit: u64,
+) -> Result<(), ApiError> {
+    copy(file, stream, limit).await?;
+    file.rewind().map_err(ApiError::Io)?;
+    Ok(())
+}
+
+/// The file, and optionally a manifest definition, of a `multipart/form-data` or JSON upload.
+struct Upload {
+    file: NamedTempFile,
+    content_type: String,
+    manifest_definition: Option<String>,
+}
+
+// Browsers send application/octet-stream for types they don't know, c2pa takes the extension.
+fn part_type(part: &Part) -> Option<String> {
+    let extension = || {
+        let name = part.filename()?;
+        Some(Path::new(name).extension()?.to_str()?.to_owned())
+    };
+    match part.content_type() {
+        Some(content_type) if content_type != "application/octet-stream" => {
+            Some(content_type.to_owned())
+        }
+        _ => extension(),
+    }
+}
+
+/// Whether `host` is one of the domains of `suffixes` or a subdomain of one. Suffixes match on
+/// a label boundary, so `contoso.com` doesn't allow `evilcontoso.com`.
+pub(crate) fn allowed_host(host: &str, suffixes: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    suffixes.iter().any(|x| {
+        let suffix = x.trim_start_matches('.').to_ascii_lowercase();
+        !suffix.is_empty()
+            && (host == suffix
+                || host
+                    .strip_suffix(suffix.as_str())
+                    .is_some_and(|x| x.ends_with('.')))
+    })
+}
+
+/// The format of the file: the declared content type or extension when it matches the
+/// content, or the format of the content when none or `application/octet-stream` was declared.
+pub(crate) fn media_type(
+    declared: Option<&str>,
+    file: &mut (impl Read + Seek),
+) -> Result<String, ApiError> {
+    let declared = declared
+        .and_then(|x| x.split(';').next())
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.eq_ignore_ascii_case("application/octet-stream"));
+    let mut header = Vec::with_capacity(SNIFF_LENGTH);
+    Read::by_ref(file)
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut header)
+        .map_err(ApiError::Io)?;
+    file.rewind().map_err(ApiError::Io)?;
+    match resolve_media_type(declared, &header) {
+        Ok(media_type) => Ok(media_type.to_owned()),
+        Err(err @ MediaTypeError::Mismatch { .. }) => Err(ApiError::BadRequest(err.to_string())),
+        Err(err) => Err(ApiError::Unsupported(err.to_string())),
+    }
+}
+
+async fn read_form(mut form: FormData, limit: u64) -> Result<Upload, ApiError> {
+    let mut file = None;
+    let mut manifest_definition = None;
+    while let Some(part) = form.next().await {
+        let part = part.map_err(ApiError::Warp)?;
+        let name = part.name().to_owned();
+        match name.as_str() {
+            "file" => {
+                let declared = part_type(&part);
+                let mut temp = NamedTempFile::new().map_err(ApiError::Io)?;
+                copy_to_file(temp.as_file(), Box::pin(part.stream()), limit).await?;
+                let content_type = media_type(declared.as_deref(), temp.as_file_mut())?;
+                file = Some((temp, content_type));
+            }
+            "manifest" => {
+                let mut data = Vec::new();
+                copy(&mut data, Box::pin(part.stream()), limit).await?;
+                let text = String::from_utf8(data).map_err(|_| {
+                    ApiError::BadRequest("The manifest part is not UTF-8".to_owned())
+                })?;
+                manifest_definition = Some(manifest_override(text)?);
+            }
+            name => log::debug!("Ignoring the form part {name}"),
+        }
+    }
+    let Some((file, content_type)) = file else {
+        return Err(ApiError::BadRequest("The form has no file part".to_owned()));
+    };
+    Ok(Upload {
+        file,
+        content_type,
+        manifest_definition,
+    })
+}
+
+/// A file and its manifest definition sent as `application/json`, for clients that can't
+/// send forms.
+#[derive(Deserialize)]
+struct Envelope {
+    /// The format of the file, taken from its content when unset.
+    content_type: Option<String>,
+    /// The file, base64 encoded.
+    data: String,
+    /// The manifest definition, as an object or as JSON text.
+    manifest_definition: Option<Value>,
+}
+
+async fn read_envelope(
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    limit: u64,
+) -> Result<Upload, ApiError> {
+    let mut body = Vec::new();
+    copy(&mut body, stream, limit).await?;
+    let envelope: Envelope = serde_json::from_slice(&body)
+        .map_err(|x| ApiError::BadRequest(format!("Invalid JSON envelope: {x}")))?;
+    let data = BASE64_STANDARD
+        .decode(envelope.data)
+        .map_err(|x| ApiError::BadRequest(format!("The data is not base64: {x}")))?;
+    let mut file = NamedTempFile::new().map_err(ApiError::Io)?;
+    file.write_all(&data).map_err(ApiError::Io)?;
+    file.rewind().map_err(ApiError::Io)?;
+    let content_type = media_type(envelope.content_type.as_deref(), file.as_file_mut())?;
+    let manifest_definition = match envelope.manifest_definition {
+        Some(Value::String(text)) => Some(manifest_override(text)?),
+        Some(value) => Some(value.to_string()),
+        None => None,
+    };
+    Ok(Upload {
+        file,
+        content_type,
+        manifest_definition,
+    })
+}
+
+// A manifest definition sent by the caller, checked to be JSON so mistakes are a 400.
+pub(crate) fn manifest_override(text: String) -> Result<String, ApiError> {
+    serde_json::from_str::<Value>(&text)
+        .map_err(|x| ApiError::BadRequest(format!("Invalid manifest definition: {x}")))?;
+    Ok(text)
+}
+
+pub(crate) async fn sign_to(
+    context: &Arc<Context>,
+    manifest_definition: &str,
+    content_type: &str,
+    file: &mut (impl Read + Seek + Send),
+    output: &mut (impl Read + Write + Seek + Send),
+) -> Result<(), ApiError> {
+    let mut builder = Builder::from_shared_context(context)
+        .with_definition(manifest_definition)
+        .map_err(ApiError::C2pa)?;
+    let signer = context.async_signer().map_err(ApiError::C2pa)?;
+    let _in_flight = metrics::start_signing();
+    let started = Instant::now();
+    let result = builder.sign_async(signer, content_type, file, output).await;
+    metrics::signed(started.elapsed(), result.is_ok());
+    result.map_err(ApiError::C2pa)?;
+    log::info!("Successfully signed the file.");
+    Ok(())
+}
+
+/// A signed file, in memory for small uploads and in a temporary file for the others.
+enum Signed {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+async fn sign(
+    context: &Arc<Context>,
+    manifest_definition: &str,
+    content_type: &str,
+    file: &mut (impl Read + Seek + Send),
+) -> Result<Signed, ApiError> {
+    let mut output = Cursor::new(Vec::new());
+    sign_to(
+        context,
+        manifest_definition,
+        content_type,
+        file,
+        &mut output,
+    )
+    .await?;
+    Ok(Signed::Memory(output.into_inner()))
+}
+
+// Signs to a temporary file, so outputs of large uploads aren't held in memory.
+async fn sign_to_file(
+    context: &Arc<Context>,
+    manifest_definition: &str,
+    content_type: &str,
+    file: &mut (impl Read + Seek + Send),
+) -> Result<Signed, ApiError> {
+    let mut output = tempfile::tempfile().map_err(ApiError::Io)?;
+    sign_to(
+        context,
+        manifest_definition,
+        content_type,
+        file,
+        &mut output,
+    )
+    .await?;
+    output.rewind().map_err(ApiError::Io)?;
+    Ok(Signed::File(output))
+}
+
+// Files are streamed in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The chunks of a file, read as the response is sent.
+pub(crate) fn chunks(file: File) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync {
+    stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut data = vec![0; CHUNK_SIZE];
+        match file.read(&mut data) {
+            Ok(0) => None,
+            Ok(size) => {
+                data.truncate(size);
+                Some((Ok(Bytes::from(data)), Some(file)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    })
+}
+
+/// A response with the streamed body and its content type.
+pub(crate) fn streamed(
+    body: impl Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    content_type: &str,
+) -> warp::reply::Response {
+    warp::reply::with_header(
+        warp::reply::Response::new(warp::Body::wrap_stream(body)),
+        "content-type",
+        content_type,
+    )
+    .into_response()
+}
+
+// The signed file, or with `include_manifest` a multipart/mixed response with the manifest
+// store JSON first and the signed file second. Files are streamed rather than read.
+async fn signed(
+    output: Signed,
+    content_type: String,
+    include_manifest: bool,
+) -> Result<warp::reply::Response, ApiError> {
+    if !include_manifest {
+        return Ok(match output {
+            Signed::Memory(data) => {
+                warp::reply::with_header(data, "content-type", content_type).into_response()
+            }
+            Signed::File(file) => streamed(chunks(file), &content_type),
+        });
+    }
+    let manifest = match &output {
+        Signed::Memory(data) => verify(&content_type, Cursor::new(data)).await,
+        Signed::File(file) => {
+            let mut file = file.try_clone().map_err(ApiError::Io)?;
+            let manifest = verify(&content_type, &mut file).await;
+            file.rewind().map_err(ApiError::Io)?;
+            manifest
+        }
+    }
+    .map_err(ApiError::C2pa)?;
+    let boundary = Uuid::new_v4().simple().to_string();
+    let mut head = Vec::with_capacity(manifest.len() + 256);
+    write!(
+        head,
+        "--{boundary}\r\ncontent-type: application/json\r\n\
+         content-disposition: inline; name=\"manifest\"\r\n\r\n{manifest}\r\n\
+         --{boundary}\r\ncontent-type: {content_type}\r\n\
+         content-disposition: attachment; name=\"file\"\r\n\r\n"
+    )
+    .map_err(ApiError::Io)?;
+    let tail = format!("\r\n--{boundary}--\r\n");
+    let content_type = format!("multipart/mixed; boundary={boundary}");
+    Ok(match output {
+        Signed::Memory(data) => {
+            head.extend_from_slice(&data);
+            head.extend_from_slice(tail.as_bytes());
+            warp::reply::with_header(head, "content-type", content_type).into_response()
+        }
+        Signed::File(file) => {
+            let body = stream::once(async { Ok(Bytes::from(head)) })
+                .chain(chunks(file))
+                .chain(stream::once(async { Ok(Bytes::from(tail)) }));
+            streamed(body, &content_type)
+        }
+    })
+}
+
+/// Whether the caller asked for the manifest with the signed file, by accepting
+/// `multipart/mixed` or with `?include=manifest`.
+fn include_manifest() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept")
+        .and(warp::query::<HashMap<String, String>>())
+        .map(|accept: Option<String>, query: HashMap<String, String>| {
+            accept.is_some_and(|x| x.contains("multipart/mixed"))
+                || query
+                    .get("include")
+                    .is_some_and(|x| x.split(',').any(|x| x == "manifest"))
+        })
+}
+
+/// The upload limit of a body, whether it is small enough to be signed from memory, and the
+/// container it is spilled to when it is too large for a temporary file.
+#[derive(Clone)]
+struct Body {
+    limit: u64,
+    in_memory: bool,
+    spill: Option<Arc<Spill>>,
+}
+
+fn body(
+    limit: u64,
+    memory_limit: u64,
+    spill: Option<Arc<Spill>>,
+) -> impl Filter<Extract = (Body,), Error = Rejection> + Clone {
+    self::limit(limit)
+        .and(warp::header::optional::<u64>("content-length"))
+        .map(move |limit, length: Option<u64>| Body {
+            limit,
+            in_memory: length.is_some_and(|x| x <= memory_limit),
+            spill: spill
+                .clone()
+                .filter(|spill| length.is_some_and(|x| spill.spills(x))),
+        })
+}
+
+async fn sign_file(
+    _permit: OwnedSemaphorePermit,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    include_manifest: bool,
+    body: Body,
+    content_type: Option<String>,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
+) -> Result<impl Reply, Rejection> {
+    let result = async {
+        if content_type
+            .as_deref()
+            .is_some_and(|x| x.starts_with("application/json"))
+        {
+            let mut upload = read_envelope(stream, body.limit).await?;
+            let manifest_definition = upload
+                .manifest_definition
+                .as_deref()
+                .unwrap_or(&manifest_definition);
+            let output = sign(
+                &context,
+                manifest_definition,
+                &upload.content_type,
+                upload.file.as_file_mut(),
+            )
+            .await?;
+            return signed(output, upload.content_type, include_manifest).await;
+        }
+
+        // Small bodies are signed from memory, larger ones and those of unknown size are
+        // copied to a temporary file first so they don't take up the memory of the instance,
+        // and the largest to a blob so they don't fill up its disk. The outputs of all but the
+        // small ones are signed to a temporary file and streamed back.
+        let (output, content_type) = if body.in_memory {
+            let mut input = Vec::new();
+            copy(&mut input, stream, body.limit).await?;
+            let mut input = Cursor::new(input);
+            let content_type = media_type(content_type.as_deref(), &mut input)?;
+            let output = sign(&context, &manifest_definition, &content_type, &mut input).await?;
+            (output, content_type)
+        } else if let Some(spill) = &body.spill {
+            let mut input = spill.write(stream, body.limit).await?;
+            let content_type = media_type(content_type.as_deref(), &mut input)?;
+            let output =
+                sign_to_file(&context, &manifest_definition, &content_type, &mut input).await?;
+            (output, content_type)
+        } else {
+            let mut file = NamedTempFile::new().map_err(ApiError::Io)?;
+            copy_to_file(file.as_file(), stream, body.limit).await?;
+            let content_type = media_type(content_type.as_deref(), file.as_file_mut())?;
+            let output = sign_to_file(
+                &context,
+                &manifest_definition,
+                &content_type,
+                file.as_file_mut(),
+            )
+            .await?;
+            (output, content_type)
+        };
+        signed(output, content_type, include_manifest).await
+    };
+    result.await.map_err(warp::reject::custom)
+}
+
+async fn sign_form(
+    _permit: OwnedSemaphorePermit,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    include_manifest: bool,
+    limit: u64,
+    form: FormData,
+) -> Result<impl Reply, Rejection> {
+    let mut upload = read_form(form, limit).await.map_err(warp::reject::custom)?;
+    let manifest_definition = upload
+        .manifest_definition
+        .as_deref()
+        .unwrap_or(&manifest_definition);
+    let output = sign_to_file(
+        &context,
+        manifest_definition,
+        &upload.content_type,
+        upload.file.as_file_mut(),
+    )
+    .await
+    .map_err(warp::reject::custom)?;
+    signed(output, upload.content_type, include_manifest)
+        .await
+        .map_err(warp::reject::custom)
+}
+
+/// The body of `POST /api/verify`: the verdict and failing codes, with the validation
+/// results, signer, timestamp and assertions of the active manifest when there is one.
+#[derive(Serialize)]
+struct Verification {
+    #[serde(flatten)]
+    summary: ValidationSummary,
+    #[serde(flatten)]
+    result: Option<VerificationResult>,
+}
+
+/// Whether the caller asked for the reader JSON with `?raw=true`.
+fn raw() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .map(|query: HashMap<String, String>| query.get("raw").is_some_and(|x| x == "true"))
+}
+
+/// The JSON of a verify request: the verdict and details, or the reader JSON with `raw`.
+pub(crate) async fn verification(
+    content_type: &str,
+    file: &mut File,
+    raw: bool,
+) -> Result<Vec<u8>, ApiError> {
+    if raw {
+        return Ok(verify(content_type, file)
+            .await
+            .map_err(ApiError::C2pa)?
+            .into_bytes());
+    }
+    let result = verify_file_detailed(content_type, file).await;
+    let details = result.as_ref().ok().cloned();
+    let summary = ValidationSummary::from_result(result).map_err(ApiError::C2pa)?;
+    serde_json::to_vec(&Verification {
+        summary,
+        result: details,
+    })
+    .map_err(|x| ApiError::Io(x.into()))
+}
+
+// The JSON reply to a verify request, from the cache when the same file was verified before.
+// Errors aren't cached.
+async fn verified(
+    cache: Option<Arc<VerifyCache>>,
+    content_type: &str,
+    file: &mut File,
+    raw: bool,
+) -> Result<impl Reply + use<>, ApiError> {
+    let key = match &cache {
+        Some(cache) => Some(cache.key(file, content_type, raw).map_err(ApiError::Io)?),
+        None => None,
+    };
+    if let (Some(cache), Some(key)) = (&cache, &key)
+        && let Some(body) = cache.get(key).await
+    {
+        return Ok(warp::reply::with_header(
+            body.to_vec(),
+            "content-type",
+            "application/json",
+        ));
+    }
+
+    let body = verification(content_type, file, raw).await?;
+    if let (Some(cache), Some(key)) = (cache, key) {
+        cache.put(key, body.as_slice().into()).await;
+    }
+    Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        "application/json",
+    ))
+}
+
+async fn verify_file(
+    cache: Option<Arc<VerifyCache>>,
+    limit: u64,
+    raw: bool,
+    content_type: Option<String>,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
+) -> Result<impl Reply, Rejection> {
+    let mut file = NamedTempFile::new().map_err(|x| warp::reject::custom(ApiError::Io(x)))?;
+    copy_to_file(file.as_file_mut(), stream, limit)
+        .await
+        .map_err(warp::reject::custom)?;
+    let result = async {
+        let content_type = media_type(content_type.as_deref(), file.as_file_mut())?;
+        verified(cache, &content_type, file.as_file_mut(), raw).await
+    };
+    result.await.map_err(warp::reject::custom)
+}
+
+async fn verify_form(
+    cache: Option<Arc<VerifyCache>>,
+    limit: u64,
+    raw: bool,
+    form: FormData,
+) -> Result<impl Reply, Rejection> {
+    let mut upload = read_form(form, limit).await.map_err(warp::reject::custom)?;
+    verified(cache, &upload.content_type, upload.file.as_file_mut(), raw)
+        .await
+        .map_err(warp::reject::custom)
+}
+
+/// The upload limit, rejecting bodies whose `content-length` is larger before reading them.
+pub(crate) fn limit(limit: u64) -> impl Filter<Extract = (u64,), Error = Rejection> + Clone {
+    warp::header::optional::<u64>("content-length").and_then(
+        move |length: Option<u64>| async move {
+            match length {
+                Some(length) if length > limit => {
+                    Err(warp::reject::custom(ApiError::TooLarge(limit)))
+                }
+                _ => Ok(limit),
+            }
+        },
+    )
+}
+
+/// A slot of the signing concurrency limit, held until the response is complete. Requests
+/// are rejected with 429 when all are taken, instead of queueing uploads and signing calls.
+pub(crate) fn permit(
+    semaphore: Arc<Semaphore>,
+) -> impl Filter<Extract = (OwnedSemaphorePermit,), Error = Rejection> + Clone {
+    warp::any().and_then(move || {
+        let semaphore = semaphore.clone();
+        async move {
+            semaphore
+                .try_acquire_owned()
+                .map_err(|_| warp::reject::custom(ApiError::Busy))
+        }
+    })
+}
+
+// Reply to the rejections of the handlers with a status code and the error as text.
+async fn recover(err: Rejection) -> Result<impl Reply, Rejection> {
+    let Some(error) = err.find::<ApiError>() else {
+        return Err(err);
+    };
+    let status = match error {
+        ApiError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+        ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+        ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        ApiError::Conflict(_) => StatusCode::CONFLICT,
+        ApiError::Busy | ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        ApiError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        error => {
+            log::error!("{error:?}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let message = error.message();
+    let mut response = warp::reply::with_status(message, status).into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    match error {
+        ApiError::Busy => {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+        }
+        ApiError::RateLimited(wait) => {
+            let secs = wait.as_secs_f64().ceil() as u64;
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, secs.max(1).into());
+        }
+        _ => (),
+    }
+    Ok(response)
+}
+
+/// `POST /api/sign`, `/api/sign/batch`, `/api/sign/blob` and `/api/verify`, the job and
+/// upload APIs and the health probes and metrics, signing with the configuration of the tenant of each request.
+/// The credential, when there is one, reads and writes blobs without a SAS token.
+pub fn routes(
+    tenants: Arc<Tenants>,
+    options: Options,
+    readiness: Arc<Readiness>,
+    credential: Option<Arc<dyn TokenCredential>>,
+) -> std::io::Result<impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone> {
+    let max = options.max_upload_size;
+    let cors = cors::cors(&options)?;
+    let health = health::routes(
+        tenants.default().clone(),
+        readiness,
+        Duration::from_secs(options.certificate_window_secs),
+    );
+    let authorizer = Authorizer::new(&options, tenants.keys())?.map(Arc::new);
+    let semaphore = Arc::new(Semaphore::new(match options.max_concurrent_signs {
+        0 => Semaphore::MAX_PERMITS,
+        permits => permits,
+    }));
+    let limiter = RateLimiter::new(&options, authorizer.clone()).map(Arc::new);
+    let jobs = Arc::new(Jobs::new(Webhooks::new(&options)?)?);
+    jobs.expire_periodically();
+    let uploads = uploads::routes(
+        jobs.clone(),
+        tenants.clone(),
+        max,
+        authorizer.clone(),
+        limiter.clone(),
+        semaphore.clone(),
+    )?;
+    let jobs = jobs::routes(
+        jobs,
+        tenants.clone(),
+        max,
+        authorizer.clone(),
+        limiter.clone(),
+        semaphore.clone(),
+    );
+    let content_type = warp::header::optional::<String>("content-type");
+    let with_cache = {
+        let cache = VerifyCache::new(&options, tenants.default().clone())?.map(Arc::new);
+        warp::any().map(move || cache.clone())
+    };
+
+    // Multipart uploads are tried first, other bodies are the raw file.
+    let form = move || warp::multipart::form().max_length(max);
+
+    let verify = warp::path("verify")
+        .and(warp::path::end())
+        .and(with_cache.clone())
+        .and(limit(max))
+        .and(raw())
+        .and(form())
+        .and_then(verify_form)
+        .or(warp::path("verify")
+            .and(warp::path::end())
+            .and(with_cache)
+            .and(limit(max))
+            .and(raw())
+            .and(content_type)
+            .and(warp::filters::body::stream())
+            .and_then(verify_file));
+
+    let capabilities = capabilities::routes(authorizer.clone(), tenants.clone());
+    let configuration = tenants::configuration(authorizer.clone(), tenants);
+    // Forms are rate limited once they are known to be forms, so other uploads don't take a
+    // token twice.
+    let with_semaphore = {
+        let semaphore = semaphore.clone();
+        warp::any().map(move || semaphore.clone())
+    };
+    let batch = warp::path!("sign" / "batch")
+        .and(authorize(authorizer.clone()))
+        .and(permit(semaphore.clone()))
+        .and(with_semaphore.clone())
+        .and(configuration.clone())
+        .and(limit(max))
+        .and(form())
+        .and(rate_limit(limiter.clone()))
+        .and_then(batch::sign_form)
+        .or(warp::path!("sign" / "batch")
+            .and(authorize(authorizer.clone()))
+            .and(rate_limit(limiter.clone()))
+            .and(permit(semaphore.clone()))
+            .and(with_semaphore)
+            .and(configuration.clone())
+            .and(limit(max))
+            .and(warp::filters::body::stream())
+            .and_then(batch::sign_zip));
+    let blobs = Arc::new(Blobs::new(credential, &options)?);
+    let spill = Spill::new(blobs.clone(), &options)?.map(Arc::new);
+    let blob = warp::path!("sign" / "blob")
+        .and(authorize(authorizer.clone()))
+        .and(rate_limit(limiter.clone()))
+        .and(permit(semaphore.clone()))
+        .and(warp::any().map(move || blobs.clone()))
+        .and(configuration.clone())
+        .and(warp::any().map(move || max))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and_then(blob::sign_blob);
+    let sign = warp::path("sign")
+        .and(warp::path::end())
+        .and(authorize(authorizer.clone()))
+        .and(permit(semaphore.clone()))
+        .and(configuration.clone())
+        .and(include_manifest())
+        .and(limit(max))
+        .and(form())
+        .and(rate_limit(limiter.clone()))
+        .and_then(sign_form)
+        .or(warp::path("sign")
+            .and(warp::path::end())
+            .and(authorize(authorizer))
+            .and(rate_limit(limiter))
+            .and(permit(semaphore))
+            .and(configuration)
+            .and(include_manifest())
+            .and(body(max, options.memory_sign_limit, spill))
+            .and(content_type)
+            .and(warp::filters::body::stream())
+            .and_then(sign_file));
+
+    let routes = warp::post()
+        .and(warp::path("api"))
+        .and(verify.or(sign).or(batch).or(blob))
+        .or(jobs)
+        .or(uploads)
+        .or(capabilities)
+        .or(health)
+        .or(metrics::routes())
+        .or(docs::routes(&options))
+        .recover(recover);
+    // Errors get the CORS headers too, so scripts can read their status.
+    let routes = match cors {
+        Some(cors) => routes
+            .with(cors)
+            .map(|x| Box::new(x) as Box<dyn Reply>)
+            .boxed(),
+        None => routes.map(|x| Box::new(x) as Box<dyn Reply>).boxed(),
+    };
+    Ok(logging::requests(routes, options.trusted_proxy_hops)
+        .with(warp::log::custom(metrics::record)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_host() {
+        let suffixes = vec![
+            ".blob.core.windows.net".to_owned(),
+            "contoso.com".to_owned(),
+        ];
+        assert!(allowed_host("account.blob.core.windows.net", &suffixes));
+        assert!(allowed_host("contoso.com", &suffixes));
+        assert!(allowed_host("hooks.Contoso.com", &suffixes));
+        assert!(!allowed_host("evilcontoso.com", &suffixes));
+        assert!(!allowed_host("blob.core.windows.net.evil.com", &suffixes));
+        assert!(!allowed_host("anything", &[String::new()]));
+    }
+
+    #[test]
+    fn test_error_message_hides_internals() {
+        let error = ApiError::Io(io::Error::other("/tmp/.tmpX1y2 is not writable"));
+        assert!(!error.message().contains("/tmp"));
+        let error = ApiError::BadRequest("No file was sent".to_owned());
+        assert_eq!(error.message(), "No file was sent");
+    }
+}
@@ -0,0 +1,228 @@
+//! Logs as JSON lines that Azure Log Analytics parses into columns, and one record per request
+//! with its correlation id, trace id, client, content type, size and outcome.
+use c2pa_azure::TraceParent;
+use log::kv::{self, Key, VisitSource};
+use serde_json::{Map, Value, json};
+use std::{io::Write, net::SocketAddr, time::Instant};
+use uuid::Uuid;
+use warp::{
+    Filter, Rejection, Reply,
+    http::{HeaderValue, Method},
+    path::FullPath,
+};
+
+const CORRELATION_ID: &str = "x-correlation-id";
+
+// Longer ids from clients are replaced, so they can't bloat every record.
+const MAX_ID_LENGTH: usize = 128;
+
+struct Fields(Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(x) = value.to_u64() {
+            json!(x)
+        } else if let Some(x) = value.to_i64() {
+            json!(x)
+        } else if let Some(x) = value.to_f64() {
+            json!(x)
+        } else if let Some(x) = value.to_bool() {
+            json!(x)
+        } else {
+            // Empty strings are fields the request didn't have.
+            let value = value.to_string();
+            if value.is_empty() {
+                return Ok(());
+            }
+            json!(value)
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Log to stderr as JSON lines with the time, level, target, message and the key-values of the
+/// record, filtered by `RUST_LOG` like `env_logger::init`.
+pub fn init_logging() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let mut fields = Fields(Map::new());
+            fields
+                .0
+                .insert("time".to_owned(), json!(buf.timestamp().to_string()));
+            fields
+                .0
+                .insert("level".to_owned(), json!(record.level().as_str()));
+            fields.0.insert("target".to_owned(), json!(record.target()));
+            fields
+                .0
+                .insert("message".to_owned(), json!(record.args().to_string()));
+            _ = record.key_values().visit(&mut fields);
+            writeln!(buf, "{}", Value::Object(fields.0))
+        })
+        .init();
+}
+
+/// What the request record says about the request, taken before it is handled.
+struct Request {
+    correlation_id: String,
+    /// The trace id of a valid `traceparent`, which is carried to Trusted Signing.
+    trace_id: Option<String>,
+    method: Method,
+    path: FullPath,
+    client: Option<String>,
+    user_agent: Option<String>,
+    content_type: Option<String>,
+    /// The `content-length`, unknown for chunked uploads.
+    size: Option<u64>,
+    started: Instant,
+}
+
+/// The peer of a connection the handler accepted itself, which warp doesn't know when it
+/// serves the routes as a service.
+#[derive(Clone, Copy)]
+pub(crate) struct Peer(pub(crate) SocketAddr);
+
+/// The address of the peer of the request.
+pub(crate) fn remote()
+-> impl Filter<Extract = (Option<SocketAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<Peer>())
+        .map(|remote: Option<SocketAddr>, peer: Option<Peer>| peer.map(|x| x.0).or(remote))
+}
+
+fn correlation_id(header: Option<String>) -> String {
+    header
+        .filter(|x| !x.is_empty() && x.len() <= MAX_ID_LENGTH)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// The address of the client. Each of the `hops` trusted proxies in front of the handler
+/// appends the address it got the request from to `x-forwarded-for`, so the client is the
+/// entry `hops` from the end; the entries before it are whatever the client sent. With no
+/// proxies, or no `x-forwarded-for`, it is the peer.
+pub(crate) fn client(
+    forwarded: Option<&str>,
+    remote: Option<SocketAddr>,
+    hops: usize,
+) -> Option<String> {
+    let entries = forwarded
+        .into_iter()
+        .flat_map(|x| x.split(','))
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    hops.checked_sub(1)
+        .and_then(|x| entries.iter().rev().nth(x).or(entries.first()))
+        .map(|x| (*x).to_owned())
+        .or_else(|| remote.map(|x| x.ip().to_string()))
+}
+
+fn request(
+    hops: usize,
+) -> impl Filter<Extract = (Request,), Error = std::convert::Infallible> + Clone {
+    warp::any()
+        .map(Instant::now)
+        .and(warp::header::optional::<String>(CORRELATION_ID))
+        .and(warp::header::optional::<String>("traceparent"))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(remote())
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::header::optional::<u64>("content-length"))
+        .map(
+            move |started,
+                  id,
+                  traceparent: Option<String>,
+                  method,
+                  path,
+                  forwarded: Option<String>,
+                  remote,
+                  user_agent,
+                  content_type,
+                  size| {
+                Request {
+                    correlation_id: correlation_id(id),
+                    trace_id: traceparent
+                        .as_deref()
+                        .and_then(TraceParent::parse)
+                        .map(|x| x.trace_id().to_owned()),
+                    method,
+                    path,
+                    client: client(forwarded.as_deref(), remote, hops),
+                    user_agent,
+                    content_type,
+                    size,
+                    started,
+                }
+            },
+        )
+}
+
+/// Log a record for every request the routes answer and return its correlation id, the
+/// `x-correlation-id` of the request or a new one, in the `x-correlation-id` header. The client
+/// is taken from behind `hops` trusted proxies.
+pub(crate) fn requests<F, R>(
+    routes: F,
+    hops: usize,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    request(hops).and(routes).map(|request: Request, reply: R| {
+        let mut response = reply.into_response();
+        let status = response.status();
+        let outcome = if status.is_success() {
+            "success"
+        } else if status.is_client_error() {
+            "rejected"
+        } else {
+            "failure"
+        };
+        log::info!(
+            target: "request",
+            correlation_id = request.correlation_id.as_str(),
+            trace_id = request.trace_id.as_deref().unwrap_or_default(),
+            method = request.method.as_str(),
+            path = request.path.as_str(),
+            client = request.client.as_deref().unwrap_or_default(),
+            user_agent = request.user_agent.as_deref().unwrap_or_default(),
+            content_type = request.content_type.as_deref().unwrap_or_default(),
+            content_length = request.size.unwrap_or_default(),
+            status = status.as_u16(),
+            outcome = outcome,
+            duration_ms = request.started.elapsed().as_millis() as u64;
+            "{} {} {}",
+            request.method,
+            request.path.as_str(),
+            status.as_u16()
+        );
+        if let Ok(value) = HeaderValue::from_str(&request.correlation_id) {
+            response.headers_mut().insert(CORRELATION_ID, value);
+        }
+        response
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client() {
+        let remote = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+        let forwarded = Some("203.0.113.9, 198.51.100.7");
+        assert_eq!(
+            client(forwarded, remote, 1).as_deref(),
+            Some("198.51.100.7")
+        );
+        assert_eq!(client(forwarded, remote, 2).as_deref(), Some("203.0.113.9"));
+        assert_eq!(client(forwarded, remote, 3).as_deref(), Some("203.0.113.9"));
+        assert_eq!(client(forwarded, remote, 0).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client(None, remote, 1).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client(None, None, 1), None);
+    }
+}
@@ -1,153 +1,118 @@
-use azure_core::credentials::TokenCredential;
-use azure_identity::{AzureCliCredential, ManagedIdentityCredential};
-use c2pa::{Builder, Context, Reader};
-use c2pa_azure::{Envconfig, SigningOptions, TrustedSigner};
-use futures::StreamExt;
-use std::fs::{self, File};
-use std::io::{Cursor, Write};
-use std::net::Ipv4Addr;
-use std::path::Path;
+use azure_function::{Config, Options, Readiness, Tenants, init_logging, routes, serve, telemetry};
+use c2pa::AsyncSigner;
+#[cfg(feature = "dev-signer")]
+use c2pa_azure::{DevSigner, DevSignerOptions};
+use c2pa_azure::{Envconfig, SigningOptions, TrustedSigner, credential_for_environment};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{env, io::Seek};
-use tempfile::NamedTempFile;
-use warp::{Buf, Error, Filter, Rejection, Reply, Stream, reject::Reject};
+use std::time::Duration;
 
-#[allow(dead_code)]
-#[derive(Debug)]
-enum ApiError {
-    Azure(azure_core::Error),
-    Io(std::io::Error),
-    C2pa(c2pa_azure::Error),
-    Warp(Error),
-}
-
-impl Reject for ApiError {}
-
-async fn copy_to_file(
-    mut file: &File,
-    mut stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
-) -> Result<(), ApiError> {
-    while let Some(value) = stream.next().await {
-        match value {
-            Ok(mut buf) => {
-                while buf.has_remaining() {
-                    let chunk = buf.chunk();
-                    file.write_all(chunk).unwrap();
-                    buf.advance(chunk.len());
-                }
-            }
-            Err(e) => {
-                log::error!("Error copying the body to file: {e:?}");
-                return Err(ApiError::Warp(e));
-            }
-        }
+// Sign with the local development certificate when DEV_SIGNER_CERTIFICATE is set.
+#[cfg(feature = "dev-signer")]
+fn dev_signer() -> Result<Option<Arc<dyn AsyncSigner + Send + Sync>>, anyhow::Error> {
+    if env::var("DEV_SIGNER_CERTIFICATE").is_err() {
+        return Ok(None);
     }
-    file.rewind().map_err(ApiError::Io)?;
-    Ok(())
+    let signer = DevSigner::new(&DevSignerOptions::init_from_env()?)?;
+    Ok(Some(Arc::new(signer)))
 }
 
-async fn sign_file(
-    context: Arc<Context>,
-    manifest_definition: Arc<String>,
-    content_type: String,
-    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
-) -> Result<impl Reply, Rejection> {
-    let mut file = NamedTempFile::new().map_err(|x| warp::reject::custom(ApiError::Io(x)))?;
-    copy_to_file(file.as_file_mut(), stream)
-        .await
-        .map_err(warp::reject::custom)?;
-
-    let mut output = Cursor::new(Vec::new());
-    let mut builder = Builder::from_shared_context(&context)
-        .with_definition(&*manifest_definition)
-        .map_err(|x| warp::reject::custom(ApiError::C2pa(x)))?;
-    let signer = context
-        .async_signer()
-        .map_err(|x| warp::reject::custom(ApiError::C2pa(x)))?;
-    builder
-        .sign_async(signer, &content_type, &mut file.as_file_mut(), &mut output)
-        .await
-        .map_err(|x| warp::reject::custom(ApiError::C2pa(x)))?;
-    log::info!("Successfully signed the file.");
-    Ok(warp::reply::with_header(
-        output.into_inner(),
-        "content-type",
-        content_type,
-    ))
-}
-
-async fn verify_file(
-    content_type: String,
-    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send + Sync,
-) -> Result<impl Reply, Rejection> {
-    let mut file = NamedTempFile::new().map_err(|x| warp::reject::custom(ApiError::Io(x)))?;
-    copy_to_file(file.as_file_mut(), stream)
-        .await
-        .map_err(warp::reject::custom)?;
-
-    let reader = Reader::from_context(Context::new())
-        .with_stream_async(&content_type, file.as_file_mut())
-        .await
-        .map_err(|x| warp::reject::custom(ApiError::C2pa(x)))?;
-    let manifest = reader.json();
-    Ok(warp::reply::with_header(
-        manifest,
-        "content-type",
-        "application/json",
-    ))
+#[cfg(not(feature = "dev-signer"))]
+fn dev_signer() -> Result<Option<Arc<dyn AsyncSigner + Send + Sync>>, anyhow::Error> {
+    Ok(None)
 }
 
 const DEFAULT_MANIFEST: &str = include_str!("../../../test_data/manifest_definition.json");
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    env_logger::init();
-    for (key, value) in std::env::vars() {
-        log::info!("{key}: {value}");
-    }
-    let credentials: Arc<dyn TokenCredential> = if cfg!(debug_assertions) {
-        AzureCliCredential::new(None)?
-    } else {
-        ManagedIdentityCredential::new(None)?
-    };
+    init_logging();
+    // Only a manifest definition read from a file is reloaded.
+    let mut manifest_path = None;
     let manifest_definition = env::var("MANIFEST_DEFINITION").ok();
-    let manifest_definition = Arc::new(if let Some(manifest) = manifest_definition {
+    let manifest_definition = if let Some(manifest) = manifest_definition {
         let path = Path::new(&manifest);
         if path.exists() {
+            manifest_path = Some(path.to_owned());
             fs::read_to_string(path)?
         } else {
             manifest
         }
     } else {
         DEFAULT_MANIFEST.to_owned()
-    });
-
-    let content_type = warp::header::<String>("content-type");
-
-    let verify = warp::path("verify")
-        .and(warp::path::end())
-        .and(content_type)
-        .and(warp::filters::body::stream())
-        .and_then(verify_file);
-
-    let options = SigningOptions::init_from_env()?;
-    let signer = TrustedSigner::new(credentials, options).await?;
-    let context = Context::new().with_async_signer(signer).into_shared();
-    let sign = warp::path("sign")
-        .and(warp::path::end())
-        .and(warp::any().map(move || context.clone()))
-        .and(warp::any().map(move || manifest_definition.clone()))
-        .and(content_type)
-        .and(warp::filters::body::stream())
-        .and_then(sign_file);
+    };
 
-    let routes = warp::post().and(warp::path("api")).and(verify.or(sign));
+    let readiness = Arc::new(Readiness::default());
+    let mut credential = None;
+    let signer: Arc<dyn AsyncSigner + Send + Sync> = match dev_signer()? {
+        Some(signer) => signer,
+        None => {
+            // Only the signer's token acquisitions decide readiness, blobs and tenants use
+            // the credential itself.
+            let identity = credential_for_environment()?;
+            credential = Some(identity.clone());
+            let options = SigningOptions::init_from_env()?;
+            let mut client_options = options.client_options();
+            client_options.telemetry = Some(telemetry());
+            let tracked = readiness.track(identity, &client_options.scope);
+            let signer =
+                TrustedSigner::with_client_options(tracked, options, client_options).await?;
+            Arc::new(signer)
+        }
+    };
     let port_key = "FUNCTIONS_CUSTOMHANDLER_PORT";
     let port: u16 = match env::var(port_key) {
         Ok(val) => val.parse().expect("Custom Handler port is not a number!"),
         Err(_) => 3000,
     };
 
-    warp::serve(routes).run((Ipv4Addr::UNSPECIFIED, port)).await;
+    let options = Options::init_from_env()?;
+    let settings_path = options.settings_file.as_ref().map(PathBuf::from);
+    let settings = settings_path.as_ref().map(fs::read_to_string).transpose()?;
+    let config = Arc::new(Config::new(
+        signer,
+        settings.as_deref(),
+        manifest_definition,
+    )?);
+    if options.reload_interval_secs > 0 {
+        config.watch(
+            settings_path,
+            manifest_path,
+            Duration::from_secs(options.reload_interval_secs),
+        );
+    }
+    let tenants = Arc::new(match &options.tenants_file {
+        Some(path) => {
+            let credential = credential
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Tenants can't sign with the dev signer"))?;
+            Tenants::load(config, Path::new(path), credential, settings.as_deref()).await?
+        }
+        None => Tenants::from(config),
+    });
+    #[cfg(feature = "grpc")]
+    if let Some(port) = options.grpc_port {
+        let grpc = azure_function::serve_grpc(
+            tenants.clone(),
+            options.clone(),
+            (options.bind_address, port),
+        );
+        tokio::spawn(async move {
+            if let Err(err) = grpc.await {
+                log::error!("The gRPC service stopped: {err:?}");
+            }
+        });
+    }
+    #[cfg(not(feature = "grpc"))]
+    if options.grpc_port.is_some() {
+        log::warn!("GRPC_PORT is set but the function was built without the grpc feature");
+    }
+    let grace = Duration::from_secs(options.shutdown_grace_secs);
+    let address = (options.bind_address, port);
+    let tls = options.tls()?;
+    let routes = routes(tenants, options, readiness, credential)?;
+    serve(routes, address, tls, grace).await;
     Ok(())
 }
@@ -0,0 +1,241 @@
+//! `GET /metrics` in the Prometheus text format, for Azure Monitor managed Prometheus and
+//! other scrapers.
+use c2pa_azure::{RequestTelemetry, TelemetryHandler};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+use warp::{Filter, Rejection, Reply, log::Info};
+
+// Upper bounds in seconds of the signing latency buckets.
+const BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+// Requests are counted by route rather than path, so job ids don't become labels. Every route
+// of `routes()` is listed, more specific ones first.
+pub(crate) const ROUTES: [&str; 12] = [
+    "/api/sign/batch",
+    "/api/sign/blob",
+    "/api/sign",
+    "/api/verify",
+    "/api/jobs",
+    "/api/results",
+    "/api/uploads",
+    "/api/capabilities",
+    "/api/docs",
+    "/healthz",
+    "/readyz",
+    "/metrics",
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    /// Requests by route and status.
+    requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+    /// Signing operations by outcome.
+    signs: Mutex<BTreeMap<&'static str, u64>>,
+    sign_duration: Mutex<Histogram>,
+    in_flight: AtomicI64,
+    /// Trusted Signing requests by status, `error` when there was no response.
+    acs: Mutex<BTreeMap<String, u64>>,
+    /// Verify cache lookups by result.
+    verify_cache: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Counts a signing operation as in flight until dropped.
+pub(crate) struct InFlight;
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn start_signing() -> InFlight {
+    METRICS.in_flight.fetch_add(1, Ordering::Relaxed);
+    InFlight
+}
+
+pub(crate) fn signed(duration: Duration, success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    *METRICS.signs.lock().unwrap().entry(outcome).or_default() += 1;
+    METRICS
+        .sign_duration
+        .lock()
+        .unwrap()
+        .observe(duration.as_secs_f64());
+}
+
+pub(crate) fn verify_cache(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    *METRICS
+        .verify_cache
+        .lock()
+        .unwrap()
+        .entry(result)
+        .or_default() += 1;
+}
+
+/// Completes once no signing operation is in flight.
+pub(crate) async fn drained() {
+    while METRICS.in_flight.load(Ordering::Relaxed) > 0 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Counts every response, for use with `warp::log::custom`.
+pub(crate) fn record(info: Info<'_>) {
+    let route = ROUTES
+        .into_iter()
+        .find(|x| info.path().starts_with(x))
+        .unwrap_or("other");
+    let status = info.status().as_u16();
+    *METRICS
+        .requests
+        .lock()
+        .unwrap()
+        .entry((route, status))
+        .or_default() += 1;
+}
+
+#[derive(Debug)]
+struct AcsTelemetry;
+
+impl TelemetryHandler for AcsTelemetry {
+    fn on_request(&self, telemetry: &RequestTelemetry) {
+        let status = telemetry
+            .status
+            .map(|x| u16::from(x).to_string())
+            .unwrap_or_else(|| "error".to_owned());
+        *METRICS.acs.lock().unwrap().entry(status).or_default() += 1;
+    }
+}
+
+/// Telemetry handler for the Trusted Signing client options, counting its requests by status.
+pub fn telemetry() -> Arc<dyn TelemetryHandler> {
+    Arc::new(AcsTelemetry)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn render() -> String {
+    let mut out = String::new();
+    header(
+        &mut out,
+        "c2pa_http_requests_total",
+        "counter",
+        "HTTP requests by route and status.",
+    );
+    for ((route, status), count) in METRICS.requests.lock().unwrap().iter() {
+        _ = writeln!(
+            out,
+            "c2pa_http_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "c2pa_sign_total",
+        "counter",
+        "Signing operations by outcome.",
+    );
+    for (outcome, count) in METRICS.signs.lock().unwrap().iter() {
+        _ = writeln!(out, "c2pa_sign_total{{outcome=\"{outcome}\"}} {count}");
+    }
+
+    header(
+        &mut out,
+        "c2pa_sign_duration_seconds",
+        "histogram",
+        "Time to sign a file, including the Trusted Signing calls.",
+    );
+    let histogram = METRICS.sign_duration.lock().unwrap();
+    for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+        _ = writeln!(
+            out,
+            "c2pa_sign_duration_seconds_bucket{{le=\"{bound}\"}} {count}"
+        );
+    }
+    _ = writeln!(
+        out,
+        "c2pa_sign_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+        histogram.count
+    );
+    _ = writeln!(out, "c2pa_sign_duration_seconds_sum {}", histogram.sum);
+    _ = writeln!(out, "c2pa_sign_duration_seconds_count {}", histogram.count);
+    drop(histogram);
+
+    header(
+        &mut out,
+        "c2pa_sign_in_flight",
+        "gauge",
+        "Signing operations in progress.",
+    );
+    _ = writeln!(
+        out,
+        "c2pa_sign_in_flight {}",
+        METRICS.in_flight.load(Ordering::Relaxed)
+    );
+
+    header(
+        &mut out,
+        "c2pa_acs_requests_total",
+        "counter",
+        "Trusted Signing requests by status, error when no response was received.",
+    );
+    for (status, count) in METRICS.acs.lock().unwrap().iter() {
+        _ = writeln!(
+            out,
+            "c2pa_acs_requests_total{{status=\"{status}\"}} {count}"
+        );
+    }
+
+    header(
+        &mut out,
+        "c2pa_verify_cache_total",
+        "counter",
+        "Verify cache lookups by result.",
+    );
+    for (result, count) in METRICS.verify_cache.lock().unwrap().iter() {
+        _ = writeln!(
+            out,
+            "c2pa_verify_cache_total{{result=\"{result}\"}} {count}"
+        );
+    }
+    out
+}
+
+/// `GET /metrics`.
+pub(crate) fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("metrics"))
+        .map(|| warp::reply::with_header(render(), "content-type", "text/plain; version=0.0.4"))
+}
@@ -0,0 +1,198 @@
+//! Token buckets per client, so one caller can't use up the Trusted Signing quota of everyone.
+//! Callers are told apart by the function key or token that authorized them, and by their
+//! address otherwise.
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use warp::{Filter, Rejection};
+
+use crate::{
+    ApiError, Options,
+    auth::Authorizer,
+    logging::{client, remote},
+};
+
+// Buckets are pruned once there are this many: the full ones are dropped, then the least
+// recently used ones until there are PRUNED_BUCKETS left, so pruning runs once for every
+// thousand new callers at most.
+const MAX_BUCKETS: usize = 10_000;
+const PRUNED_BUCKETS: usize = 9_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The buckets of the callers, refilled at `rate` tokens a second up to `burst`.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    authorizer: Option<Arc<Authorizer>>,
+    hops: usize,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// The limiter of the options, none when `RATE_LIMIT_PER_MINUTE` is 0. The authorizer
+    /// tells which keys and tokens identify a caller.
+    pub(crate) fn new(options: &Options, authorizer: Option<Arc<Authorizer>>) -> Option<Self> {
+        if options.rate_limit_per_minute == 0 {
+            return None;
+        }
+        Some(Self {
+            rate: f64::from(options.rate_limit_per_minute) / 60.0,
+            burst: f64::from(options.rate_limit_burst.max(1)),
+            authorizer,
+            hops: options.trusted_proxy_hops,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The key of the caller: the function key or token that authorized the request, or its
+    /// address when neither did. Keys and tokens the authorizer didn't accept are ignored, so
+    /// callers can't get a new bucket by making them up.
+    pub(crate) fn caller(
+        &self,
+        key: Option<&str>,
+        authorization: Option<&str>,
+        client: Option<&str>,
+    ) -> String {
+        self.authorizer
+            .as_ref()
+            .and_then(|x| x.caller(key, authorization))
+            .or_else(|| client.map(|x| format!("ip:{x}")))
+            .unwrap_or_default()
+    }
+
+    /// Take a token from the bucket of `client`, or return how long until there is one.
+    pub(crate) fn acquire(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(client) {
+            self.prune(&mut buckets, now);
+        }
+        let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn prune(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        buckets
+            .retain(|_, x| x.tokens + now.duration_since(x.updated).as_secs_f64() * rate < burst);
+        // Many callers that took a token each keep their buckets, so drop the oldest ones.
+        if buckets.len() <= PRUNED_BUCKETS {
+            return;
+        }
+        let mut updated: Vec<_> = buckets.values().map(|x| x.updated).collect();
+        let (_, cutoff, _) = updated.select_nth_unstable(buckets.len() - PRUNED_BUCKETS - 1);
+        let cutoff = *cutoff;
+        buckets.retain(|_, x| x.updated > cutoff);
+    }
+}
+
+/// Reject the request with 429 Too Many Requests when its caller has no token left. Runs after
+/// `authorize`.
+pub(crate) fn rate_limit(
+    limiter: Option<Arc<RateLimiter>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-functions-key")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(remote())
+        .and_then(
+            move |key: Option<String>,
+                  mut query: HashMap<String, String>,
+                  authorization: Option<String>,
+                  forwarded: Option<String>,
+                  remote: Option<SocketAddr>| {
+                let limiter = limiter.clone();
+                async move {
+                    let Some(limiter) = limiter else {
+                        return Ok(());
+                    };
+                    let key = key.or_else(|| query.remove("code"));
+                    let client = client(forwarded.as_deref(), remote, limiter.hops);
+                    let caller =
+                        limiter.caller(key.as_deref(), authorization.as_deref(), client.as_deref());
+                    limiter
+                        .acquire(&caller)
+                        .map_err(|x| warp::reject::custom(ApiError::RateLimited(x)))
+                }
+            },
+        )
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_minute: u32, burst: u32) -> RateLimiter {
+        let options = Options {
+            rate_limit_per_minute: per_minute,
+            rate_limit_burst: burst,
+            function_keys: Some("key".to_owned()),
+            ..Options::default()
+        };
+        let authorizer = Authorizer::new(&options, Vec::new()).unwrap().map(Arc::new);
+        RateLimiter::new(&options, authorizer).unwrap()
+    }
+
+    #[test]
+    fn test_unlimited() {
+        assert!(RateLimiter::new(&Options::default(), None).is_none());
+    }
+
+    #[test]
+    fn test_bucket() {
+        let limiter = limiter(60, 3);
+        for _ in 0..3 {
+            assert!(limiter.acquire("a").is_ok());
+        }
+        let wait = limiter.acquire("a").unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        // Every caller has a bucket of its own.
+        assert!(limiter.acquire("b").is_ok());
+    }
+
+    #[test]
+    fn test_buckets_are_bounded() {
+        let limiter = limiter(1, 3);
+        for i in 0..MAX_BUCKETS * 2 {
+            assert!(limiter.acquire(&format!("ip:{i}")).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+        // The most recent callers keep their buckets.
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_BUCKETS * 2 - 1)));
+    }
+
+    #[test]
+    fn test_caller() {
+        let limiter = limiter(60, 3);
+        assert_eq!(
+            limiter.caller(Some("key"), None, Some("10.0.0.1")),
+            "key:key"
+        );
+        assert_eq!(
+            limiter.caller(Some("made-up"), None, Some("10.0.0.1")),
+            "ip:10.0.0.1"
+        );
+        assert_eq!(limiter.caller(None, None, None), "");
+    }
+}
@@ -0,0 +1,82 @@
+//! Graceful shutdown: on SIGTERM or Ctrl+C stop accepting connections and give in-flight
+//! signing a grace period to finish, so scale-in doesn't leave truncated outputs.
+use futures::future::Either;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::Notify;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{Tls, metrics, tls};
+
+/// Completes on SIGTERM or Ctrl+C.
+pub(crate) async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(err) => {
+                log::warn!("Can't handle SIGTERM: {err}");
+                _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Serve the routes, over TLS when given, until SIGTERM or Ctrl+C, then wait up to `grace`
+/// for open requests and for jobs still signing.
+pub async fn serve<F, R>(
+    routes: F,
+    address: impl Into<SocketAddr>,
+    tls: Option<Tls>,
+    grace: Duration,
+) where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let stopping = Arc::new(Notify::new());
+    let graceful = {
+        let stopping = stopping.clone();
+        async move {
+            signal().await;
+            log::info!("Shutting down, waiting up to {grace:?} for in-flight signing");
+            stopping.notify_one();
+        }
+    };
+    let server = match tls {
+        Some(tls) => Either::Left(tls::run(routes, address.into(), tls, graceful)),
+        None => Either::Right(
+            warp::serve(routes)
+                .bind(address)
+                .await
+                .graceful(graceful)
+                .run(),
+        ),
+    };
+    let deadline = async {
+        stopping.notified().await;
+        tokio::time::sleep(grace).await;
+    };
+    tokio::pin!(deadline);
+
+    tokio::select! {
+        () = server => {}
+        () = &mut deadline => {
+            log::warn!("Requests still open after {grace:?}, exiting");
+            return;
+        }
+    }
+    // Jobs sign after their request has been answered.
+    tokio::select! {
+        () = metrics::drained() => log::info!("In-flight signing finished"),
+        () = deadline => log::warn!("Jobs still signing after {grace:?}, exiting"),
+    }
+}
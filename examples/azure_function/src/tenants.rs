@@ -0,0 +1,376 @@
+//! Signing for several brands or tenants from one deployment, each with its own Trusted Signing
+//! account and certificate profile. A request signs for the tenant of its function key, of a
+//! claim of its token, or named by the `x-c2pa-tenant` header when the tenants file allows it,
+//! and for the default signer otherwise.
+use azure_core::credentials::TokenCredential;
+use c2pa::{Context, SigningAlg};
+use c2pa_azure::{SigningOptions, TraceParent, TrustedSigner};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+use warp::{Filter, Rejection};
+
+use crate::{
+    ApiError, Config,
+    auth::{Authorizer, claim, same_key},
+    manifest_override, metrics,
+};
+
+pub(crate) const TENANT_HEADER: &str = "x-c2pa-tenant";
+
+/// A tenant of the tenants file.
+#[derive(Deserialize)]
+struct TenantDefinition {
+    endpoint: String,
+    account: String,
+    certificate_profile: String,
+    /// `ps384` when unset.
+    algorithm: Option<SigningAlg>,
+    /// Manifest definition file, the default one when unset.
+    manifest_definition: Option<String>,
+    /// Function keys that sign for this tenant.
+    #[serde(default)]
+    keys: Vec<String>,
+    /// Values of the tenant claim of the tokens that sign for this tenant.
+    #[serde(default)]
+    claims: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TenantsFile {
+    /// The token claim matched against the `claims` of the tenants, e.g. `azp` or `tid`.
+    claim: Option<String>,
+    /// Whether callers that aren't bound to a tenant choose one with the `x-c2pa-tenant` header.
+    #[serde(default)]
+    tenant_header: bool,
+    tenants: HashMap<String, TenantDefinition>,
+}
+
+struct Tenant {
+    config: Arc<Config>,
+    keys: Vec<String>,
+    claims: Vec<String>,
+}
+
+/// The default configuration and the tenants signing with their own signer.
+pub struct Tenants {
+    default: Arc<Config>,
+    claim: Option<String>,
+    tenant_header: bool,
+    tenants: HashMap<String, Tenant>,
+}
+
+impl From<Arc<Config>> for Tenants {
+    fn from(default: Arc<Config>) -> Self {
+        Self {
+            default,
+            claim: None,
+            tenant_header: false,
+            tenants: HashMap::new(),
+        }
+    }
+}
+
+impl Tenants {
+    /// Read the tenants file and create the signer of every tenant with `credential`. The
+    /// settings apply to every tenant.
+    pub async fn load(
+        default: Arc<Config>,
+        path: &Path,
+        credential: Arc<dyn TokenCredential>,
+        settings: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let file: TenantsFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut tenants = HashMap::new();
+        for (name, tenant) in file.tenants {
+            let mut options = SigningOptions::new(
+                tenant.endpoint.parse()?,
+                tenant.account,
+                tenant.certificate_profile,
+                None,
+            );
+            if let Some(algorithm) = tenant.algorithm {
+                options = options.with_algorithm(algorithm);
+            }
+            let mut client_options = options.client_options();
+            client_options.telemetry = Some(metrics::telemetry());
+            let signer =
+                TrustedSigner::with_client_options(credential.clone(), options, client_options)
+                    .await?;
+            let manifest_definition = match &tenant.manifest_definition {
+                Some(path) => manifest_override(fs::read_to_string(path)?)
+                    .map_err(|x| anyhow::anyhow!("{path}: {x:?}"))?,
+                None => default.manifest_definition().to_string(),
+            };
+            log::info!("Signing for tenant {name}");
+            tenants.insert(
+                name,
+                Tenant {
+                    config: Arc::new(Config::new(
+                        Arc::new(signer),
+                        settings,
+                        manifest_definition,
+                    )?),
+                    keys: tenant.keys,
+                    claims: tenant.claims,
+                },
+            );
+        }
+        Ok(Self {
+            default,
+            claim: file.claim,
+            tenant_header: file.tenant_header,
+            tenants,
+        })
+    }
+
+    pub(crate) fn default(&self) -> &Arc<Config> {
+        &self.default
+    }
+
+    /// The function keys of the tenants, accepted by the authorizer like `FUNCTIONS_KEYS`.
+    pub(crate) fn keys(&self) -> Vec<String> {
+        self.tenants
+            .values()
+            .flat_map(|x| x.keys.iter().cloned())
+            .collect()
+    }
+
+    // The tenant the caller is bound to by its key, or by its token when no key authorized
+    // the request and the token was validated.
+    fn bound(
+        &self,
+        authorizer: Option<&Authorizer>,
+        key: Option<&str>,
+        authorization: Option<&str>,
+    ) -> Option<&str> {
+        let authorizer = authorizer?;
+        if let Some(key) = key
+            && authorizer.is_key(key)
+        {
+            return self
+                .tenants
+                .iter()
+                .find(|(_, x)| x.keys.iter().any(|y| same_key(y, key)))
+                .map(|(name, _)| name.as_str());
+        }
+        if !authorizer.validates_tokens() {
+            return None;
+        }
+        let value = claim(authorization?, self.claim.as_deref()?)?;
+        self.tenants
+            .iter()
+            .find(|(_, x)| x.claims.contains(&value))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub(crate) fn select(
+        &self,
+        authorizer: Option<&Authorizer>,
+        key: Option<&str>,
+        authorization: Option<&str>,
+        header: Option<&str>,
+    ) -> Result<Arc<Config>, ApiError> {
+        let bound = self.bound(authorizer, key, authorization);
+        let name = match (bound, header) {
+            (Some(bound), Some(header)) if bound != header => {
+                return Err(ApiError::Forbidden(format!(
+                    "the caller can't sign for tenant {header}"
+                )));
+            }
+            (None, Some(header)) if !self.tenant_header => {
+                return Err(ApiError::Forbidden(format!(
+                    "the caller isn't bound to tenant {header}"
+                )));
+            }
+            (Some(name), _) | (None, Some(name)) => name,
+            (None, None) => return Ok(self.default.clone()),
+        };
+        self.tenants
+            .get(name)
+            .map(|x| x.config.clone())
+            .ok_or_else(|| ApiError::BadRequest(format!("Unknown tenant {name}")))
+    }
+}
+
+/// The context and manifest definition of the tenant of the request, the manifest definition
+/// replaced by the `x-manifest-definition` header. A valid `traceparent` header is carried to
+/// Trusted Signing. Runs after `authorize`.
+pub(crate) fn configuration(
+    authorizer: Option<Arc<Authorizer>>,
+    tenants: Arc<Tenants>,
+) -> impl Filter<Extract = (Arc<Context>, Arc<String>), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-functions-key")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>(TENANT_HEADER))
+        .and(warp::header::optional::<String>("x-manifest-definition"))
+        .and(warp::header::optional::<String>("traceparent"))
+        .and_then(
+            move |key: Option<String>,
+                  mut query: HashMap<String, String>,
+                  authorization: Option<String>,
+                  tenant: Option<String>,
+                  manifest: Option<String>,
+                  traceparent: Option<String>| {
+                let authorizer = authorizer.clone();
+                let tenants = tenants.clone();
+                async move {
+                    let key = key.or_else(|| query.remove("code"));
+                    let config = tenants
+                        .select(
+                            authorizer.as_deref(),
+                            key.as_deref(),
+                            authorization.as_deref(),
+                            tenant.as_deref(),
+                        )
+                        .map_err(warp::reject::custom)?;
+                    let manifest_definition = match manifest {
+                        Some(text) => {
+                            Arc::new(manifest_override(text).map_err(warp::reject::custom)?)
+                        }
+                        None => config.manifest_definition(),
+                    };
+                    let context = match traceparent.as_deref().and_then(TraceParent::parse) {
+                        Some(trace) => config
+                            .traced_context(trace)
+                            .map_err(|x| warp::reject::custom(ApiError::C2pa(x)))?,
+                        None => config.context(),
+                    };
+                    Ok::<_, Rejection>((context, manifest_definition))
+                }
+            },
+        )
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+    use async_trait::async_trait;
+    use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+    use c2pa::AsyncSigner;
+    use serde_json::Value;
+
+    struct TestSigner;
+
+    #[async_trait]
+    impl AsyncSigner for TestSigner {
+        async fn sign(&self, _data: Vec<u8>) -> c2pa::Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn alg(&self) -> SigningAlg {
+            SigningAlg::Ps384
+        }
+
+        fn certs(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+            Ok(Vec::new())
+        }
+
+        fn reserve_size(&self) -> usize {
+            0
+        }
+    }
+
+    fn config(title: &str) -> Arc<Config> {
+        let definition = format!(r#"{{"title":"{title}"}}"#);
+        Arc::new(Config::new(Arc::new(TestSigner), None, definition).unwrap())
+    }
+
+    fn tenants(tenant_header: bool) -> Tenants {
+        let tenant = |title, key: &str, claim: &str| Tenant {
+            config: config(title),
+            keys: vec![key.to_owned()],
+            claims: vec![claim.to_owned()],
+        };
+        Tenants {
+            default: config("default"),
+            claim: Some("azp".to_owned()),
+            tenant_header,
+            tenants: HashMap::from([
+                (
+                    "contoso".to_owned(),
+                    tenant("contoso", "contoso-key", "contoso-app"),
+                ),
+                (
+                    "fabrikam".to_owned(),
+                    tenant("fabrikam", "fabrikam-key", "fabrikam-app"),
+                ),
+            ]),
+        }
+    }
+
+    fn authorizer(tenants: &Tenants) -> Authorizer {
+        let options = Options {
+            function_keys: Some("shared-key".to_owned()),
+            auth_issuer: Some("https://login.microsoftonline.com/tenant/v2.0".to_owned()),
+            auth_audience: Some("api://c2pa".to_owned()),
+            ..Options::default()
+        };
+        Authorizer::new(&options, tenants.keys()).unwrap().unwrap()
+    }
+
+    // The title of the manifest definition of the selected tenant.
+    fn selected(
+        tenants: &Tenants,
+        key: Option<&str>,
+        authorization: Option<&str>,
+        header: Option<&str>,
+    ) -> Result<String, ApiError> {
+        let authorizer = authorizer(tenants);
+        let config = tenants.select(Some(&authorizer), key, authorization, header)?;
+        let definition: Value = serde_json::from_str(&config.manifest_definition()).unwrap();
+        Ok(definition["title"].as_str().unwrap().to_owned())
+    }
+
+    fn bearer(azp: &str) -> String {
+        let claims = BASE64_URL_SAFE_NO_PAD.encode(format!(r#"{{"azp":"{azp}"}}"#));
+        format!("Bearer e30.{claims}.")
+    }
+
+    #[test]
+    fn test_bound_by_key_or_claim() {
+        let tenants = tenants(false);
+        assert_eq!(
+            selected(&tenants, Some("contoso-key"), None, None).unwrap(),
+            "contoso"
+        );
+        let token = bearer("fabrikam-app");
+        assert_eq!(
+            selected(&tenants, None, Some(&token), None).unwrap(),
+            "fabrikam"
+        );
+        assert_eq!(
+            selected(&tenants, Some("contoso-key"), None, Some("contoso")).unwrap(),
+            "contoso"
+        );
+        assert!(matches!(
+            selected(&tenants, Some("contoso-key"), None, Some("fabrikam")),
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_unbound_callers() {
+        let tenants = tenants(false);
+        assert_eq!(
+            selected(&tenants, Some("shared-key"), None, None).unwrap(),
+            "default"
+        );
+        assert!(matches!(
+            selected(&tenants, Some("shared-key"), None, Some("contoso")),
+            Err(ApiError::Forbidden(_))
+        ));
+
+        let tenants = self::tenants(true);
+        assert_eq!(
+            selected(&tenants, Some("shared-key"), None, Some("contoso")).unwrap(),
+            "contoso"
+        );
+        assert!(matches!(
+            selected(&tenants, Some("shared-key"), None, Some("unknown")),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+//! TLS termination, for running the handler outside the Functions host (containers, VMs)
+//! without a proxy in front of it.
+use hyper::{Request, body::Incoming, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+use std::{future::Future, io, net::SocketAddr, path::Path, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+use tower_service::Service;
+use warp::{Filter, Rejection, Reply};
+
+use crate::logging::Peer;
+
+/// The certificate and key the server terminates TLS with.
+pub struct Tls {
+    acceptor: TlsAcceptor,
+}
+
+impl Tls {
+    /// Load a PEM certificate chain, leaf first, and its PEM private key.
+    pub fn load(certificate: &Path, key: &Path) -> io::Result<Self> {
+        let certificates = CertificateDer::pem_file_iter(certificate)
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(io::Error::other)?;
+        let key = PrivateKeyDer::from_pem_file(key).map_err(io::Error::other)?;
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .map_err(io::Error::other)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+}
+
+/// Serve the routes over TLS until `shutdown` completes, then wait for the open connections.
+pub(crate) async fn run<F, R>(
+    routes: F,
+    address: SocketAddr,
+    tls: Tls,
+    shutdown: impl Future<Output = ()>,
+) where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Can't listen on {address}: {err}");
+            return;
+        }
+    };
+    log::info!("Listening with TLS on {address}");
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    log::warn!("Can't accept a connection: {err}");
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let acceptor = tls.acceptor.clone();
+        let service = warp::service(routes.clone());
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    log::debug!("TLS handshake with {peer} failed: {err}");
+                    return;
+                }
+            };
+            // warp doesn't know the peer of connections it didn't accept, so it is passed on
+            // as an extension.
+            let service = service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(Peer(peer));
+                service.clone().call(request)
+            });
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            if let Err(err) = watcher.watch(connection.into_owned()).await {
+                log::debug!("Connection with {peer} failed: {err}");
+            }
+        });
+    }
+    graceful.shutdown().await;
+}
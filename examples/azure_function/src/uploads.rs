@@ -0,0 +1,345 @@
+//! Resumable uploads, for large files from unreliable clients: `POST /api/uploads` starts an
+//! upload, `PUT /api/uploads/{id}` writes the block of its `content-range`,
+//! `GET /api/uploads/{id}` returns how much was received so a client can resume after a
+//! failure, and `POST /api/uploads/{id}/sign` signs the complete file as a job.
+use azure_core::http::Url;
+use c2pa::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tempfile::{NamedTempFile, TempDir};
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+use warp::{
+    Buf, Filter, Rejection, Reply, Stream,
+    http::StatusCode,
+    reply::{json, with_header, with_status},
+};
+
+use crate::{
+    ApiError,
+    auth::{Authorizer, authorize},
+    copy,
+    jobs::{Jobs, callback},
+    media_type, permit,
+    ratelimit::{RateLimiter, rate_limit},
+    tenants::{Tenants, configuration},
+};
+
+// Uploads that haven't been written to for this long are removed.
+const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The body of `POST /api/uploads`, both fields optional.
+#[derive(Deserialize)]
+struct NewUpload {
+    /// The format of the file, taken from its content when unset.
+    content_type: Option<String>,
+    /// The size of the file, which can also be given by the `content-range` of the blocks.
+    length: Option<u64>,
+}
+
+struct Upload {
+    file: NamedTempFile,
+    /// The bytes from the start of the file that were written.
+    received: u64,
+    length: Option<u64>,
+    content_type: Option<String>,
+    updated: Instant,
+}
+
+/// The reply to every upload request.
+#[derive(Serialize)]
+struct Progress<'a> {
+    id: &'a str,
+    /// Where the next block starts.
+    offset: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<u64>,
+}
+
+// An upload is `None` once it has been signed, so blocks still being written can't change it.
+type Entry = Arc<AsyncMutex<Option<Upload>>>;
+
+/// Uploads of this instance, in a temporary directory. Like jobs, they are only known to the
+/// instance that started them.
+pub(crate) struct Uploads {
+    uploads: Mutex<HashMap<String, Entry>>,
+    dir: TempDir,
+    max_upload_size: u64,
+}
+
+// `bytes {first}-{last}/{length}`, the length `*` when the client doesn't know it yet.
+fn content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, length) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (first.parse().ok()?, last.parse::<u64>().ok()?);
+    let length = match length {
+        "*" => None,
+        length => Some(length.parse().ok()?),
+    };
+    (first <= last && length.is_none_or(|x| last < x)).then_some((first, last, length))
+}
+
+impl Uploads {
+    pub(crate) fn new(max_upload_size: u64) -> io::Result<Self> {
+        Ok(Self {
+            uploads: Mutex::default(),
+            dir: TempDir::new()?,
+            max_upload_size,
+        })
+    }
+
+    fn get(&self, id: &str) -> Result<Entry, Rejection> {
+        let uploads = self.uploads.lock().unwrap();
+        uploads.get(id).cloned().ok_or_else(warp::reject::not_found)
+    }
+
+    // Forget uploads left for more than RETENTION, except those being written.
+    fn expire(&self) {
+        self.uploads.lock().unwrap().retain(|_, entry| {
+            entry.try_lock().map_or(true, |x| {
+                x.as_ref().is_some_and(|x| x.updated.elapsed() <= RETENTION)
+            })
+        });
+    }
+
+    fn insert(&self, upload: NewUpload) -> Result<String, ApiError> {
+        if upload.length.is_some_and(|x| x > self.max_upload_size) {
+            return Err(ApiError::TooLarge(self.max_upload_size));
+        }
+        self.expire();
+        let file = NamedTempFile::new_in(self.dir.path()).map_err(ApiError::Io)?;
+        let id = Uuid::new_v4().to_string();
+        self.uploads.lock().unwrap().insert(
+            id.clone(),
+            Arc::new(AsyncMutex::new(Some(Upload {
+                file,
+                received: 0,
+                length: upload.length,
+                content_type: upload.content_type,
+                updated: Instant::now(),
+            }))),
+        );
+        Ok(id)
+    }
+}
+
+impl Upload {
+    // Write the block at `first`, keeping what arrived of it when the body is cut short.
+    async fn write(
+        &mut self,
+        range: &str,
+        limit: u64,
+        stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+    ) -> Result<(), ApiError> {
+        let (first, last, length) = content_range(range).ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "Invalid content-range {range}, expected bytes first-last/length"
+            ))
+        })?;
+        if let (Some(length), Some(expected)) = (length, self.length)
+            && length != expected
+        {
+            return Err(ApiError::BadRequest(format!(
+                "The upload has a length of {expected}, not {length}"
+            )));
+        }
+        if first > self.received {
+            return Err(ApiError::Conflict(format!(
+                "The upload has {} bytes, the block can't start at {first}",
+                self.received
+            )));
+        }
+        if last >= limit {
+            return Err(ApiError::TooLarge(limit));
+        }
+        self.length = self.length.or(length);
+        if self.length.is_some_and(|x| last >= x) {
+            return Err(ApiError::BadRequest(format!(
+                "The block ends after the {} bytes of the upload",
+                self.length.unwrap_or_default()
+            )));
+        }
+        self.updated = Instant::now();
+
+        let file = self.file.as_file_mut();
+        file.seek(SeekFrom::Start(first)).map_err(ApiError::Io)?;
+        let result = copy(&*file, stream, last - first + 1).await;
+        let end = file.stream_position().map_err(ApiError::Io)?;
+        self.received = self.received.max(end);
+        result?;
+        if end != last + 1 {
+            return Err(ApiError::BadRequest(format!(
+                "The block ends at {end}, not at {} as its content-range says",
+                last + 1
+            )));
+        }
+        Ok(())
+    }
+
+    // Complete when all the bytes were received, and any of them when the length is unknown.
+    fn complete(&self) -> Result<(), ApiError> {
+        match self.length {
+            Some(length) if self.received != length => Err(ApiError::Conflict(format!(
+                "Only {} of the {length} bytes were uploaded",
+                self.received
+            ))),
+            None if self.received == 0 => {
+                Err(ApiError::Conflict("Nothing was uploaded".to_owned()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn create(uploads: Arc<Uploads>, upload: NewUpload) -> Result<impl Reply, Rejection> {
+    let length = upload.length;
+    let id = uploads.insert(upload).map_err(warp::reject::custom)?;
+    let reply = json(&Progress {
+        id: &id,
+        offset: 0,
+        length,
+    });
+    Ok(with_status(
+        with_header(reply, "location", format!("/api/uploads/{id}")),
+        StatusCode::CREATED,
+    ))
+}
+
+async fn status(uploads: Arc<Uploads>, id: String) -> Result<impl Reply, Rejection> {
+    let entry = uploads.get(&id)?;
+    let upload = entry.lock().await;
+    let upload = upload.as_ref().ok_or_else(warp::reject::not_found)?;
+    Ok(json(&Progress {
+        id: &id,
+        offset: upload.received,
+        length: upload.length,
+    }))
+}
+
+async fn write(
+    uploads: Arc<Uploads>,
+    id: String,
+    range: String,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin + Send,
+) -> Result<impl Reply, Rejection> {
+    let entry = uploads.get(&id)?;
+    let mut upload = entry.lock().await;
+    let upload = upload.as_mut().ok_or_else(warp::reject::not_found)?;
+    upload
+        .write(&range, uploads.max_upload_size, stream)
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(json(&Progress {
+        id: &id,
+        offset: upload.received,
+        length: upload.length,
+    }))
+}
+
+async fn sign(
+    uploads: Arc<Uploads>,
+    jobs: Arc<Jobs>,
+    id: String,
+    permit: OwnedSemaphorePermit,
+    context: Arc<Context>,
+    manifest_definition: Arc<String>,
+    callback: Option<Url>,
+) -> Result<impl Reply, Rejection> {
+    let entry = uploads.get(&id)?;
+    let mut slot = entry.lock().await;
+    let upload = slot.as_mut().ok_or_else(warp::reject::not_found)?;
+    let content_type = async {
+        upload.complete()?;
+        upload.file.rewind().map_err(ApiError::Io)?;
+        media_type(upload.content_type.as_deref(), upload.file.as_file_mut())
+    }
+    .await
+    .map_err(warp::reject::custom)?;
+    let Some(upload) = slot.take() else {
+        return Err(warp::reject::not_found());
+    };
+    uploads.uploads.lock().unwrap().remove(&id);
+    Ok(jobs.start(
+        permit,
+        context,
+        manifest_definition,
+        upload.file,
+        content_type,
+        callback,
+    ))
+}
+
+/// `POST /api/uploads`, `GET` and `PUT /api/uploads/{id}` and `POST /api/uploads/{id}/sign`,
+/// all requiring a token when signing does. Only signing is rate limited.
+pub(crate) fn routes(
+    jobs: Arc<Jobs>,
+    tenants: Arc<Tenants>,
+    max_upload_size: u64,
+    authorizer: Option<Arc<Authorizer>>,
+    limiter: Option<Arc<RateLimiter>>,
+    semaphore: Arc<Semaphore>,
+) -> io::Result<impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone> {
+    let uploads = Arc::new(Uploads::new(max_upload_size)?);
+    let with_uploads = warp::any().map(move || uploads.clone());
+
+    let create = warp::post()
+        .and(with_uploads.clone())
+        .and(warp::path!("api" / "uploads"))
+        .and(authorize(authorizer.clone()))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and_then(create);
+
+    let status = warp::get()
+        .and(with_uploads.clone())
+        .and(warp::path!("api" / "uploads" / String))
+        .and(authorize(authorizer.clone()))
+        .and_then(status);
+
+    let write = warp::put()
+        .and(with_uploads.clone())
+        .and(warp::path!("api" / "uploads" / String))
+        .and(authorize(authorizer.clone()))
+        .and(warp::header::<String>("content-range"))
+        .and(warp::filters::body::stream())
+        .and_then(write);
+
+    let with_jobs = {
+        let jobs = jobs.clone();
+        warp::any().map(move || jobs.clone())
+    };
+    let sign = warp::post()
+        .and(with_uploads)
+        .and(with_jobs)
+        .and(warp::path!("api" / "uploads" / String / "sign"))
+        .and(authorize(authorizer.clone()))
+        .and(rate_limit(limiter))
+        .and(permit(semaphore))
+        .and(configuration(authorizer, tenants))
+        .and(callback(jobs))
+        .and_then(sign);
+
+    Ok(create.or(status).or(write).or(sign))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(content_range("bytes 0-99/200"), Some((0, 99, Some(200))));
+        assert_eq!(content_range("bytes 100-199/*"), Some((100, 199, None)));
+        assert_eq!(content_range(" bytes 5-5/6 "), Some((5, 5, Some(6))));
+        assert_eq!(content_range("bytes 100-199/150"), None);
+        assert_eq!(content_range("bytes 10-5/20"), None);
+        assert_eq!(content_range("bytes 0-9"), None);
+        assert_eq!(content_range("items 0-9/10"), None);
+        assert_eq!(content_range("bytes a-9/10"), None);
+    }
+}
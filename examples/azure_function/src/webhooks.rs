@@ -0,0 +1,182 @@
+//! Completion callbacks of jobs: the final status of a job created with an `x-callback-url`
+//! header is POSTed to that URL, signed with `WEBHOOK_SECRET`, so clients don't have to poll.
+use azure_core::http::{
+    ClientOptions, Context as HttpContext, Method, Pipeline, Request, Url, headers::CONTENT_TYPE,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt::Write,
+    io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{ApiError, Options, allowed_host};
+
+const TIMESTAMP: &str = "x-c2pa-timestamp";
+const SIGNATURE: &str = "x-c2pa-signature";
+
+/// Sends the callbacks to the allowed hosts, so callers can't make the function POST to
+/// internal services.
+pub(crate) struct Webhooks {
+    pipeline: Pipeline,
+    secret: Vec<u8>,
+    allowed_hosts: Vec<String>,
+}
+
+impl Webhooks {
+    /// The webhooks of the options, none when `WEBHOOK_SECRET` is unset. `WEBHOOK_ALLOWED_HOSTS`
+    /// is required with it.
+    pub(crate) fn new(options: &Options) -> io::Result<Option<Self>> {
+        let Some(secret) = options.webhook_secret.as_deref().filter(|x| !x.is_empty()) else {
+            return Ok(None);
+        };
+        let webhooks = Self {
+            pipeline: Pipeline::new(
+                option_env!("CARGO_PKG_NAME"),
+                option_env!("CARGO_PKG_VERSION"),
+                ClientOptions::default(),
+                vec![],
+                vec![],
+                None,
+            ),
+            secret: secret.as_bytes().to_vec(),
+            allowed_hosts: options
+                .webhook_allowed_hosts
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        };
+        if webhooks.allowed_hosts.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "WEBHOOK_ALLOWED_HOSTS is required with WEBHOOK_SECRET",
+            ));
+        }
+        Ok(Some(webhooks))
+    }
+
+    /// Check the callback URL when the job is created, so a bad one is a 400 rather than a
+    /// callback that never comes. Addresses are rejected, callbacks go to allowed domains.
+    pub(crate) fn check(&self, url: &str) -> Result<Url, ApiError> {
+        let url = Url::parse(url)
+            .map_err(|x| ApiError::BadRequest(format!("Invalid callback URL: {x}")))?;
+        // Only domains have a `domain()`, IP addresses don't.
+        let host = url.host_str().unwrap_or_default();
+        if url.scheme() == "https"
+            && url
+                .domain()
+                .is_some_and(|x| allowed_host(x, &self.allowed_hosts))
+        {
+            return Ok(url);
+        }
+        Err(ApiError::BadRequest(format!(
+            "The callback URL of {host} is not an https URL of an allowed host"
+        )))
+    }
+
+    // `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}`, so receivers can reject
+    // callbacks that were forged or replayed.
+    fn signature(&self, timestamp: u64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .fold("sha256=".to_owned(), |mut hex, x| {
+                _ = write!(hex, "{x:02x}");
+                hex
+            })
+    }
+
+    /// POST the JSON body to the callback URL. Failures are logged, the job result stays
+    /// available to poll.
+    pub(crate) async fn notify(&self, url: &Url, body: Vec<u8>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut request = Request::new(url.clone(), Method::Post);
+        request.insert_header(CONTENT_TYPE, "application/json");
+        request.insert_header(TIMESTAMP, timestamp.to_string());
+        request.insert_header(SIGNATURE, self.signature(timestamp, &body));
+        request.set_body(body);
+        let host = url.host_str().unwrap_or_default();
+        match self
+            .pipeline
+            .send(&HttpContext::new(), &mut request, None)
+            .await
+        {
+            Ok(response) if response.status().is_success() => {
+                log::info!("Sent the job callback to {host}");
+            }
+            Ok(response) => {
+                log::warn!("The job callback to {host} returned {}", response.status());
+            }
+            Err(err) => log::warn!("Can't send the job callback to {host}: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhooks() -> Webhooks {
+        let options = Options {
+            webhook_secret: Some("secret".to_owned()),
+            webhook_allowed_hosts: Some("hooks.contoso.com".to_owned()),
+            ..Options::default()
+        };
+        Webhooks::new(&options).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_allowed_hosts_are_required() {
+        let options = Options {
+            webhook_secret: Some("secret".to_owned()),
+            ..Options::default()
+        };
+        assert!(Webhooks::new(&options).is_err());
+        assert!(Webhooks::new(&Options::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        let webhooks = webhooks();
+        assert!(webhooks.check("https://hooks.contoso.com/done").is_ok());
+        assert!(webhooks.check("https://eu.hooks.contoso.com/done").is_ok());
+        assert!(webhooks.check("http://hooks.contoso.com/done").is_err());
+        assert!(
+            webhooks
+                .check("https://evilhooks.contoso.com/done")
+                .is_err()
+        );
+        assert!(webhooks.check("https://169.254.169.254/metadata").is_err());
+        assert!(webhooks.check("https://[::1]/done").is_err());
+    }
+
+    #[test]
+    fn test_signature() {
+        let body = br#"{"id":"1","status":"succeeded"}"#;
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"1700000000.");
+        mac.update(body);
+        let expected: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|x| format!("{x:02x}"))
+            .collect();
+
+        let signature = webhooks().signature(1_700_000_000, body);
+        assert_eq!(signature, format!("sha256={expected}"));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_ne!(signature, webhooks().signature(1_700_000_001, body));
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context as _, Result};
+use azure_core::time::{OffsetDateTime, to_rfc3339};
+use c2pa::AsyncSigner;
+use c2pa_azure::CertificateChain;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// A JSON line per signed file appended to `--audit-log`, as evidence of what was signed.
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    input: &'a Path,
+    input_sha256: &'a str,
+    output: &'a Path,
+    output_sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest_label: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    certificate_serial: Option<&'a str>,
+}
+
+pub(crate) struct AuditLog {
+    file: Mutex<File>,
+    // Serial number of the signing certificate, the same for every file of a run.
+    certificate_serial: Option<String>,
+}
+
+impl AuditLog {
+    pub(crate) fn open(path: &Path, signer: &dyn AsyncSigner) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| path.display().to_string())?;
+        let certificate_serial = signer
+            .certs()
+            .ok()
+            .and_then(|certs| CertificateChain::from_der(certs).ok())
+            .and_then(|chain| chain.info().ok())
+            .and_then(|info| Some(info.leaf()?.serial_number.clone()));
+        Ok(Self {
+            file: Mutex::new(file),
+            certificate_serial,
+        })
+    }
+
+    /// Append the record of a signed file. `output` is the signed asset, or the sidecar when
+    /// the manifest was not embedded.
+    pub(crate) fn record(
+        &self,
+        input: &Path,
+        input_sha256: &str,
+        output: &Path,
+        manifest_label: Option<&str>,
+    ) -> Result<()> {
+        let record = Record {
+            timestamp: to_rfc3339(&OffsetDateTime::now_utc()),
+            input,
+            input_sha256,
+            output,
+            output_sha256: sha256(output)?,
+            manifest_label,
+            certificate_serial: self.certificate_serial.as_deref(),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        // One write per line, so records of concurrent jobs don't interleave.
+        let mut file = self.file.lock().expect("audit log lock");
+        file.write_all(&line)?;
+        Ok(file.flush()?)
+    }
+}
+
+/// The hex encoded SHA-256 of a file.
+pub(crate) fn sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| path.display().to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect())
+}
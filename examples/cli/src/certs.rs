@@ -0,0 +1,74 @@
+use anyhow::Result;
+use azure_core::time::{OffsetDateTime, to_rfc3339};
+use c2pa_azure::TrustedSigner;
+use clap::Args;
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use crate::GlobalArgs;
+
+#[derive(Args, Debug)]
+pub(crate) struct CertsArgs {
+    /// Write the chain as PEM to this file, `-` for stdout.
+    #[arg(long, value_name = "PATH")]
+    pem: Option<PathBuf>,
+
+    /// Include the root CA, e.g. to build a trust list.
+    #[arg(long)]
+    include_root: bool,
+}
+
+// Names of the extended key usages relevant to C2PA signing.
+fn key_usage(oid: &str) -> &str {
+    match oid {
+        "1.3.6.1.5.5.7.3.3" => "codeSigning",
+        "1.3.6.1.5.5.7.3.4" => "emailProtection",
+        "1.3.6.1.5.5.7.3.8" => "timeStamping",
+        "1.3.6.1.5.5.7.3.9" => "OCSPSigning",
+        "1.3.6.1.5.5.7.3.36" => "documentSigning",
+        oid => oid,
+    }
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: CertsArgs) -> Result<()> {
+    let options = global
+        .signing_options()?
+        .with_root_certificate(args.include_root);
+    let signer = TrustedSigner::new(global.credential()?, options).await?;
+
+    if let Some(path) = &args.pem {
+        let pem = signer.certificate_chain_pem()?;
+        if path.as_os_str() == "-" {
+            print!("{pem}");
+            return Ok(());
+        }
+        fs::write(path, pem)?;
+        log::info!("Wrote the certificate chain to {}", path.display());
+    }
+
+    let now = SystemTime::now();
+    for certificate in signer.certificate_chain()?.certificates {
+        println!("{}", certificate.subject);
+        println!("  issuer:     {}", certificate.issuer);
+        println!("  serial:     {}", certificate.serial_number);
+        println!("  key:        {}", certificate.key_algorithm);
+        println!(
+            "  valid:      {} to {}{}",
+            to_rfc3339(&OffsetDateTime::from(certificate.not_before)),
+            to_rfc3339(&OffsetDateTime::from(certificate.not_after)),
+            if certificate.is_valid_at(now) {
+                ""
+            } else {
+                " (not valid now)"
+            }
+        );
+        if !certificate.extended_key_usages.is_empty() {
+            let usages: Vec<_> = certificate
+                .extended_key_usages
+                .iter()
+                .map(|x| key_usage(x))
+                .collect();
+            println!("  key usages: {}", usages.join(", "));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use clap::{Args, Command, CommandFactory};
+use clap_complete::Shell;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::Cli;
+
+const BIN_NAME: &str = "c2pa-acs";
+
+#[derive(Args, Debug)]
+pub(crate) struct CompletionsArgs {
+    /// Print the completion script for this shell.
+    shell: Shell,
+}
+
+#[derive(Args, Debug)]
+pub(crate) struct ManArgs {
+    /// Directory to write c2pa-acs.1 and a page per subcommand to.
+    #[arg(short, long, default_value = ".")]
+    out: PathBuf,
+}
+
+pub(crate) fn completions(args: CompletionsArgs) -> Result<()> {
+    clap_complete::generate(args.shell, &mut Cli::command(), BIN_NAME, &mut io::stdout());
+    Ok(())
+}
+
+fn render(command: Command, out: &Path) -> Result<()> {
+    let path = out.join(format!("{}.1", command.get_name()));
+    let mut page = Vec::new();
+    clap_mangen::Man::new(command).render(&mut page)?;
+    fs::write(&path, page)?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+pub(crate) fn man(args: ManArgs) -> Result<()> {
+    fs::create_dir_all(&args.out)?;
+    let mut command = Cli::command().name(BIN_NAME);
+    command.build();
+    for subcommand in command.get_subcommands().filter(|x| !x.is_hide_set()) {
+        let name = format!("{BIN_NAME}-{}", subcommand.get_name());
+        render(subcommand.clone().name(name), &args.out)?;
+    }
+    render(command, &args.out)
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const FILE_NAME: &str = "c2pa-acs.toml";
+
+/// Defaults read from `c2pa-acs.toml`, command line flags take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) endpoint: Option<String>,
+    pub(crate) account: Option<String>,
+    pub(crate) certificate_profile: Option<String>,
+    pub(crate) time_authority_url: Option<String>,
+    /// Relative paths are resolved against the directory of the configuration file.
+    pub(crate) manifest_definition: Option<PathBuf>,
+    pub(crate) settings: Option<PathBuf>,
+}
+
+fn home() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+// The explicit path, or c2pa-acs.toml in the current directory, then the home directory.
+fn locate(path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = path {
+        return Some(path.to_owned());
+    }
+    [Some(PathBuf::from(".")), home()]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+impl Config {
+    pub(crate) fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = locate(path) else {
+            return Ok(Self::default());
+        };
+        log::debug!("Reading configuration from {}", path.display());
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: Self =
+            toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for file in [&mut config.manifest_definition, &mut config.settings]
+            .into_iter()
+            .flatten()
+        {
+            *file = dir.join(&*file);
+        }
+        Ok(config)
+    }
+}
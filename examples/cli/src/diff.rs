@@ -0,0 +1,62 @@
+use anyhow::Result;
+use c2pa::Reader;
+use c2pa_azure::{ManifestDiff, VerifySettings, verify_file_with_settings};
+use clap::Args;
+use std::{fs::File, path::Path, path::PathBuf};
+
+use crate::{GlobalArgs, format_of};
+
+#[derive(Args, Debug)]
+pub(crate) struct DiffArgs {
+    file_a: PathBuf,
+
+    file_b: PathBuf,
+
+    /// Print the differences as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+async fn read(path: &Path, settings: &VerifySettings) -> Result<Reader> {
+    let file = File::open(path)?;
+    Ok(verify_file_with_settings(format_of(path, None)?, file, settings).await?)
+}
+
+fn print(diff: &ManifestDiff) {
+    for change in &diff.signer {
+        let value = |x: &Option<String>| x.clone().unwrap_or_else(|| "(none)".to_owned());
+        println!(
+            "~ signer {}: {} -> {}",
+            change.field,
+            value(&change.before),
+            value(&change.after)
+        );
+    }
+    for label in &diff.assertions_removed {
+        println!("- assertion {label}");
+    }
+    for label in &diff.assertions_added {
+        println!("+ assertion {label}");
+    }
+    for ingredient in &diff.ingredients_removed {
+        println!("- ingredient {ingredient}");
+    }
+    for ingredient in &diff.ingredients_added {
+        println!("+ ingredient {ingredient}");
+    }
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: DiffArgs) -> Result<()> {
+    let settings = VerifySettings::from_toml(&global.settings()?)?;
+    let a = read(&args.file_a, &settings).await?;
+    let b = read(&args.file_b, &settings).await?;
+    let diff = ManifestDiff::between(&a, &b)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else if diff.is_empty() {
+        println!("The manifests have the same signer, assertions and ingredients.");
+    } else {
+        print(&diff);
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use anyhow::{Context as _, Result, bail};
+use azure_core::{
+    http::Url,
+    time::{OffsetDateTime, to_rfc3339},
+};
+use c2pa::Context;
+use c2pa_azure::{CertificateChain, VerifySettings};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+
+use crate::{
+    Backend, GlobalArgs,
+    exit::{Exit, Failed},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prints the outcome of each check, with what to do about failures.
+#[derive(Default)]
+struct Checks {
+    failed: usize,
+}
+
+impl Checks {
+    fn report(&mut self, name: &str, result: Result<String>, remedy: &str) {
+        match result {
+            Ok(detail) => println!("ok    {name}: {detail}"),
+            Err(err) => {
+                self.failed += 1;
+                println!("FAIL  {name}: {err:#}");
+                println!("      {remedy}");
+            }
+        }
+    }
+}
+
+// Connect to the host of a URL, without sending a request.
+async fn connect(url: &Url) -> Result<String> {
+    let host = url.host_str().context("the URL has no host")?;
+    let port = url.port_or_known_default().context("the URL has no port")?;
+    tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .context("timed out")??;
+    Ok(format!("{host}:{port} is reachable"))
+}
+
+fn settings(global: &GlobalArgs) -> Result<String> {
+    let settings = global.settings()?;
+    VerifySettings::from_toml(&settings)?;
+    Context::new().with_settings(settings.as_str())?;
+    Ok(match &global.settings {
+        Some(path) => format!("{} parsed", path.display()),
+        None => "using the built-in Trusted Signing trust anchors".to_owned(),
+    })
+}
+
+// The service signing requests are sent to and the token scope for it.
+fn service(global: &GlobalArgs) -> Result<(Url, String)> {
+    Ok(match global.backend {
+        Backend::TrustedSigning => {
+            let scope = global.signing_options()?.client_options().scope;
+            (
+                global.endpoint.clone().context("--endpoint is required")?,
+                scope,
+            )
+        }
+        Backend::Keyvault => {
+            let options = global.key_vault_options()?;
+            (options.vault_url.clone(), options.scope().to_owned())
+        }
+    })
+}
+
+async fn credential(global: &GlobalArgs, scope: &str) -> Result<String> {
+    let token = global.credential()?.get_token(&[scope], None).await?;
+    Ok(format!(
+        "got a token for {scope}, expiring {}",
+        to_rfc3339(&token.expires_on)
+    ))
+}
+
+async fn certificate(global: &GlobalArgs) -> Result<String> {
+    let signer = global.signer().await?;
+    let chain = CertificateChain::from_der(signer.certs()?)?.info()?;
+    let leaf = chain.leaf().context("the certificate chain is empty")?;
+    if !leaf.is_valid_at(SystemTime::now()) {
+        bail!(
+            "{} is not valid now, it is valid from {} to {}",
+            leaf.subject,
+            to_rfc3339(&OffsetDateTime::from(leaf.not_before)),
+            to_rfc3339(&OffsetDateTime::from(leaf.not_after))
+        );
+    }
+    Ok(format!(
+        "{} ({}), valid until {}",
+        leaf.subject,
+        signer.alg(),
+        to_rfc3339(&OffsetDateTime::from(leaf.not_after))
+    ))
+}
+
+pub(crate) async fn run(global: &GlobalArgs) -> Result<()> {
+    let mut checks = Checks::default();
+    checks.report(
+        "trust settings",
+        settings(global),
+        "Fix the TOML file given with --settings or the settings entry of c2pa-acs.toml.",
+    );
+
+    match service(global) {
+        Ok((url, scope)) => {
+            checks.report(
+                "credentials",
+                credential(global, &scope).await,
+                "Sign in with `az login`, or choose a credential with --credential and check \
+                 AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET.",
+            );
+            checks.report(
+                "connectivity",
+                connect(&url).await,
+                "Check the endpoint or vault URL, proxy and firewall rules for outbound HTTPS.",
+            );
+        }
+        Err(err) => checks.report(
+            "configuration",
+            Err(err),
+            "Set the account flags or run `c2pa-acs init` to create c2pa-acs.toml.",
+        ),
+    }
+
+    checks.report(
+        "certificate",
+        certificate(global).await,
+        "Check the account and certificate profile (or key and certificate name), and that the \
+         identity has the Trusted Signing Certificate Profile Signer (or Key Vault Crypto User) role.",
+    );
+
+    match global.time_authority_url()? {
+        Some(url) => checks.report(
+            "timestamp authority",
+            connect(&url).await,
+            "Use --timestamp-url for a reachable RFC3161 service, or --no-timestamp.",
+        ),
+        None => println!("skip  timestamp authority: --no-timestamp"),
+    }
+
+    if checks.failed > 0 {
+        return Err(Failed {
+            exit: Exit::Failure,
+            message: format!("{} checks failed", checks.failed),
+        }
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use azure_core::{error::ErrorKind, http::StatusCode};
+use c2pa_azure::{CircuitOpenError, MediaTypeError, PolicyError};
+use std::{error::Error, fmt, process::ExitCode};
+
+/// Process exit codes, so scripts and CI can tell failures apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Exit {
+    Failure = 1,
+    /// The credential could not get a token or was not authorized.
+    Auth = 2,
+    /// Trusted Signing failed or could not be reached.
+    Signing = 3,
+    /// The file is not in a format that can be signed, or does not match its extension.
+    Unsupported = 4,
+    /// The manifest did not pass verification.
+    Validation = 5,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+impl Exit {
+    /// Classify an error by the first cause that is recognized.
+    pub(crate) fn of(err: &anyhow::Error) -> Self {
+        err.chain().find_map(classify).unwrap_or(Exit::Failure)
+    }
+
+    /// The exit code for several failures, the shared one or a generic failure.
+    pub(crate) fn combine(self, other: Self) -> Self {
+        if self == other { self } else { Exit::Failure }
+    }
+}
+
+fn classify(err: &(dyn Error + 'static)) -> Option<Exit> {
+    if let Some(failed) = err.downcast_ref::<Failed>() {
+        return Some(failed.exit);
+    }
+    if err.is::<MediaTypeError>() {
+        return Some(Exit::Unsupported);
+    }
+    if err.is::<PolicyError>() {
+        return Some(Exit::Validation);
+    }
+    if err.is::<CircuitOpenError>() {
+        return Some(Exit::Signing);
+    }
+    if let Some(err) = err.downcast_ref::<c2pa::Error>() {
+        return match err {
+            c2pa::Error::UnsupportedType => Some(Exit::Unsupported),
+            c2pa::Error::CoseSignature => Some(Exit::Signing),
+            c2pa::Error::OtherError(inner) => classify(inner.as_ref()),
+            _ => None,
+        };
+    }
+    if let Some(err) = err.downcast_ref::<azure_core::Error>() {
+        return match err.kind() {
+            ErrorKind::Credential => Some(Exit::Auth),
+            ErrorKind::HttpResponse { status, .. }
+                if matches!(*status, StatusCode::Unauthorized | StatusCode::Forbidden) =>
+            {
+                Some(Exit::Auth)
+            }
+            // The signer reports failed signing operations with the Azure error.
+            _ => Some(Exit::Signing),
+        };
+    }
+    None
+}
+
+/// An error with a known exit code, e.g. for a batch in which some files failed.
+#[derive(Debug)]
+pub(crate) struct Failed {
+    pub(crate) exit: Exit,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failed {}
@@ -0,0 +1,95 @@
+use anyhow::{Result, bail};
+use c2pa_azure::{VerifySettings, extract_manifest, verify_file_with_settings};
+use clap::Args;
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::{GlobalArgs, format_of};
+
+#[derive(Args, Debug)]
+pub(crate) struct ExtractArgs {
+    file: PathBuf,
+
+    /// Where to write the manifest store, defaults to the file with a `.c2pa` extension.
+    #[arg(short, long, value_name = "PATH")]
+    out: Option<PathBuf>,
+
+    /// Also write each assertion as JSON, under a directory per manifest.
+    #[arg(long, value_name = "DIR")]
+    assertions: Option<PathBuf>,
+
+    /// Overwrite existing files.
+    #[arg(long)]
+    force: bool,
+}
+
+// Manifest labels are URNs, which can't be used as file names on Windows.
+fn file_name(label: &str) -> String {
+    label.replace([':', '/', '\\'], "_")
+}
+
+fn write(path: &Path, contents: &[u8], force: bool) -> Result<()> {
+    if !force && path.exists() {
+        bail!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        );
+    }
+    Ok(fs::write(path, contents)?)
+}
+
+// Write the assertions of every manifest in the store and return how many were written.
+fn write_assertions(store: &Value, dir: &Path, force: bool) -> Result<usize> {
+    let mut count = 0;
+    let Some(manifests) = store["manifests"].as_object() else {
+        return Ok(count);
+    };
+    for (label, manifest) in manifests {
+        let manifest_dir = dir.join(file_name(label));
+        fs::create_dir_all(&manifest_dir)?;
+        for assertion in manifest["assertions"].as_array().into_iter().flatten() {
+            let Some(label) = assertion["label"].as_str() else {
+                continue;
+            };
+            // Repeated assertions are told apart by their instance.
+            let name = match assertion["instance"].as_u64() {
+                Some(instance) if instance > 1 => format!("{label}__{instance}"),
+                _ => label.to_owned(),
+            };
+            let path = manifest_dir.join(format!("{}.json", file_name(&name)));
+            write(
+                &path,
+                &serde_json::to_vec_pretty(&assertion["data"])?,
+                force,
+            )?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: ExtractArgs) -> Result<()> {
+    let format = format_of(&args.file, None)?;
+    let manifest = extract_manifest(format, File::open(&args.file)?)?;
+    let out = args
+        .out
+        .clone()
+        .unwrap_or_else(|| args.file.with_extension("c2pa"));
+    if out == args.file {
+        bail!("--out would overwrite {}", args.file.display());
+    }
+    write(&out, &manifest, args.force)?;
+    println!("extracted {} -> {}", args.file.display(), out.display());
+
+    if let Some(dir) = &args.assertions {
+        let settings = VerifySettings::from_toml(&global.settings()?)?;
+        let reader = verify_file_with_settings(format, File::open(&args.file)?, &settings).await?;
+        let store: Value = serde_json::from_str(&reader.json())?;
+        let count = write_assertions(&store, dir, args.force)?;
+        println!("wrote {count} assertions to {}", dir.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use anyhow::{Context as _, Result, bail};
+use c2pa::{AsyncSigner, Builder, Context, Signer, SigningAlg};
+use clap::Args;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Handle;
+
+use crate::{
+    GlobalArgs, format_of,
+    sign::{expand, manifest_definition},
+    template,
+};
+
+#[derive(Args, Debug)]
+pub(crate) struct SignFragmentsArgs {
+    /// Initialization segment of the fragmented MP4 (DASH or CMAF) rendition.
+    #[arg(long, value_name = "PATH")]
+    init: PathBuf,
+
+    /// Media segments or glob patterns, e.g. "seg_*.m4s". Sorted by name when expanded.
+    #[arg(long, value_name = "PATTERN", num_args = 1.., required = true)]
+    fragments: Vec<String>,
+
+    /// Directory the signed init segment and media segments are written to.
+    #[arg(short, long)]
+    output: PathBuf,
+
+    #[arg(short, long)]
+    manifest_definition: Option<PathBuf>,
+
+    /// Replace `{{KEY}}` in the manifest definition with VALUE.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = template::parse_variable)]
+    set: Vec<(String, String)>,
+
+    /// Overwrite existing output files.
+    #[arg(long)]
+    force: bool,
+}
+
+// The fragmented BMFF API of c2pa only takes a blocking signer.
+struct BlockingSigner<'a> {
+    signer: &'a dyn AsyncSigner,
+    runtime: Handle,
+}
+
+impl Signer for BlockingSigner<'_> {
+    fn sign(&self, data: &[u8]) -> c2pa::Result<Vec<u8>> {
+        self.runtime.block_on(self.signer.sign(data.to_vec()))
+    }
+
+    fn alg(&self) -> SigningAlg {
+        self.signer.alg()
+    }
+
+    fn certs(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.signer.certs()
+    }
+
+    fn reserve_size(&self) -> usize {
+        self.signer.reserve_size()
+    }
+
+    fn time_authority_url(&self) -> Option<String> {
+        self.signer.time_authority_url()
+    }
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: SignFragmentsArgs) -> Result<()> {
+    // The init segment has no media, only check that it is a BMFF file.
+    format_of(&args.init, Some("mp4"))?;
+    let mut fragments = expand(&args.fragments)?;
+    fragments.sort();
+    if fragments.is_empty() {
+        bail!("No fragments to sign");
+    }
+    if args.output.exists() && !args.output.is_dir() {
+        bail!("{} is not a directory", args.output.display());
+    }
+    let outputs: Vec<PathBuf> = std::iter::once(&args.init)
+        .chain(&fragments)
+        .map(|path| output_path(path, &args.output))
+        .collect::<Result<_>>()?;
+    if !args.force
+        && let Some(existing) = outputs.iter().find(|x| x.exists())
+    {
+        bail!(
+            "{} already exists, use --force to overwrite it",
+            existing.display()
+        );
+    }
+
+    let definition = manifest_definition(global, args.manifest_definition.as_deref(), &args.set)?;
+    let context = Context::new().with_settings(global.settings()?.as_str())?;
+    let mut builder = Builder::from_context(context).with_definition(definition.as_str())?;
+    let signer = global.signer().await?;
+    let signer = BlockingSigner {
+        signer: &*signer,
+        runtime: Handle::current(),
+    };
+    // Signing blocks this thread, including the timestamp request.
+    tokio::task::block_in_place(|| {
+        builder.sign_fragmented_files(&signer, &args.init, &fragments, &args.output)
+    })
+    .context("signing the fragments")?;
+    println!(
+        "signed {} and {} fragments -> {}",
+        args.init.display(),
+        fragments.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+// c2pa writes each segment under its file name in the output directory.
+fn output_path(path: &Path, output: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file", path.display()))?;
+    Ok(output.join(name))
+}
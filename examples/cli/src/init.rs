@@ -0,0 +1,62 @@
+use anyhow::{Result, bail};
+use clap::Args;
+use std::{fs, path::PathBuf};
+
+use crate::{DEFAULT_SETTINGS, sign::DEFAULT_MANIFEST};
+
+const CONFIG: &str = r#"# Defaults for c2pa-acs, read from the current or home directory or --config.
+# Command line flags and environment variables take precedence.
+
+# Trusted Signing endpoint of the account's region (-e, SIGNING_ENDPOINT).
+# endpoint = "https://eus.codesigning.azure.net"
+
+# Trusted Signing account (-a, SIGNING_ACCOUNT).
+# account = "signing_account"
+
+# Certificate profile of the account (-c, CERTIFICATE_PROFILE).
+# certificate_profile = "certificate_profile"
+
+# RFC3161 timestamp authority (--timestamp-url), defaults to DigiCert.
+# time_authority_url = "http://timestamp.acs.microsoft.com"
+
+# Manifest definition of signed files (-m). {{key}} placeholders are filled with --set key=value.
+manifest_definition = "manifest_definition.json"
+
+# c2pa settings (-s): trust anchors, verification and thumbnails.
+settings = "settings.toml"
+"#;
+
+#[derive(Args, Debug)]
+pub(crate) struct InitArgs {
+    /// Directory to write the files to.
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+
+    /// Overwrite existing files.
+    #[arg(long)]
+    force: bool,
+}
+
+pub(crate) fn run(args: InitArgs) -> Result<()> {
+    let files = [
+        ("c2pa-acs.toml", CONFIG),
+        ("manifest_definition.json", DEFAULT_MANIFEST),
+        ("settings.toml", DEFAULT_SETTINGS),
+    ];
+    let paths: Vec<_> = files.iter().map(|(name, _)| args.dir.join(name)).collect();
+    if !args.force
+        && let Some(existing) = paths.iter().find(|x| x.exists())
+    {
+        bail!(
+            "{} already exists, use --force to overwrite it",
+            existing.display()
+        );
+    }
+    fs::create_dir_all(&args.dir)?;
+    for (path, (_, contents)) in paths.iter().zip(files) {
+        fs::write(path, contents)?;
+        println!("wrote {}", path.display());
+    }
+    println!("Set the account in c2pa-acs.toml and edit the manifest definition to start signing.");
+    Ok(())
+}
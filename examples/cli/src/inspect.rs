@@ -0,0 +1,163 @@
+use anyhow::Result;
+use c2pa_azure::{VerifySettings, verify_file_with_settings};
+use clap::Args;
+use serde_json::Value;
+use std::{collections::HashSet, fs::File, path::PathBuf};
+
+use crate::{GlobalArgs, format_of};
+
+#[derive(Args, Debug)]
+pub(crate) struct InspectArgs {
+    file: PathBuf,
+}
+
+/// A line of the tree and the lines nested under it.
+struct Node {
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            children: Vec::new(),
+        }
+    }
+
+    fn with(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn print(&self) {
+        println!("{}", self.text);
+        self.print_children("");
+    }
+
+    fn print_children(&self, prefix: &str) {
+        for (index, child) in self.children.iter().enumerate() {
+            let last = index + 1 == self.children.len();
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            println!("{prefix}{branch}{}", child.text);
+            child.print_children(&format!("{prefix}{indent}"));
+        }
+    }
+}
+
+fn text(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(str::to_owned)
+}
+
+fn signature(info: &Value) -> Node {
+    let mut node = Node::new("signature");
+    let fields = [
+        ("algorithm", "alg"),
+        ("issuer", "issuer"),
+        ("common name", "common_name"),
+        ("serial number", "cert_serial_number"),
+        ("time", "time"),
+    ];
+    for (name, key) in fields {
+        if let Some(value) = text(info, key) {
+            node = node.with(Node::new(format!("{name}: {value}")));
+        }
+    }
+    node
+}
+
+fn manifest(store: &Value, label: &str, visited: &mut HashSet<String>) -> Node {
+    let mut node = Node::new(label);
+    let Some(manifest) = store["manifests"].get(label) else {
+        return node.with(Node::new("(missing)"));
+    };
+    // Guard against malformed stores where ingredients reference each other.
+    if !visited.insert(label.to_owned()) {
+        return node.with(Node::new("(already shown)"));
+    }
+    if let Some(title) = text(manifest, "title") {
+        node = node.with(Node::new(format!("title: {title}")));
+    }
+    if let Some(format) = text(manifest, "format") {
+        node = node.with(Node::new(format!("format: {format}")));
+    }
+    let generators: Vec<_> = manifest["claim_generator_info"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|x| {
+            let name = text(x, "name")?;
+            Some(match text(x, "version") {
+                Some(version) => format!("{name} {version}"),
+                None => name,
+            })
+        })
+        .collect();
+    if !generators.is_empty() {
+        node = node.with(Node::new(format!(
+            "claim generator: {}",
+            generators.join(", ")
+        )));
+    }
+    if let Some(info) = manifest.get("signature_info") {
+        node = node.with(signature(info));
+    }
+    if let Some(assertions) = manifest["assertions"].as_array() {
+        let mut parent = Node::new("assertions");
+        for assertion in assertions {
+            if let Some(label) = text(assertion, "label") {
+                parent = parent.with(Node::new(label));
+            }
+        }
+        node = node.with(parent);
+    }
+    if let Some(ingredients) = manifest["ingredients"].as_array() {
+        let mut parent = Node::new("ingredients");
+        for ingredient in ingredients {
+            let title = text(ingredient, "title").unwrap_or_else(|| "(untitled)".to_owned());
+            let mut child = Node::new(match text(ingredient, "relationship") {
+                Some(relationship) => format!("{title} ({relationship})"),
+                None => title,
+            });
+            if let Some(label) = text(ingredient, "active_manifest") {
+                child = child.with(manifest(store, &label, visited));
+            }
+            parent = parent.with(child);
+        }
+        node = node.with(parent);
+    }
+    node
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: InspectArgs) -> Result<()> {
+    let settings = VerifySettings::from_toml(&global.settings()?)?;
+    let file = File::open(&args.file)?;
+    let reader = verify_file_with_settings(format_of(&args.file, None)?, file, &settings).await?;
+    let store: Value = serde_json::from_str(&reader.json())?;
+
+    let mut root = Node::new(args.file.display().to_string());
+    if let Some(state) = text(&store, "validation_state") {
+        root = root.with(Node::new(format!("validation state: {state}")));
+    }
+    let failures = store["validation_results"]["activeManifest"]["failure"].as_array();
+    if let Some(failures) = failures.filter(|x| !x.is_empty()) {
+        let mut node = Node::new("validation failures");
+        for failure in failures {
+            if let Some(code) = text(failure, "code") {
+                node = node.with(Node::new(code));
+            }
+        }
+        root = root.with(node);
+    }
+    if let Some(label) = text(&store, "active_manifest") {
+        let mut active = manifest(&store, &label, &mut HashSet::new());
+        active.text.push_str(" (active)");
+        root = root.with(active);
+    }
+    root.print();
+    Ok(())
+}
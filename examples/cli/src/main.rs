@@ -1,112 +1,393 @@
-use anyhow::Result;
-use azure_core::{credentials::TokenCredential, http::Url};
-use azure_identity::{
-    AzureCliCredential, ManagedIdentityCredential, ManagedIdentityCredentialOptions, UserAssignedId,
+use anyhow::{Context as _, Result, bail};
+use azure_core::credentials::TokenCredential;
+use azure_core::http::Url;
+use c2pa::AsyncSigner;
+use c2pa::SigningAlg;
+use c2pa_azure::{
+    CredentialKind, CredentialOptions, KeyVaultOptions, KeyVaultSigner, RetrySettings,
+    SNIFF_LENGTH, SigningOptions, TrustedSigner, credential, credential_for_environment,
+    resolve_media_type,
 };
-use c2pa::{Builder, Context};
-use c2pa_azure::{SigningOptions, TrustedSigner};
-use clap::Parser;
+#[cfg(feature = "dev-signer")]
+use c2pa_azure::{DevSigner, DevSignerOptions};
+use clap::{Args, CommandFactory, Parser, Subcommand, builder::PossibleValuesParser};
+use config::Config;
+use exit::Exit;
 use std::{
-    env,
-    fs::{self, File, OpenOptions},
+    ffi::OsString,
+    fs::{self, File},
+    io::Read,
+    path::Path,
     path::PathBuf,
+    process::ExitCode,
     sync::Arc,
 };
 
+mod audit;
+mod certs;
+mod completions;
+mod config;
+mod diff;
+mod doctor;
+mod exit;
+mod extract;
+mod fragments;
+mod init;
+mod inspect;
+mod progress;
+mod remote;
+mod resume;
+mod serve;
+mod sign;
+mod template;
+mod verify;
+mod watch;
+
 #[derive(Parser, Debug)]
 #[command(
+    name = "c2pa-acs",
     version,
     about,
     author = "Prakash Duggaraju<duggaraju@gmail.com>",
-    long_about = "A command line tool to add content credentials to a file using the Azure Code Signing service."
+    long_about = "A command line tool to add and inspect content credentials using the Azure Code Signing service."
 )]
-struct Arguments {
-    #[arg(short, long)]
-    input: PathBuf,
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+}
 
-    #[arg(short, long)]
-    output: PathBuf,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Add content credentials to a file.
+    Sign(sign::SignArgs),
+    /// Add content credentials to a fragmented MP4 (DASH or CMAF) rendition.
+    SignFragments(fragments::SignFragmentsArgs),
+    /// Validate the content credentials of a file.
+    Verify(verify::VerifyArgs),
+    /// Show the manifest store of a file.
+    Inspect(inspect::InspectArgs),
+    /// Compare the signer, assertions and ingredients of the manifests of two files.
+    Diff(diff::DiffArgs),
+    /// Write the manifest store of a file to a `.c2pa` file.
+    Extract(extract::ExtractArgs),
+    /// Serve the sign and verify HTTP API of the Azure Function example.
+    Serve(serve::ServeArgs),
+    /// Check the settings, credentials, connectivity, certificate and timestamp authority.
+    Doctor,
+    /// Print a shell completion script, e.g. `c2pa-acs completions bash > /etc/bash_completion.d/c2pa-acs`.
+    Completions(completions::CompletionsArgs),
+    /// Write the man pages, for packaging.
+    #[command(hide = true)]
+    Man(completions::ManArgs),
+    /// Write a starter configuration file, manifest definition and c2pa settings.
+    Init(init::InitArgs),
+    /// Show the Trusted Signing certificate chain.
+    Certs(certs::CertsArgs),
+}
 
-    #[arg(short, long)]
-    manifest_definition: Option<PathBuf>,
+/// Flags shared by all subcommands.
+#[derive(Args, Debug)]
+pub(crate) struct GlobalArgs {
+    /// Configuration file, defaults to c2pa-acs.toml in the current or home directory.
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
 
-    #[arg(short = 's', long, value_name = "PATH")]
+    /// c2pa settings (TOML), defaults to the Trusted Signing trust anchors.
+    #[arg(short = 's', long, value_name = "PATH", global = true)]
     settings: Option<PathBuf>,
 
-    #[arg(short, long)]
-    account: String,
+    #[arg(short, long, global = true, env = "SIGNING_ACCOUNT")]
+    account: Option<String>,
+
+    #[arg(short, long, global = true, env = "SIGNING_ENDPOINT")]
+    endpoint: Option<Url>,
+
+    #[arg(short, long, global = true, env = "CERTIFICATE_PROFILE")]
+    certificate_profile: Option<String>,
+
+    /// RFC3161 timestamp authority, defaults to the configuration file or DigiCert.
+    #[arg(long, value_name = "URL", global = true, env = "TIME_AUTHORITY_URL")]
+    timestamp_url: Option<Url>,
+
+    /// Don't timestamp signatures.
+    #[arg(long, global = true)]
+    no_timestamp: bool,
+
+    /// Signing algorithm, checked against the key of the certificate profile. Defaults to ps384.
+    #[arg(
+        long,
+        global = true,
+        value_parser = PossibleValuesParser::new(["ps256", "ps384", "ps512", "es256", "es384"])
+            .try_map(|x| x.parse::<SigningAlg>()),
+    )]
+    algorithm: Option<SigningAlg>,
+
+    /// Retries of failed or throttled requests to Trusted Signing.
+    #[arg(long, value_name = "N", global = true)]
+    max_retries: Option<u32>,
+
+    /// Timeout of each request attempt to Trusted Signing, in seconds.
+    #[arg(long, value_name = "SECS", global = true)]
+    request_timeout: Option<u64>,
+
+    /// How long signing may wait for Trusted Signing to complete, in seconds. Defaults to 30.
+    #[arg(long, value_name = "SECS", global = true)]
+    sign_timeout: Option<u64>,
+
+    /// Service holding the signing key.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        global = true,
+        env = "SIGNING_BACKEND"
+    )]
+    backend: Backend,
+
+    /// Vault (or Managed HSM) URL for the keyvault backend.
+    #[arg(long, value_name = "URL", global = true, env = "KEY_VAULT_URL")]
+    key_vault_url: Option<Url>,
 
-    #[arg(short, long)]
-    endpoint: Url,
+    /// Name of the Key Vault key used for signing.
+    #[arg(long, global = true, env = "KEY_VAULT_KEY")]
+    key_name: Option<String>,
 
-    #[arg(short, long)]
-    certificate_profile: String,
+    /// Key Vault certificate with the signing chain, defaults to the key name.
+    #[arg(long, global = true, env = "KEY_VAULT_CERTIFICATE")]
+    certificate_name: Option<String>,
+
+    /// Azure credential: cli, managed-identity, client-secret, device-code or workload-identity.
+    /// Defaults to workload or managed identity when running in Azure and the Azure CLI otherwise.
+    /// The client secret is read from AZURE_CLIENT_SECRET.
+    #[arg(long, value_name = "KIND", global = true, env = "AZURE_CREDENTIAL")]
+    credential: Option<CredentialKind>,
+
+    #[arg(long, global = true, env = "AZURE_TENANT_ID")]
+    tenant_id: Option<String>,
+
+    /// Client id of the service principal, app registration or user assigned identity.
+    #[arg(long, global = true, env = "AZURE_CLIENT_ID")]
+    client_id: Option<String>,
+
+    /// Sign with a local PEM certificate chain instead of Trusted Signing.
+    #[cfg(feature = "dev-signer")]
+    #[arg(long, value_name = "PATH", requires = "dev_key", global = true)]
+    dev_certificate: Option<PathBuf>,
+
+    /// PEM private key of the development certificate.
+    #[cfg(feature = "dev-signer")]
+    #[arg(long, value_name = "PATH", global = true)]
+    dev_key: Option<PathBuf>,
+
+    #[cfg(feature = "dev-signer")]
+    #[arg(long, default_value = "ps384", global = true)]
+    dev_algorithm: SigningAlg,
+
+    #[arg(skip)]
+    pub(crate) file: Config,
 }
 
-const DEFAULT_MANIFEST: &str = include_str!("../../../test_data/manifest_definition.json");
-const DEFAULT_SETTINGS: &str = include_str!("../../../test_data/settings.toml");
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Backend {
+    /// Azure Trusted Signing, using --endpoint, --account and --certificate-profile.
+    #[default]
+    TrustedSigning,
+    /// A key and certificate in Azure Key Vault, using --key-vault-url and --key-name.
+    Keyvault,
+}
+
+const DEFAULT_TIME_AUTHORITY: &str = "http://timestamp.digicert.com";
+pub(crate) const DEFAULT_SETTINGS: &str = include_str!("../../../test_data/settings.toml");
+
+impl GlobalArgs {
+    /// Fill the flags that were not given from the configuration file.
+    fn load_config(&mut self) -> Result<()> {
+        self.file = Config::load(self.config.as_deref())?;
+        if self.endpoint.is_none() {
+            self.endpoint = self.file.endpoint.as_deref().map(Url::parse).transpose()?;
+        }
+        self.account = self.account.take().or(self.file.account.take());
+        self.certificate_profile = self
+            .certificate_profile
+            .take()
+            .or(self.file.certificate_profile.take());
+        self.settings = self.settings.take().or(self.file.settings.take());
+        if self.timestamp_url.is_none() {
+            self.timestamp_url = self
+                .file
+                .time_authority_url
+                .as_deref()
+                .map(Url::parse)
+                .transpose()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn settings(&self) -> Result<String> {
+        Ok(match &self.settings {
+            Some(path) => fs::read_to_string(path)?,
+            None => DEFAULT_SETTINGS.to_owned(),
+        })
+    }
+
+    pub(crate) fn time_authority_url(&self) -> Result<Option<Url>> {
+        Ok(match (&self.timestamp_url, self.no_timestamp) {
+            (_, true) => None,
+            (Some(url), false) => Some(url.clone()),
+            (None, false) => Some(Url::parse(DEFAULT_TIME_AUTHORITY)?),
+        })
+    }
+
+    fn retry(&self) -> RetrySettings {
+        let mut retry = RetrySettings::default();
+        if let Some(max_retries) = self.max_retries {
+            retry.max_retries = max_retries;
+        }
+        retry.request_timeout_ms = self.request_timeout.map(|x| x * 1000);
+        if let Some(sign_timeout) = self.sign_timeout {
+            retry.sign_timeout_ms = sign_timeout * 1000;
+        }
+        retry
+    }
 
-impl Arguments {
-    fn signing_options(&self) -> SigningOptions {
-        SigningOptions::new(
-            self.endpoint.clone(),
-            self.account.clone(),
-            self.certificate_profile.clone(),
-            Some("http://timestamp.digicert.com"),
+    pub(crate) fn signing_options(&self) -> Result<SigningOptions> {
+        if self.backend != Backend::TrustedSigning {
+            bail!("this command needs the trusted-signing backend");
+        }
+        let missing = "--account, --endpoint and --certificate-profile are required";
+        let mut options = SigningOptions::new(
+            self.endpoint.clone().context(missing)?,
+            self.account.clone().context(missing)?,
+            self.certificate_profile.clone().context(missing)?,
+            None,
         )
+        .with_time_authority_url(self.time_authority_url()?);
+        if let Some(algorithm) = self.algorithm {
+            options = options.with_algorithm(algorithm);
+        }
+        Ok(options.with_retry(self.retry()))
+    }
+
+    pub(crate) fn key_vault_options(&self) -> Result<KeyVaultOptions> {
+        let missing = "--key-vault-url and --key-name are required for the keyvault backend";
+        let mut options = KeyVaultOptions::new(
+            self.key_vault_url.clone().context(missing)?,
+            self.key_name.as_deref().context(missing)?,
+        );
+        options.certificate_name = self.certificate_name.clone();
+        options.algorithm = self.algorithm;
+        options.time_authority_url = self.time_authority_url()?;
+        options.retry = self.retry();
+        Ok(options)
+    }
+
+    #[cfg(feature = "dev-signer")]
+    fn dev_signer_options(&self) -> Option<DevSignerOptions> {
+        let mut options = DevSignerOptions::new(
+            self.dev_certificate.clone()?,
+            self.dev_key.clone()?,
+            self.dev_algorithm,
+        );
+        if !self.no_timestamp {
+            options.time_authority_url = self.timestamp_url.clone();
+        }
+        Some(options)
+    }
+
+    pub(crate) fn credential(&self) -> Result<Arc<dyn TokenCredential>> {
+        let Some(kind) = self.credential else {
+            return Ok(credential_for_environment()?);
+        };
+        let options = CredentialOptions {
+            tenant_id: self.tenant_id.clone(),
+            client_id: self.client_id.clone(),
+            device_code_prompt: Some(Arc::new(|message| eprintln!("{message}"))),
+            ..CredentialOptions::from_env()
+        };
+        Ok(credential(kind, options)?)
+    }
+
+    pub(crate) async fn trusted_signer(&self) -> Result<TrustedSigner> {
+        Ok(TrustedSigner::new(self.credential()?, self.signing_options()?).await?)
+    }
+
+    pub(crate) async fn signer(&self) -> Result<Box<dyn AsyncSigner + Send + Sync>> {
+        #[cfg(feature = "dev-signer")]
+        if let Some(options) = self.dev_signer_options() {
+            return Ok(Box::new(DevSigner::new(&options)?));
+        }
+        Ok(match self.backend {
+            Backend::TrustedSigning => Box::new(self.trusted_signer().await?),
+            Backend::Keyvault => {
+                Box::new(KeyVaultSigner::new(self.credential()?, self.key_vault_options()?).await?)
+            }
+        })
+    }
+}
+
+/// The media type of a file from its signature, checked against `--format` or its extension.
+pub(crate) fn format_of(path: &Path, format: Option<&str>) -> Result<&'static str> {
+    let mut header = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)
+        .and_then(|file| file.take(SNIFF_LENGTH as u64).read_to_end(&mut header))
+        .with_context(|| path.display().to_string())?;
+    let declared = format.or(path.extension().and_then(|x| x.to_str()));
+    resolve_media_type(declared, &header).with_context(|| path.display().to_string())
+}
+
+// Older versions only signed and took the sign flags without a subcommand.
+fn arguments() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let command = Cli::command();
+    let subcommand = args.iter().skip(1).any(|arg| {
+        command
+            .get_subcommands()
+            .any(|x| arg.to_str() == Some(x.get_name()))
+    });
+    let flags = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        arg.starts_with('-') && !matches!(arg, "-h" | "--help" | "-V" | "--version")
+    });
+    if flags && !subcommand {
+        args.insert(1, "sign".into());
+    }
+    args
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    // These don't need the configuration, which init writes.
+    if !matches!(
+        cli.command,
+        Command::Init(_) | Command::Completions(_) | Command::Man(_)
+    ) {
+        cli.global.load_config()?;
+    }
+    match cli.command {
+        Command::Sign(args) => sign::run(&cli.global, args).await,
+        Command::SignFragments(args) => fragments::run(&cli.global, args).await,
+        Command::Verify(args) => verify::run(&cli.global, args).await,
+        Command::Inspect(args) => inspect::run(&cli.global, args).await,
+        Command::Diff(args) => diff::run(&cli.global, args).await,
+        Command::Extract(args) => extract::run(&cli.global, args).await,
+        Command::Certs(args) => certs::run(&cli.global, args).await,
+        Command::Serve(args) => serve::run(&cli.global, args).await,
+        Command::Doctor => doctor::run(&cli.global).await,
+        Command::Init(args) => init::run(args),
+        Command::Completions(args) => completions::completions(args),
+        Command::Man(args) => completions::man(args),
     }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     env_logger::init();
-    let args = Arguments::parse();
-    let credentials: Arc<dyn TokenCredential> = if cfg!(debug_assertions) {
-        AzureCliCredential::new(None)?
-    } else {
-        let options = ManagedIdentityCredentialOptions {
-            user_assigned_id: Some(UserAssignedId::ClientId(
-                env::var("AZURE_CLIENT_ID").expect("missing AZURE_CLIENT_ID"),
-            )),
-            ..Default::default()
-        };
-        ManagedIdentityCredential::new(Some(options))?
-    };
-
-    let options = args.signing_options();
-
-    let mut input = File::open(&args.input)?;
-    let mut output = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&args.output)?;
-    let format = args
-        .input
-        .extension()
-        .map(|x| x.to_str().unwrap())
-        .unwrap_or("application/octet-stream");
-
-    let settings = if let Some(path) = args.settings.as_ref() {
-        fs::read_to_string(path)?
-    } else {
-        DEFAULT_SETTINGS.to_owned()
-    };
-    let context = Context::new().with_settings(settings)?;
-
-    let manifest_definition = if let Some(path) = args.manifest_definition {
-        fs::read_to_string(path)?
-    } else {
-        DEFAULT_MANIFEST.to_owned()
-    };
-
-    let mut builder = Builder::from_context(context).with_definition(&manifest_definition)?;
-    let signer = TrustedSigner::new(credentials, options).await?;
-
-    builder
-        .sign_async(&signer, format, &mut input, &mut output)
-        .await?;
-    log::info!("Successfully signed the file.");
-    Ok(())
+    match run(Cli::parse_from(arguments())).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            Exit::of(&err).into()
+        }
+    }
 }
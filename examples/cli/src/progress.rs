@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use c2pa::{AsyncSigner, SigningAlg};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// Smaller files are signed before a progress bar would be of any use.
+const LARGE_FILE: u64 = 64 * 1024 * 1024;
+
+/// Progress bars on stderr for the large files being signed, one per file.
+pub(crate) struct Progress {
+    bars: Option<MultiProgress>,
+}
+
+impl Progress {
+    pub(crate) fn new(quiet: bool) -> Self {
+        Self {
+            bars: (!quiet).then(MultiProgress::new),
+        }
+    }
+
+    /// A bar for signing `input`, hidden for small files or with `--quiet`.
+    pub(crate) fn bar(&self, input: &Path, length: u64) -> ProgressBar {
+        let Some(bars) = self.bars.as_ref().filter(|_| length >= LARGE_FILE) else {
+            return ProgressBar::hidden();
+        };
+        let style = ProgressStyle::with_template(
+            "{prefix} {msg:8} [{bar:30}] {bytes}/{total_bytes} ({eta})",
+        )
+        .expect("valid template")
+        .progress_chars("=> ");
+        let name = input.file_name().unwrap_or_default().to_string_lossy();
+        bars.add(
+            ProgressBar::new(length)
+                .with_style(style)
+                .with_prefix(name.into_owned()),
+        )
+    }
+}
+
+/// Reports the position of a stream read or written while signing.
+pub(crate) struct Tracked<S> {
+    inner: S,
+    bar: ProgressBar,
+    position: u64,
+}
+
+impl<S> Tracked<S> {
+    pub(crate) fn new(inner: S, bar: ProgressBar) -> Self {
+        Self {
+            inner,
+            bar,
+            position: 0,
+        }
+    }
+
+    fn advance(&mut self, phase: &'static str, bytes: usize) {
+        self.position += bytes as u64;
+        self.bar.set_message(phase);
+        self.bar.set_position(self.position);
+    }
+}
+
+impl<S: Read> Read for Tracked<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        // c2pa hashes both the input and the output before signing.
+        self.advance("hashing", read);
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for Tracked<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.advance("writing", written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Seek> Seek for Tracked<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Shows the signing phase while Trusted Signing signs the claim.
+pub(crate) struct ProgressSigner<'a> {
+    pub(crate) signer: &'a dyn AsyncSigner,
+    pub(crate) bar: ProgressBar,
+}
+
+#[async_trait]
+impl AsyncSigner for ProgressSigner<'_> {
+    async fn sign(&self, data: Vec<u8>) -> c2pa::Result<Vec<u8>> {
+        self.bar.set_message("signing");
+        self.signer.sign(data).await
+    }
+
+    fn alg(&self) -> SigningAlg {
+        self.signer.alg()
+    }
+
+    fn certs(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.signer.certs()
+    }
+
+    fn reserve_size(&self) -> usize {
+        self.signer.reserve_size()
+    }
+
+    fn time_authority_url(&self) -> Option<String> {
+        self.signer.time_authority_url()
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{Context as _, Result, bail};
+use azure_core::http::{
+    ClientOptions, Context, Method, Pipeline, Request, Url,
+    headers::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures::StreamExt;
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+use tempfile::TempPath;
+
+const STORAGE_VERSION: &str = "2021-08-06";
+// Uploads are sent in blocks of this size, so outputs larger than memory can be written.
+const BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// The URL of an `--input` or `--output` given as http(s) URL.
+pub(crate) fn url(path: &str) -> Option<Url> {
+    if !path.starts_with("https://") && !path.starts_with("http://") {
+        return None;
+    }
+    Url::parse(path).ok()
+}
+
+fn pipeline() -> Pipeline {
+    Pipeline::new(
+        option_env!("CARGO_PKG_NAME"),
+        option_env!("CARGO_PKG_VERSION"),
+        ClientOptions::default(),
+        vec![],
+        vec![],
+        None,
+    )
+}
+
+// The extension of the last path segment, so the downloaded file keeps its format.
+fn extension(url: &Url) -> Option<&str> {
+    let name = url.path_segments()?.next_back()?;
+    Path::new(name).extension()?.to_str()
+}
+
+/// Stream a remote asset to a temporary file, failing once it is larger than `limit` bytes.
+pub(crate) async fn download(url: &Url, limit: u64) -> Result<TempPath> {
+    let mut request = Request::new(url.clone(), Method::Get);
+    let response = pipeline()
+        .stream(&Context::new(), &mut request, None)
+        .await
+        .with_context(|| format!("failed to download {}", redact(url)))?;
+    let status = u16::from(response.status());
+    if !(200..300).contains(&status) {
+        bail!("failed to download {}, status {status}", redact(url));
+    }
+    let length = response
+        .headers()
+        .get_optional_str(&CONTENT_LENGTH)
+        .and_then(|x| x.parse::<u64>().ok());
+    if length.is_some_and(|x| x > limit) {
+        bail!("{} is larger than {limit} bytes", redact(url));
+    }
+
+    let suffix = extension(url).map(|x| format!(".{x}")).unwrap_or_default();
+    let mut file = tempfile::Builder::new().suffix(&suffix).tempfile()?;
+    let mut body = response.into_body();
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        if size > limit {
+            bail!("{} is larger than {limit} bytes", redact(url));
+        }
+        file.write_all(&chunk)?;
+    }
+    file.flush()?;
+    Ok(file.into_temp_path())
+}
+
+async fn send(pipeline: &Pipeline, url: &Url, mut request: Request) -> Result<()> {
+    request.insert_header("x-ms-version", STORAGE_VERSION);
+    let response = pipeline
+        .send(&Context::new(), &mut request, None)
+        .await
+        .with_context(|| format!("failed to upload to {}", redact(url)))?;
+    let status = u16::from(response.status());
+    if !(200..300).contains(&status) {
+        bail!("failed to upload to {}, status {status}", redact(url));
+    }
+    Ok(())
+}
+
+/// Upload a signed file to a blob SAS URL, a block at a time.
+pub(crate) async fn upload(url: &Url, path: &Path, content_type: &str) -> Result<()> {
+    let pipeline = pipeline();
+    let mut file = File::open(path)?;
+    let mut ids = Vec::new();
+    loop {
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        (&mut file)
+            .take(BLOCK_SIZE as u64)
+            .read_to_end(&mut block)?;
+        if block.is_empty() {
+            break;
+        }
+        let last = block.len() < BLOCK_SIZE;
+        let id = BASE64_STANDARD.encode(format!("{:08}", ids.len()));
+        let mut block_url = url.clone();
+        block_url
+            .query_pairs_mut()
+            .append_pair("comp", "block")
+            .append_pair("blockid", &id);
+        let mut request = Request::new(block_url, Method::Put);
+        request.set_body(block);
+        send(&pipeline, url, request).await?;
+        ids.push(id);
+        if last {
+            break;
+        }
+    }
+
+    let mut list_url = url.clone();
+    list_url.query_pairs_mut().append_pair("comp", "blocklist");
+    let mut body = "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>".to_owned();
+    for id in &ids {
+        _ = write!(body, "<Latest>{id}</Latest>");
+    }
+    body.push_str("</BlockList>");
+    let mut request = Request::new(list_url, Method::Put);
+    request.insert_header(CONTENT_TYPE, "application/xml");
+    request.insert_header("x-ms-blob-content-type", content_type.to_owned());
+    request.set_body(body.into_bytes());
+    send(&pipeline, url, request).await
+}
+
+/// A temporary file to sign into before uploading, with the extension of the URL.
+pub(crate) fn upload_path(url: &Url) -> Result<TempPath> {
+    let suffix = extension(url).map(|x| format!(".{x}")).unwrap_or_default();
+    let path = tempfile::Builder::new()
+        .suffix(&suffix)
+        .tempfile()?
+        .into_temp_path();
+    // Signing refuses to replace existing files, and writes the output itself.
+    fs::remove_file(&path)?;
+    Ok(path)
+}
+
+/// The URL without its query, so SAS tokens don't end up in logs.
+pub(crate) fn redact(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_query(None);
+    url
+}
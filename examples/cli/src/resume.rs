@@ -0,0 +1,56 @@
+use anyhow::{Context as _, Result};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// Written to the output directory, or the current directory when signing in place.
+const STATE_FILE: &str = ".c2pa-acs.state";
+
+/// The outputs of a batch that were signed, a line each, so `--resume` can skip them.
+pub(crate) struct State {
+    path: PathBuf,
+    file: File,
+    done: HashSet<PathBuf>,
+}
+
+impl State {
+    /// Open the state of the batch writing to `dir`. Without `resume` a previous state is discarded.
+    pub(crate) fn open(dir: &Path, resume: bool) -> Result<Self> {
+        let path = dir.join(STATE_FILE);
+        let done = match fs::read_to_string(&path) {
+            Ok(state) if resume => state.lines().map(PathBuf::from).collect(),
+            _ => HashSet::new(),
+        };
+        fs::create_dir_all(dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| path.display().to_string())?;
+        // Keep what was done before, even if this run is interrupted too.
+        for output in &done {
+            writeln!(file, "{}", output.display())?;
+        }
+        Ok(Self { path, file, done })
+    }
+
+    /// Whether a previous run signed `output` and it still exists.
+    pub(crate) fn is_done(&self, output: &Path) -> bool {
+        self.done.contains(output) && output.exists()
+    }
+
+    pub(crate) fn complete(&mut self, output: &Path) -> Result<()> {
+        writeln!(self.file, "{}", output.display())?;
+        Ok(self.file.flush()?)
+    }
+
+    /// Remove the state once every file of the batch was signed.
+    pub(crate) fn finish(self) -> Result<()> {
+        drop(self.file);
+        Ok(fs::remove_file(&self.path)?)
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use azure_function::{Config, Options, Readiness, Tenants};
+use clap::Args;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{GlobalArgs, sign::manifest_definition, template};
+
+#[derive(Args, Debug)]
+pub(crate) struct ServeArgs {
+    #[arg(
+        short,
+        long,
+        default_value_t = 3000,
+        env = "FUNCTIONS_CUSTOMHANDLER_PORT"
+    )]
+    port: u16,
+
+    #[arg(short, long)]
+    manifest_definition: Option<PathBuf>,
+
+    /// Replace `{{KEY}}` in the manifest definition with VALUE.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = template::parse_variable)]
+    set: Vec<(String, String)>,
+
+    /// The options of the function, under the same environment variables.
+    #[command(flatten)]
+    options: Options,
+}
+
+/// Serve the sign and verify API of the Azure Function example until the process is stopped.
+pub(crate) async fn run(global: &GlobalArgs, args: ServeArgs) -> Result<()> {
+    let definition = manifest_definition(global, args.manifest_definition.as_deref(), &args.set)?;
+    // The manifest definition is expanded from its template once and the settings come from
+    // the command line, so neither is reloaded.
+    let config = Config::new(
+        Arc::from(global.signer().await?),
+        Some(global.settings()?.as_str()),
+        definition,
+    )?;
+    log::info!(
+        "Serving POST /api/sign, /api/verify and /api/jobs on port {}",
+        args.port
+    );
+    let options = args.options;
+    let address = (options.bind_address, args.port);
+    let grace = Duration::from_secs(options.shutdown_grace_secs);
+    let tls = options.tls()?;
+    let routes = azure_function::routes(
+        Arc::new(Tenants::from(Arc::new(config))),
+        options,
+        // The credential is owned by the signer, so readiness checks the certificate only.
+        Arc::new(Readiness::default()),
+        global.credential().ok(),
+    )?;
+    azure_function::serve(routes, address, tls, grace).await;
+    Ok(())
+}
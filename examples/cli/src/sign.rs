@@ -0,0 +1,886 @@
+use anyhow::{Context as _, Result, bail};
+use azure_core::http::Url;
+use c2pa::{AsyncSigner, Builder, Context, Reader};
+use c2pa_azure::{Relationship, SNIFF_LENGTH, resolve_media_type};
+use clap::Args;
+use futures::{StreamExt, stream};
+use indicatif::ProgressBar;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    GlobalArgs,
+    audit::{self, AuditLog},
+    exit::{Exit, Failed},
+    format_of,
+    progress::{Progress, ProgressSigner, Tracked},
+    remote,
+    resume::State,
+    template,
+    watch::watch,
+};
+
+pub(crate) const DEFAULT_MANIFEST: &str =
+    include_str!("../../../test_data/manifest_definition.json");
+
+// Reads from stdin or writes to stdout.
+const PIPE: &str = "-";
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("inputs").args(["input", "files"]).required(true).multiple(true)))]
+pub(crate) struct SignArgs {
+    /// Files or glob patterns to sign, `-` for stdin, or an http(s) URL to download.
+    #[arg(short, long, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Files or glob patterns to sign, same as `--input`.
+    #[arg(value_name = "FILES")]
+    files: Vec<String>,
+
+    /// Output file, or the output directory when signing several files. `-` for stdout,
+    /// which is the default when reading from stdin. A blob SAS URL uploads the signed file.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Largest file downloaded for an `--input` URL, in MiB.
+    #[arg(long, value_name = "MIB", default_value_t = 2048)]
+    max_download_mb: u64,
+
+    /// Format of the input (extension or MIME type). Defaults to the extension of each input
+    /// file. The content is checked against it, and files that don't match are not signed.
+    #[arg(short, long)]
+    format: Option<String>,
+
+    #[arg(short, long)]
+    manifest_definition: Option<PathBuf>,
+
+    /// Replace `{{KEY}}` in the manifest definition with VALUE. `title`, `author` and
+    /// `claim_generator` (name/version) set those fields even without a placeholder.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = template::parse_variable)]
+    set: Vec<(String, String)>,
+
+    /// Attach a file as an ingredient of every signed file. Can be repeated.
+    #[arg(long, value_name = "PATH")]
+    ingredient: Vec<PathBuf>,
+
+    /// Relationship of each `--ingredient`, in the same order: parentOf, componentOf or inputTo.
+    /// Defaults to componentOf. With a parentOf ingredient the input is not added as the parent.
+    #[arg(long, value_name = "RELATIONSHIP")]
+    relationship: Vec<Relationship>,
+
+    /// Sign the files under input directories into a mirrored output tree.
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Number of files signed concurrently, also with --watch. Requests throttled by Trusted
+    /// Signing are retried.
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// Only sign the files under input directories matching one of these globs, relative to
+    /// the directory, e.g. `*.jpg`. Applies to `--recursive` and `--watch`. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<glob::Pattern>,
+
+    /// Skip the files under input directories matching one of these globs, e.g. `thumbs/**`.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<glob::Pattern>,
+
+    /// Keep running and sign new files as they appear in the input directory.
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Check the credentials, certificate chain, manifest definition and inputs, and list what
+    /// would be signed without signing or writing anything.
+    #[arg(long, conflicts_with = "watch")]
+    dry_run: bool,
+
+    /// Overwrite existing output files.
+    #[arg(long)]
+    force: bool,
+
+    /// Skip the files an interrupted batch already signed. Batches record the files they signed
+    /// in `.c2pa-acs.state` in the output directory, or for --in-place in the common parent
+    /// directory of the inputs, removed once every file is signed.
+    #[arg(long, conflicts_with = "watch")]
+    resume: bool,
+
+    /// Replace each input with its signed version, once signing it succeeded.
+    #[arg(long, conflicts_with_all = ["output", "watch", "no_embed"])]
+    in_place: bool,
+
+    /// Don't show progress bars for large files.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Also write the manifest store to a `.c2pa` sidecar next to each output file.
+    #[arg(long)]
+    sidecar: bool,
+
+    /// Don't embed the manifest and only write the `.c2pa` sidecar, the asset is left unchanged.
+    #[arg(long)]
+    no_embed: bool,
+
+    /// Reference the manifest at this base URL instead of embedding it. The manifest store of each
+    /// output is written to a `.c2pa` file, to be uploaded to the base URL under the same name.
+    #[arg(long, value_name = "BASE")]
+    remote_manifest_url: Option<Url>,
+
+    /// Append a JSON line per signed file to this log, with the SHA-256 of the input and output,
+    /// the manifest label, the certificate serial number and the time.
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+
+    /// Print a JSON line per file (path, output, manifest label, duration, error) instead of text.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The outcome of signing one file, printed with `--output-format json`.
+#[derive(Serialize)]
+struct FileResult<'a> {
+    path: &'a Path,
+    output: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manifest_label: Option<String>,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<u8>,
+}
+
+/// A file to sign and where to write it.
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+/// Settings, manifest definition and signer shared by every file signed in one run.
+pub(crate) struct Signing {
+    settings: String,
+    manifest_definition: String,
+    signer: Box<dyn AsyncSigner>,
+    format: Option<String>,
+    sidecar: bool,
+    embed: bool,
+    remote_manifest_url: Option<Url>,
+    force: bool,
+    progress: Progress,
+    ingredients: Vec<(PathBuf, Relationship)>,
+    audit: Option<AuditLog>,
+}
+
+/// The `.c2pa` sidecar written next to an output file.
+fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("c2pa")
+}
+
+// Outputs are signed into this file and renamed, so they are never left half written.
+fn temp_path(output: &Path) -> PathBuf {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!(".{name}.tmp"))
+}
+
+impl Signing {
+    fn builder(&self) -> Result<Builder> {
+        let context = Context::new().with_settings(self.settings.as_str())?;
+        Ok(Builder::from_context(context).with_definition(self.manifest_definition.as_str())?)
+    }
+
+    async fn add_ingredients(&self, builder: &mut Builder) -> Result<()> {
+        for (path, relationship) in &self.ingredients {
+            let format = format_of(path, None)?;
+            let title = path.file_name().unwrap_or_default().to_string_lossy();
+            let ingredient = json!({ "title": title, "relationship": relationship });
+            builder
+                .add_ingredient_from_stream_async(ingredient, format, &mut File::open(path)?)
+                .await
+                .with_context(|| format!("ingredient {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    // Refuse to replace existing outputs without --force. Signing in place always replaces the input.
+    fn check_overwrite(&self, input: &Path, output: &Path) -> Result<()> {
+        if self.force || input == output {
+            return Ok(());
+        }
+        let asset = self.writes_asset().then(|| output.to_owned());
+        let sidecar = self.sidecar.then(|| sidecar_path(output));
+        if let Some(existing) = asset.into_iter().chain(sidecar).find(|x| x.exists()) {
+            bail!(
+                "{} already exists, use --force to overwrite it",
+                existing.display()
+            );
+        }
+        Ok(())
+    }
+
+    // Report what would be signed. The signer was already created, which checked the
+    // credentials and fetched the certificate chain.
+    fn dry_run(&self, jobs: &[Job]) -> Result<()> {
+        self.builder().context("invalid manifest definition")?;
+        for (path, _) in &self.ingredients {
+            format_of(path, None).context("invalid ingredient")?;
+        }
+        let mut failed = 0;
+        for job in jobs {
+            if job.input.as_os_str() == PIPE {
+                println!("would sign stdin -> {}", job.output.display());
+                continue;
+            }
+            let checked = self
+                .check_overwrite(&job.input, &job.output)
+                .and_then(|()| format_of(&job.input, self.format.as_deref()));
+            match checked {
+                Ok(format) => println!(
+                    "would sign {} ({format}) -> {}",
+                    job.input.display(),
+                    job.output.display()
+                ),
+                Err(err) => {
+                    failed += 1;
+                    eprintln!("cannot sign {err:#}");
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("{failed} of {} files can't be signed", jobs.len());
+        }
+        Ok(())
+    }
+
+    // The asset is unchanged unless the manifest, or a reference to a remote one, is embedded.
+    fn writes_asset(&self) -> bool {
+        self.embed || self.remote_manifest_url.is_some()
+    }
+
+    // Where the sidecar of an output is uploaded to for remote manifests.
+    fn remote_url(&self, output: &Path) -> Result<Option<Url>> {
+        let Some(base) = &self.remote_manifest_url else {
+            return Ok(None);
+        };
+        let sidecar = sidecar_path(output);
+        let name = sidecar
+            .file_name()
+            .and_then(|x| x.to_str())
+            .with_context(|| format!("{} has no file name", output.display()))?;
+        Ok(Some(base.join(name)?))
+    }
+
+    async fn sign_stream(
+        &self,
+        format: &str,
+        remote_url: Option<Url>,
+        bar: ProgressBar,
+        source: &mut (impl Read + Seek + Send),
+        destination: &mut (impl Read + Write + Seek + Send),
+    ) -> Result<Vec<u8>> {
+        let mut builder = self.builder()?;
+        self.add_ingredients(&mut builder).await?;
+        builder.set_no_embed(!self.embed);
+        if let Some(url) = remote_url {
+            builder.set_remote_url(url.as_str());
+        }
+        let signer = ProgressSigner {
+            signer: &*self.signer,
+            bar: bar.clone(),
+        };
+        let mut source = Tracked::new(source, bar.clone());
+        let mut destination = Tracked::new(destination, bar);
+        Ok(builder
+            .sign_async(&signer, format, &mut source, &mut destination)
+            .await?)
+    }
+
+    /// Sign `input` into `output` and return the manifest store.
+    pub(crate) async fn sign_file(&self, input: &Path, output: &Path) -> Result<Vec<u8>> {
+        let format = format_of(input, self.format.as_deref())?;
+        self.check_overwrite(input, output)?;
+        // Hashed first, the input is replaced when signing in place.
+        let input_sha256 = self
+            .audit
+            .as_ref()
+            .map(|_| audit::sha256(input))
+            .transpose()?;
+        let mut source = File::open(input)?;
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let remote_url = self.remote_url(output)?;
+        let bar = self.progress.bar(input, source.metadata()?.len());
+        let manifest = if self.writes_asset() {
+            let temp = temp_path(output);
+            let result = async {
+                let mut destination = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&temp)?;
+                let manifest = self
+                    .sign_stream(
+                        format,
+                        remote_url,
+                        bar.clone(),
+                        &mut source,
+                        &mut destination,
+                    )
+                    .await?;
+                // The input is still open when signing in place.
+                drop(source);
+                drop(destination);
+                fs::rename(&temp, output)?;
+                anyhow::Ok(manifest)
+            }
+            .await;
+            if result.is_err() {
+                _ = fs::remove_file(&temp);
+            }
+            result
+        } else {
+            let mut destination = Cursor::new(Vec::new());
+            self.sign_stream(format, None, bar.clone(), &mut source, &mut destination)
+                .await
+        };
+        bar.finish_and_clear();
+        let manifest = manifest?;
+        if self.sidecar {
+            fs::write(sidecar_path(output), &manifest)?;
+        }
+        if let (Some(audit), Some(input_sha256)) = (&self.audit, input_sha256) {
+            let label = self.label(input, output, &manifest).await;
+            let written = if self.writes_asset() {
+                output.to_owned()
+            } else {
+                sidecar_path(output)
+            };
+            audit.record(input, &input_sha256, &written, label.as_deref())?;
+        }
+        Ok(manifest)
+    }
+
+    // The label of the active manifest just signed, for the JSON results and the audit log.
+    async fn label(&self, input: &Path, output: &Path, manifest: &[u8]) -> Option<String> {
+        let asset = if self.writes_asset() { output } else { input };
+        let result = async {
+            let format = format_of(asset, self.format.as_deref())?;
+            let context = Context::new().with_settings(self.settings.as_str())?;
+            let reader = Reader::from_context(context)
+                .with_manifest_data_and_stream_async(manifest, format, &mut File::open(asset)?)
+                .await?;
+            anyhow::Ok(reader.active_label().map(str::to_owned))
+        }
+        .await;
+        result
+            .inspect_err(|err| {
+                log::warn!("Can't read the manifest of {}: {err:#}", asset.display())
+            })
+            .ok()
+            .flatten()
+    }
+
+    // Sign an asset downloaded from a URL and/or upload the signed asset to a SAS URL.
+    async fn sign_remote(&self, input: &str, output: &Path, limit: u64) -> Result<()> {
+        let download = match remote::url(input) {
+            Some(url) => Some(remote::download(&url, limit).await?),
+            None => None,
+        };
+        let source = download.as_deref().unwrap_or(Path::new(input));
+        let upload = output.to_str().and_then(remote::url);
+        let signed = upload.as_ref().map(remote::upload_path).transpose()?;
+        let destination = signed.as_deref().unwrap_or(output);
+        self.sign_file(source, destination).await?;
+        if let Some(url) = &upload {
+            let format = format_of(destination, self.format.as_deref())?;
+            remote::upload(url, destination, format).await?;
+            log::info!("Uploaded the signed file to {}", remote::redact(url));
+        }
+        Ok(())
+    }
+
+    // Sign from stdin and/or to stdout. Both are buffered in memory since signing seeks.
+    async fn pipe(&self, input: &str, output: &Path) -> Result<()> {
+        let mut source = Vec::new();
+        let declared = if input == PIPE {
+            io::stdin().read_to_end(&mut source)?;
+            self.format.as_deref()
+        } else {
+            source = fs::read(input)?;
+            self.format
+                .as_deref()
+                .or(Path::new(input).extension().and_then(|x| x.to_str()))
+        };
+        let header = &source[..source.len().min(SNIFF_LENGTH)];
+        let format = resolve_media_type(declared, header).with_context(|| input.to_owned())?;
+        if output.as_os_str() != PIPE {
+            self.check_overwrite(Path::new(input), output)?;
+        }
+        let remote_url = self.remote_url(output)?;
+        let mut destination = Cursor::new(Vec::new());
+        let manifest = self
+            .sign_stream(
+                format,
+                remote_url,
+                ProgressBar::hidden(),
+                &mut Cursor::new(source),
+                &mut destination,
+            )
+            .await?;
+        if output.as_os_str() == PIPE {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(if self.embed {
+                destination.get_ref()
+            } else {
+                &manifest
+            })?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        if self.writes_asset() {
+            fs::write(output, destination.into_inner())?;
+        }
+        if self.sidecar {
+            fs::write(sidecar_path(output), manifest)?;
+        }
+        Ok(())
+    }
+}
+
+// Extensions of the formats c2pa-rs can embed a manifest in.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "arw", "avi", "avif", "c2pa", "dng", "gif", "heic", "heif", "jpeg", "jpg", "m4a", "mov", "mp3",
+    "mp4", "nef", "pdf", "png", "svg", "tif", "tiff", "wav", "webp",
+];
+
+pub(crate) fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| SUPPORTED_EXTENSIONS.contains(&x.to_ascii_lowercase().as_str()))
+}
+
+/// The `--include` and `--exclude` globs for the files under input directories.
+pub(crate) struct Filter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl Filter {
+    /// Whether to sign a file, by its path relative to the input directory.
+    pub(crate) fn matches(&self, relative: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|x| x.matches_path(relative)))
+            && !self.exclude.iter().any(|x| x.matches_path(relative))
+    }
+}
+
+// Collect the supported files under a directory that pass the filter, relative to it.
+fn walk(root: &Path, dir: &Path, filter: &Filter, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(root, &path, filter, files)?;
+        } else if is_supported(&path) {
+            let relative = path.strip_prefix(root)?;
+            if filter.matches(relative) {
+                files.push(relative.to_path_buf());
+            }
+        } else {
+            log::warn!("Skipping {}, the format is not supported", path.display());
+        }
+    }
+    Ok(())
+}
+
+fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+// Expand the glob patterns, shells on Windows don't.
+pub(crate) fn expand(inputs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        if !is_pattern(input) {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+        let before = paths.len();
+        for entry in glob::glob(input).with_context(|| format!("invalid pattern {input}"))? {
+            let path = entry?;
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        if paths.len() == before {
+            log::warn!("No files match {input}");
+        }
+    }
+    Ok(paths)
+}
+
+// Jobs that replace each input with its signed version.
+fn plan_in_place(patterns: &[String], recursive: bool, filter: &Filter) -> Result<Vec<Job>> {
+    let mut files = Vec::new();
+    for input in expand(patterns)? {
+        if !input.is_dir() {
+            files.push(input);
+        } else if recursive {
+            let mut relative = Vec::new();
+            walk(&input, &input, filter, &mut relative)?;
+            files.extend(relative.into_iter().map(|file| input.join(file)));
+        } else {
+            bail!("{} is a directory, use --recursive", input.display());
+        }
+    }
+    Ok(files
+        .into_iter()
+        .map(|file| Job {
+            input: file.clone(),
+            output: file,
+        })
+        .collect())
+}
+
+// The deepest directory containing all the files, the current directory when they share none.
+fn common_parent<'a>(files: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let mut common: Option<Vec<Component>> = None;
+    for file in files {
+        let parent = file.parent().unwrap_or(Path::new(""));
+        let components = parent.components().collect::<Vec<_>>();
+        common = Some(match common {
+            None => components,
+            Some(common) => common
+                .into_iter()
+                .zip(components)
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    let common = common.unwrap_or_default();
+    if common.is_empty() {
+        PathBuf::from(".")
+    } else {
+        common.into_iter().collect()
+    }
+}
+
+fn plan(patterns: &[String], output: &Path, recursive: bool, filter: &Filter) -> Result<Vec<Job>> {
+    let inputs = expand(patterns)?;
+    let batch = inputs.len() > 1
+        || patterns.iter().any(|x| is_pattern(x))
+        || inputs.iter().any(|x| x.is_dir())
+        || output.is_dir();
+    if !batch {
+        return Ok(inputs
+            .into_iter()
+            .map(|input| Job {
+                input,
+                output: output.to_owned(),
+            })
+            .collect());
+    }
+
+    let mut outputs = HashSet::new();
+    let mut jobs = Vec::new();
+    let mut add = |input: PathBuf, output: PathBuf| {
+        if !outputs.insert(output.clone()) {
+            bail!(
+                "{} and another input would both be written to {}",
+                input.display(),
+                output.display()
+            );
+        }
+        jobs.push(Job { input, output });
+        Ok(())
+    };
+    for input in inputs {
+        if input.is_dir() {
+            if !recursive {
+                bail!("{} is a directory, use --recursive", input.display());
+            }
+            let mut files = Vec::new();
+            walk(&input, &input, filter, &mut files)?;
+            for file in files {
+                add(input.join(&file), output.join(file))?;
+            }
+            continue;
+        }
+        let name = input
+            .file_name()
+            .with_context(|| format!("{} is not a file", input.display()))?;
+        add(input.clone(), output.join(name))?;
+    }
+    Ok(jobs)
+}
+
+/// The manifest definition from the flag, the configuration file or the default, with the
+/// `--set` variables applied.
+pub(crate) fn manifest_definition(
+    global: &GlobalArgs,
+    path: Option<&Path>,
+    variables: &[(String, String)],
+) -> Result<String> {
+    let path = path.or(global.file.manifest_definition.as_deref());
+    let definition = if let Some(path) = path {
+        fs::read_to_string(path)?
+    } else {
+        DEFAULT_MANIFEST.to_owned()
+    };
+    template::apply(&definition, variables)
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: SignArgs) -> Result<()> {
+    let manifest_definition =
+        manifest_definition(global, args.manifest_definition.as_deref(), &args.set)?;
+    if args.relationship.len() > args.ingredient.len() {
+        bail!("--relationship is given more often than --ingredient");
+    }
+    let ingredients: Vec<_> = args
+        .ingredient
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let relationship = args.relationship.get(i).copied();
+            (
+                path.clone(),
+                relationship.unwrap_or(Relationship::ComponentOf),
+            )
+        })
+        .collect();
+    let parents = ingredients
+        .iter()
+        .filter(|(_, x)| *x == Relationship::ParentOf)
+        .count();
+    if parents > 1 {
+        bail!("only one ingredient can be the parentOf the signed file");
+    }
+    let patterns: Vec<String> = args.input.iter().chain(&args.files).cloned().collect();
+    let stdin = patterns.iter().any(|x| x == PIPE);
+    if stdin && patterns.len() > 1 {
+        bail!("stdin can't be combined with other inputs");
+    }
+    if stdin && args.in_place {
+        bail!("stdin can't be signed in place");
+    }
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None if stdin => PathBuf::from(PIPE),
+        None if args.in_place => PathBuf::new(),
+        None => bail!("--output is required"),
+    };
+    let stdout = output.as_os_str() == PIPE;
+    if stdout && args.sidecar && !args.no_embed {
+        bail!("--sidecar needs an output file, use --no-embed to write the manifest to stdout");
+    }
+    let remote_input = patterns.iter().any(|x| remote::url(x).is_some());
+    let upload = output.to_str().and_then(remote::url).is_some();
+    if remote_input || upload {
+        if patterns.len() > 1 {
+            bail!("a URL can't be combined with other inputs");
+        }
+        if args.in_place || args.watch || args.dry_run || stdout {
+            bail!("URLs can't be signed in place, to stdout, watched or dry run");
+        }
+        if upload && (args.sidecar || args.no_embed || args.remote_manifest_url.is_some()) {
+            bail!("uploading to a URL only writes the signed asset, not a sidecar");
+        }
+    }
+    let mut remote_manifest_url = args.remote_manifest_url.clone();
+    if let Some(url) = &mut remote_manifest_url {
+        if stdout {
+            bail!("--remote-manifest-url needs an output file for the manifest store");
+        }
+        if args.no_embed {
+            bail!("--remote-manifest-url can't be combined with --no-embed");
+        }
+        // Join file names to the base, not replace its last segment.
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+    }
+
+    if args.audit_log.is_some() && (stdin || stdout) {
+        bail!("--audit-log needs input and output files");
+    }
+    let signer = global.signer().await?;
+    let audit = match &args.audit_log {
+        Some(path) if !args.dry_run => Some(AuditLog::open(path, &*signer)?),
+        _ => None,
+    };
+
+    let signing = Signing {
+        settings: global.settings()?,
+        manifest_definition,
+        signer,
+        format: args.format.clone(),
+        sidecar: args.sidecar || args.no_embed || remote_manifest_url.is_some(),
+        embed: !args.no_embed && remote_manifest_url.is_none(),
+        remote_manifest_url,
+        force: args.force,
+        progress: Progress::new(args.quiet),
+        ingredients,
+        audit,
+    };
+
+    let filter = Filter {
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+    };
+    if args.watch {
+        let [input] = patterns.as_slice() else {
+            bail!("--watch takes a single input directory");
+        };
+        let jobs = args.jobs.into();
+        return watch(
+            Path::new(input),
+            &output,
+            args.recursive,
+            &filter,
+            &signing,
+            jobs,
+        )
+        .await;
+    }
+
+    if remote_input || upload {
+        let limit = args.max_download_mb * 1024 * 1024;
+        return signing.sign_remote(&patterns[0], &output, limit).await;
+    }
+
+    if stdin || stdout {
+        let [input] = patterns.as_slice() else {
+            bail!("stdout takes a single input");
+        };
+        if args.dry_run {
+            let job = Job {
+                input: PathBuf::from(input),
+                output,
+            };
+            return signing.dry_run(&[job]);
+        }
+        return signing.pipe(input, &output).await;
+    }
+
+    let mut jobs = if args.in_place {
+        plan_in_place(&patterns, args.recursive, &filter)?
+    } else {
+        plan(&patterns, &output, args.recursive, &filter)?
+    };
+    if jobs.is_empty() {
+        bail!("No files to sign");
+    }
+    // Batches record the files they signed, so an interrupted run can be resumed.
+    let state_dir = if args.in_place && jobs.len() > 1 {
+        Some(common_parent(jobs.iter().map(|x| x.output.as_path())))
+    } else if args.in_place {
+        None
+    } else if jobs.iter().any(|x| x.output != output) {
+        Some(output.clone())
+    } else {
+        None
+    };
+    if args.resume && state_dir.is_none() {
+        bail!("--resume needs a batch of files signed into a directory or in place");
+    }
+    if args.dry_run {
+        return signing.dry_run(&jobs);
+    }
+    let mut state = state_dir
+        .map(|dir| State::open(&dir, args.resume))
+        .transpose()?;
+    if let Some(state) = &state {
+        let total = jobs.len();
+        jobs.retain(|x| !state.is_done(&x.output));
+        if jobs.len() < total {
+            eprintln!(
+                "skipping {} files signed by a previous run",
+                total - jobs.len()
+            );
+        }
+    }
+    if jobs.is_empty() {
+        return state.map_or(Ok(()), State::finish);
+    }
+
+    let json = args.output_format == OutputFormat::Json;
+    if let ([job], false, None) = (jobs.as_slice(), json, &state) {
+        signing.sign_file(&job.input, &job.output).await?;
+        log::info!("Successfully signed the file.");
+        return Ok(());
+    }
+
+    // All jobs share the signer, so the certificate chain and access token are fetched once.
+    let signing = &signing;
+    let mut results = stream::iter(&jobs)
+        .map(|job| async move {
+            let start = Instant::now();
+            let result = match signing.sign_file(&job.input, &job.output).await {
+                Ok(manifest) if json => Ok(signing.label(&job.input, &job.output, &manifest).await),
+                Ok(_) => Ok(None),
+                Err(err) => Err(err),
+            };
+            (job, start.elapsed(), result)
+        })
+        .buffer_unordered(args.jobs.into());
+    let mut failed = 0;
+    let mut exit: Option<Exit> = None;
+    while let Some((job, duration, result)) = results.next().await {
+        if let Err(err) = &result {
+            failed += 1;
+            let code = Exit::of(err);
+            exit = Some(exit.map_or(code, |x| x.combine(code)));
+        } else if let Some(state) = &mut state {
+            state.complete(&job.output)?;
+        }
+        if json {
+            report(job, duration, &result)?;
+            continue;
+        }
+        match result {
+            Ok(_) => println!("signed {} -> {}", job.input.display(), job.output.display()),
+            Err(err) => eprintln!("failed {}: {err:#}", job.input.display()),
+        }
+    }
+    if !json {
+        println!("{} signed, {failed} failed", jobs.len() - failed);
+    }
+    if let Some(exit) = exit {
+        return Err(Failed {
+            exit,
+            message: format!("{failed} of {} files failed to sign", jobs.len()),
+        }
+        .into());
+    }
+    state.map_or(Ok(()), State::finish)
+}
+
+fn report(job: &Job, duration: Duration, result: &Result<Option<String>>) -> Result<()> {
+    let (manifest_label, error) = match result {
+        Ok(label) => (label.clone(), None),
+        Err(err) => (None, Some(err)),
+    };
+    let line = FileResult {
+        path: &job.input,
+        output: &job.output,
+        manifest_label,
+        duration_ms: duration.as_millis(),
+        error: error.map(|err| format!("{err:#}")),
+        exit_code: error.map(|err| Exit::of(err) as u8),
+    };
+    println!("{}", serde_json::to_string(&line)?);
+    Ok(())
+}
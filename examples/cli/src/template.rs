@@ -0,0 +1,123 @@
+use anyhow::{Result, bail};
+use serde_json::{Map, Value, json};
+use std::collections::HashSet;
+
+const CREATIVE_WORK: &str = "stds.schema-org.CreativeWork";
+
+/// Parse a `--set key=value` flag.
+pub(crate) fn parse_variable(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("expected key=value, got {s}")),
+    }
+}
+
+/// Substitute `{{key}}` placeholders in the manifest definition. The `title`, `author` and
+/// `claim_generator` keys set those fields directly when the definition has no placeholder for them.
+pub(crate) fn apply(definition: &str, variables: &[(String, String)]) -> Result<String> {
+    if variables.is_empty() && !definition.contains("{{") {
+        return Ok(definition.to_owned());
+    }
+    let mut definition: Value = serde_json::from_str(definition)?;
+    let mut used = HashSet::new();
+    substitute(&mut definition, variables, &mut used);
+
+    let Some(fields) = definition.as_object_mut() else {
+        bail!("the manifest definition must be a JSON object");
+    };
+    for (key, value) in variables
+        .iter()
+        .filter(|(key, _)| !used.contains(key.as_str()))
+    {
+        match key.as_str() {
+            "title" => {
+                fields.insert("title".to_owned(), value.as_str().into());
+            }
+            "author" => set_author(fields, value),
+            "claim_generator" => {
+                let (name, version) = match value.split_once('/') {
+                    Some((name, version)) => (name, Some(version)),
+                    None => (value.as_str(), None),
+                };
+                let mut info = json!({ "name": name });
+                if let Some(version) = version {
+                    info["version"] = version.into();
+                }
+                fields.insert("claim_generator_info".to_owned(), json!([info]));
+            }
+            _ => bail!("--set {key}: the manifest definition has no {{{{{key}}}}} placeholder"),
+        }
+    }
+
+    if let Some(placeholder) = unresolved(&definition) {
+        bail!("the manifest definition uses {placeholder}, set it with --set");
+    }
+    Ok(definition.to_string())
+}
+
+fn substitute<'a>(
+    value: &mut Value,
+    variables: &'a [(String, String)],
+    used: &mut HashSet<&'a str>,
+) {
+    match value {
+        Value::String(text) => {
+            for (key, replacement) in variables {
+                let placeholder = format!("{{{{{key}}}}}");
+                if text.contains(&placeholder) {
+                    *text = text.replace(&placeholder, replacement);
+                    used.insert(key.as_str());
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                substitute(item, variables, used);
+            }
+        }
+        Value::Object(fields) => {
+            for item in fields.values_mut() {
+                substitute(item, variables, used);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The author goes in the schema.org CreativeWork assertion, which is added if missing.
+fn set_author(fields: &mut Map<String, Value>, author: &str) {
+    let assertions = fields.entry("assertions").or_insert_with(|| json!([]));
+    let Some(assertions) = assertions.as_array_mut() else {
+        return;
+    };
+    let index = match assertions.iter().position(|x| x["label"] == CREATIVE_WORK) {
+        Some(index) => index,
+        None => {
+            assertions.push(json!({
+                "label": CREATIVE_WORK,
+                "data": {
+                    "@context": "https://schema.org",
+                    "@type": "CreativeWork"
+                },
+                "kind": "Json"
+            }));
+            assertions.len() - 1
+        }
+    };
+    assertions[index]["data"]["author"] = json!([{ "@type": "Person", "name": author }]);
+}
+
+fn unresolved(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => {
+            let start = text.find("{{")?;
+            let end = text[start..].find("}}")?;
+            Some(text[start..start + end + 2].to_owned())
+        }
+        Value::Array(items) => items.iter().find_map(unresolved),
+        Value::Object(fields) => fields.values().find_map(unresolved),
+        _ => None,
+    }
+}
@@ -0,0 +1,39 @@
+use anyhow::Result;
+use c2pa_azure::{
+    ReportFormat, VerificationResult, VerifyMode, VerifySettings, render_report,
+    verify_file_with_settings,
+};
+use clap::Args;
+use std::{fs::File, path::PathBuf};
+
+use crate::{GlobalArgs, format_of};
+
+#[derive(Args, Debug)]
+pub(crate) struct VerifyArgs {
+    file: PathBuf,
+
+    /// Print a Markdown or HTML report instead of the manifest store JSON.
+    #[arg(short, long)]
+    report: Option<ReportFormat>,
+
+    /// Fail unless the manifest is valid and trusted.
+    #[arg(long)]
+    strict: bool,
+}
+
+pub(crate) async fn run(global: &GlobalArgs, args: VerifyArgs) -> Result<()> {
+    let mut settings = VerifySettings::from_toml(&global.settings()?)?;
+    if args.strict {
+        settings.mode = VerifyMode::Strict;
+    }
+    let file = File::open(&args.file)?;
+    let reader = verify_file_with_settings(format_of(&args.file, None)?, file, &settings).await?;
+    match args.report {
+        Some(format) => print!(
+            "{}",
+            render_report(&VerificationResult::from_reader(&reader)?, format)
+        ),
+        None => println!("{}", reader.json()),
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use anyhow::{Result, bail};
+use futures::{StreamExt, stream::FuturesUnordered};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+use crate::sign::{Filter, Signing, is_supported};
+
+// A file is signed once it has not changed for this long, so partial writes are skipped.
+const QUIET_PERIOD: Duration = Duration::from_secs(1);
+
+/// Sign files as they appear in `input` into `output` until the process is stopped, up to
+/// `jobs` at a time.
+pub(crate) async fn watch(
+    input: &Path,
+    output: &Path,
+    recursive: bool,
+    filter: &Filter,
+    signing: &Signing,
+    jobs: usize,
+) -> Result<()> {
+    if !input.is_dir() {
+        bail!(
+            "--watch needs an input directory, {} is not",
+            input.display()
+        );
+    }
+    let input = input.canonicalize()?;
+    std::fs::create_dir_all(output)?;
+    let output = output.canonicalize()?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    _ = sender.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(err) => log::warn!("Error watching files: {err}"),
+        })?;
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(&input, mode)?;
+    log::info!(
+        "Watching {} and signing into {}",
+        input.display(),
+        output.display()
+    );
+
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    // Files that are ready, waiting for one of the `jobs` slots, and those being signed.
+    let mut queue: VecDeque<(PathBuf, PathBuf)> = VecDeque::new();
+    let mut queued: HashSet<PathBuf> = HashSet::new();
+    let mut running = FuturesUnordered::new();
+    let mut interval = tokio::time::interval(QUIET_PERIOD / 2);
+    loop {
+        while running.len() < jobs
+            && let Some((path, target)) = queue.pop_front()
+        {
+            running.push(async move {
+                let result = signing.sign_file(&path, &target).await;
+                (path, target, result)
+            });
+        }
+        tokio::select! {
+            Some(path) = receiver.recv() => {
+                // Don't sign our own output when it is inside the watched directory.
+                if path.is_file() && is_supported(&path) && !path.starts_with(&output) {
+                    pending.insert(path, Instant::now());
+                }
+            }
+            Some((path, target, result)) = running.next(), if !running.is_empty() => {
+                queued.remove(&path);
+                match result {
+                    Ok(_) => println!("signed {} -> {}", path.display(), target.display()),
+                    Err(err) => eprintln!("failed {}: {err:#}", path.display()),
+                }
+            }
+            _ = interval.tick() => {
+                // A file changed again while it is queued or signed waits until it is done,
+                // so it is never signed twice at once.
+                let ready: Vec<_> = pending
+                    .iter()
+                    .filter(|(path, changed)| {
+                        changed.elapsed() >= QUIET_PERIOD && !queued.contains(*path)
+                    })
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in ready {
+                    pending.remove(&path);
+                    let Ok(relative) = path.strip_prefix(&input) else {
+                        continue;
+                    };
+                    if !filter.matches(relative) {
+                        continue;
+                    }
+                    let target = output.join(relative);
+                    queued.insert(path.clone());
+                    queue.push_back((path, target));
+                }
+            }
+        }
+    }
+}
@@ -8,19 +8,19 @@ use std::{
 };
 
 use azure_core::{
-    credentials::TokenCredential,
     error::ErrorKind,
     http::{Body, RequestContent, headers::HeaderName},
     stream::SeekableStream,
 };
-use azure_identity::{
-    AzureCliCredential, ManagedIdentityCredential, ManagedIdentityCredentialOptions, UserAssignedId,
-};
 use azure_storage_blob::{
     BlobClient, clients::BlobContainerClient, models::BlobClientAcquireLeaseResultHeaders,
 };
 use c2pa::{AsyncSigner, Builder, Context, ManifestDefinition};
-use c2pa_azure::{Envconfig, SigningOptions, TrustedSigner};
+#[cfg(feature = "dev-signer")]
+use c2pa_azure::{DevSigner, DevSignerOptions};
+use c2pa_azure::{
+    Envconfig, SigningOptions, TrustedSigner, credential_for_environment, is_circuit_open,
+};
 use futures::{StreamExt, io::AsyncRead};
 use tokio::{
     fs::File,
@@ -160,6 +160,14 @@ async fn process_blobs(
         let output_blob = output_container.blob_client(name);
         let result = process_blob(input_blob, output_blob, builder, signer).await;
         if let Err(err) = result {
+            if err
+                .downcast_ref::<c2pa::Error>()
+                .is_some_and(is_circuit_open)
+            {
+                // Leave the remaining blobs for the next run instead of burning retries.
+                log::warn!("Trusted Signing is unavailable, pausing blob processing: {err}");
+                break;
+            }
             log::error!("Error processing blob: {err:?}");
         } else {
             log::info!("Blob {name} processed successfully");
@@ -168,20 +176,25 @@ async fn process_blobs(
     Ok(())
 }
 
+// Sign with the local development certificate when DEV_SIGNER_CERTIFICATE is set.
+#[cfg(feature = "dev-signer")]
+fn dev_signer() -> anyhow::Result<Option<Box<dyn AsyncSigner>>> {
+    if env::var("DEV_SIGNER_CERTIFICATE").is_err() {
+        return Ok(None);
+    }
+    let signer = DevSigner::new(&DevSignerOptions::init_from_env()?)?;
+    Ok(Some(Box::new(signer)))
+}
+
+#[cfg(not(feature = "dev-signer"))]
+fn dev_signer() -> anyhow::Result<Option<Box<dyn AsyncSigner>>> {
+    Ok(None)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
-    let credential: Arc<dyn TokenCredential> = if cfg!(debug_assertions) {
-        AzureCliCredential::new(None)?
-    } else {
-        let options = ManagedIdentityCredentialOptions {
-            user_assigned_id: Some(UserAssignedId::ClientId(
-                env::var("AZURE_CLIENT_ID").expect("missing AZURE_CLIENT_ID"),
-            )),
-            ..Default::default()
-        };
-        ManagedIdentityCredential::new(Some(options))?
-    };
+    let credential = credential_for_environment()?;
 
     let manifest_definition = env::var("MANIFEST_DEFINITION").ok();
     let manifest_definition = if let Some(manifest) = manifest_definition {
@@ -209,10 +222,15 @@ async fn main() -> Result<(), anyhow::Error> {
     let output_container =
         BlobContainerClient::new(output_container_url, Some(credential.clone()), None)?;
 
-    let options = SigningOptions::init_from_env()?;
-    let signer = TrustedSigner::new(credential, options).await?;
+    let signer: Box<dyn AsyncSigner> = match dev_signer()? {
+        Some(signer) => signer,
+        None => {
+            let options = SigningOptions::init_from_env()?;
+            Box::new(TrustedSigner::new(credential, options).await?)
+        }
+    };
     let context = Context::new();
     let mut builder = Builder::from_context(context).with_definition(manifest_definition)?;
-    process_blobs(input_container, output_container, &mut builder, &signer).await?;
+    process_blobs(input_container, output_container, &mut builder, &*signer).await?;
     Ok(())
 }
@@ -5,19 +5,35 @@ use azure_core::{
     credentials::TokenCredential,
     error::ErrorKind,
     http::{
-        ClientOptions, Context, ExponentialRetryOptions, Method, Pipeline, RawResponse, Request,
-        Response, RetryOptions, Url, UserAgentOptions,
+        ClientOptions, Context, Method, Pipeline, RawResponse, Request, Response, Url,
+        UserAgentOptions, policies::Policy,
     },
     sleep::sleep,
     time::Duration,
 };
 use bytes::Bytes;
 use c2pa::SigningAlg;
-use std::sync::Arc;
+use serde::{
+    Deserialize,
+    de::{
+        IntoDeserializer,
+        value::{Error as ValueError, StrDeserializer},
+    },
+};
+use std::{future::Future, sync::Arc, time::Instant};
 
-use crate::{auth::AuthorizationPolicy, p7b::CertificateChain};
+use crate::{
+    auth::{AuthorizationPolicy, TokenSettings},
+    circuit_breaker::{CircuitBreaker, CircuitBreakerSettings, CircuitOpenError},
+    p7b::{CertificateChain, LeafSelector},
+    retry::RetrySettings,
+    telemetry::{TelemetryHandler, TelemetryPolicy},
+    trace::TraceParent,
+};
 const DEFAULT_API_VERSION: &str = "2022-06-15-preview";
 const DEFAULT_SCOPE: &str = "https://codesigning.azure.net/.default";
+const CLIENT_REQUEST_ID: &str = "x-ms-client-request-id";
+const TRACEPARENT: &str = "traceparent";
 
 #[derive(Clone, Debug)]
 pub struct TrustedSigningClientOptions {
@@ -27,6 +43,18 @@ pub struct TrustedSigningClientOptions {
     pub algorithm: SigningAlg,
     pub client_options: ClientOptions,
     pub scope: String,
+    /// Optional hook invoked with the metadata of every request sent to the service.
+    pub telemetry: Option<Arc<dyn TelemetryHandler>>,
+    pub circuit_breaker: CircuitBreakerSettings,
+    pub token: TokenSettings,
+    /// Keep the self-signed root at the end of the returned certificate chain.
+    pub include_root: bool,
+    /// Selects the signing profile's leaf when the service returns more than one chain.
+    pub leaf: Option<LeafSelector>,
+    /// Timeout of each request attempt, none by default.
+    pub request_timeout: Option<std::time::Duration>,
+    /// How long a sign operation may take, including polling for its result.
+    pub sign_timeout: std::time::Duration,
 }
 
 impl TrustedSigningClientOptions {
@@ -41,14 +69,17 @@ impl TrustedSigningClientOptions {
             algorithm,
             scope: DEFAULT_SCOPE.to_owned(),
             client_options: ClientOptions {
-                retry: RetryOptions::exponential(ExponentialRetryOptions {
-                    max_retries: 5,
-                    max_delay: Duration::seconds(10),
-                    ..Default::default()
-                }),
+                retry: RetrySettings::default().retry_options(),
                 user_agent,
                 ..Default::default()
             },
+            telemetry: None,
+            circuit_breaker: CircuitBreakerSettings::default(),
+            token: TokenSettings::default(),
+            include_root: false,
+            leaf: None,
+            request_timeout: None,
+            sign_timeout: RetrySettings::default().sign_timeout(),
         }
     }
 }
@@ -58,6 +89,7 @@ pub struct TrustedSigningClient {
     endpoint: Url,
     options: TrustedSigningClientOptions,
     pipeline: Pipeline,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
 }
 
 #[derive(serde::Serialize)]
@@ -75,6 +107,9 @@ enum Status {
     TimedOut,
     NotFound,
     Running,
+    /// A status added to the service after this client was written.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(serde::Deserialize)]
@@ -82,11 +117,20 @@ enum Status {
 #[allow(dead_code)]
 struct SigningStatus {
     pub operation_id: String,
-    pub status: Status,
+    /// Raw status string as returned by the service, see [`SigningStatus::status`].
+    pub status: String,
     pub signature: Option<String>,
     pub signing_certificate: Option<String>,
 }
 
+impl SigningStatus {
+    fn status(&self) -> Status {
+        let deserializer: StrDeserializer<'_, ValueError> =
+            self.status.as_str().into_deserializer();
+        Status::deserialize(deserializer).unwrap_or(Status::Unknown)
+    }
+}
+
 impl SigningRequest {
     pub fn new(alg: SigningAlg, digest: &[u8]) -> Self {
         Self {
@@ -104,72 +148,183 @@ impl TrustedSigningClient {
     ) -> Self {
         let client_options = options.client_options.clone();
         let scope = options.scope.clone();
+        let per_try_policies: Vec<Arc<dyn Policy>> = options
+            .telemetry
+            .iter()
+            .map(|handler| Arc::new(TelemetryPolicy::new(handler.clone())) as Arc<dyn Policy>)
+            .chain(RetrySettings::timeout_policies(options.request_timeout))
+            .collect();
+        let circuit_breaker = CircuitBreaker::new(&options.circuit_breaker).map(Arc::new);
         Self {
             endpoint,
             options,
+            circuit_breaker,
             pipeline: Pipeline::new(
                 option_env!("CARGO_PKG_NAME"),
                 option_env!("CARGO_PKG_VERSION"),
                 client_options,
-                vec![Arc::new(AuthorizationPolicy::new(credential, scope))],
-                vec![],
+                vec![Arc::new(AuthorizationPolicy::new(
+                    credential,
+                    scope,
+                    &options.token,
+                ))],
+                per_try_policies,
                 None,
             ),
         }
     }
 
-    pub async fn get_certificatechain(&self) -> Result<Vec<Vec<u8>>> {
+    pub fn is_circuit_open(&self) -> bool {
+        self.check_circuit().is_err()
+    }
+
+    pub fn check_circuit(&self) -> std::result::Result<(), CircuitOpenError> {
+        self.circuit_breaker
+            .as_ref()
+            .map_or(Ok(()), |breaker| breaker.status())
+    }
+
+    // Run the operation through the circuit breaker (if configured), failing fast while it is open.
+    async fn guarded<T>(&self, operation: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(breaker) = &self.circuit_breaker else {
+            return operation.await;
+        };
+        breaker
+            .check()
+            .map_err(|err| azure_core::Error::new(ErrorKind::Other, err))?;
+        let result = operation.await;
+        match &result {
+            Ok(_) => breaker.record_success(),
+            Err(_) => breaker.record_failure(),
+        }
+        result
+    }
+
+    /// Fetch the certificate chain. The optional correlation id is sent as `x-ms-client-request-id`,
+    /// the trace id of the current [`TraceParent`] otherwise.
+    pub async fn get_certificatechain(
+        &self,
+        correlation_id: Option<&str>,
+    ) -> Result<CertificateChain> {
+        let trace = TraceParent::current();
+        let correlation_id = correlation_id.or(trace.as_ref().map(TraceParent::trace_id));
+        self.guarded(self.fetch_certificatechain(correlation_id))
+            .await
+            .map_err(|err| Self::correlate(err, correlation_id))
+    }
+
+    /// Sign the digest. The optional correlation id, or the trace id of the current
+    /// [`TraceParent`], is sent as `x-ms-client-request-id` on the sign request and every status
+    /// poll.
+    pub async fn sign(&self, data: &[u8], correlation_id: Option<&str>) -> Result<Vec<u8>> {
+        let trace = TraceParent::current();
+        let correlation_id = correlation_id.or(trace.as_ref().map(TraceParent::trace_id));
+        self.guarded(self.try_sign(data, correlation_id))
+            .await
+            .map_err(|err| Self::correlate(err, correlation_id))
+    }
+
+    fn correlate(err: azure_core::Error, correlation_id: Option<&str>) -> azure_core::Error {
+        match correlation_id {
+            Some(id) => {
+                let kind = err.kind().clone();
+                azure_core::Error::with_error(kind, err, format!("correlation id: {id}"))
+            }
+            None => err,
+        }
+    }
+
+    fn new_request(url: Url, method: Method, correlation_id: Option<&str>) -> Request {
+        let mut request = Request::new(url, method);
+        if let Some(id) = correlation_id {
+            request.insert_header(CLIENT_REQUEST_ID, id.to_owned());
+        }
+        if let Some(trace) = TraceParent::current() {
+            request.insert_header(TRACEPARENT, trace.to_string());
+        }
+        request
+    }
+
+    async fn fetch_certificatechain(
+        &self,
+        correlation_id: Option<&str>,
+    ) -> Result<CertificateChain> {
         let url = self.endpoint.join(&format!(
             "/codesigningaccounts/{}/certificateprofiles/{}/sign/certchain?api-version={}",
             self.options.account, self.options.certificate_profile, self.options.api_version
         ))?;
         let context = Context::new();
-        let mut request = Request::new(url, Method::Get);
+        let mut request = Self::new_request(url, Method::Get, correlation_id);
         request.insert_header("accept", "application/pkcs7-mime");
         let response: RawResponse = self.pipeline.send(&context, &mut request, None).await?;
         let body = response.into_body();
         let bytes = Bytes::from(body);
-        let cert = CertificateChain::from_cert_chain(bytes);
-        let pem = cert
-            .get_pem_certificates()
+        let mut cert = CertificateChain::from_cert_chain(bytes)
+            .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))?
+            .with_root(self.options.include_root)
+            .with_leaf(self.options.leaf.clone());
+        cert.complete_chain()
+            .await
+            .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))?;
+        cert.validate()
             .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))?;
-        Ok(pem)
+        Ok(cert)
     }
 
-    pub async fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+    async fn try_sign(&self, data: &[u8], correlation_id: Option<&str>) -> Result<Vec<u8>> {
         let url = self.endpoint.join(&format!(
             "/codesigningaccounts/{}/certificateprofiles/{}/sign?api-version={}",
             self.options.account, self.options.certificate_profile, self.options.api_version
         ))?;
         let context = Context::new();
-        let mut request = Request::new(url, Method::Post);
+        let mut request = Self::new_request(url, Method::Post, correlation_id);
         request.insert_header("content-type", "application/json");
         let data = SigningRequest::new(self.options.algorithm, data);
         request.set_json(&data)?;
 
-        for _ in 0..5 {
+        let started = Instant::now();
+        loop {
             let response: Response<SigningStatus> = self
                 .pipeline
                 .send(&context, &mut request, None)
                 .await?
                 .into();
             let status: SigningStatus = response.into_body().json()?;
+            let correlation = correlation_id.unwrap_or("none");
             log::info!(
-                "Signing operation: {}, status: {:?}",
+                "Signing operation: {}, status: {}, correlation id: {correlation}",
                 status.operation_id,
                 status.status
             );
-            if status.status == Status::Succeeded {
-                log::info!(
-                    "Signing request succeeded operation: {}",
-                    status.operation_id
-                );
-                let signature = base64::decode(status.signature.unwrap())?;
-                return Ok(signature);
-            } else if status.status != Status::InProgress {
-                return Err(azure_core::Error::new(
+            match status.status() {
+                Status::Succeeded => {
+                    log::info!(
+                        "Signing request succeeded operation: {}, correlation id: {correlation}",
+                        status.operation_id
+                    );
+                    let signature = base64::decode(status.signature.unwrap())?;
+                    return Ok(signature);
+                }
+                Status::InProgress => {}
+                Status::Unknown => log::warn!(
+                    "Signing operation: {} returned unknown status: {}, polling again",
+                    status.operation_id,
+                    status.status
+                ),
+                _ => {
+                    return Err(azure_core::Error::new(
+                        ErrorKind::Other,
+                        format!("Signing request failed with status: {}", status.status),
+                    ));
+                }
+            }
+            if started.elapsed() >= self.options.sign_timeout {
+                return Err(azure_core::Error::with_message(
                     ErrorKind::Other,
-                    format!("Signing request failed with status: {:?}", status.status),
+                    format!(
+                        "Signing request did not complete within {:?}",
+                        self.options.sign_timeout
+                    ),
                 ));
             }
             sleep(Duration::milliseconds(250)).await;
@@ -180,12 +335,38 @@ impl TrustedSigningClient {
                 status.operation_id,
                 self.options.api_version,
             ))?;
-            request = Request::new(url, Method::Get);
+            request = Self::new_request(url, Method::Get, correlation_id);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::rejection;
 
-        Err(azure_core::Error::new(
+    #[test]
+    fn test_unknown_status_is_tolerated() {
+        let status: SigningStatus =
+            serde_json::from_str(r#"{"operationId":"1","status":"Queued"}"#).unwrap();
+        assert_eq!(status.status(), Status::Unknown);
+        assert_eq!(status.status, "Queued");
+
+        let status: SigningStatus =
+            serde_json::from_str(r#"{"operationId":"1","status":"Succeeded"}"#).unwrap();
+        assert_eq!(status.status(), Status::Succeeded);
+    }
+
+    #[test]
+    fn test_rejection_survives_correlation() {
+        let err = azure_core::Error::new(
             ErrorKind::Other,
-            "Signing request did not succeed after 5 iterations".to_owned(),
-        ))
+            CircuitOpenError {
+                retry_after: std::time::Duration::from_secs(5),
+            },
+        );
+        let err = TrustedSigningClient::correlate(err, Some("1234"));
+        let rejected = rejection(&err).unwrap();
+        assert_eq!(rejected.retry_after.as_secs(), 5);
     }
 }
@@ -1,25 +1,286 @@
 use async_trait::async_trait;
 use azure_core::{
-    credentials::TokenCredential,
+    async_runtime::get_async_runtime,
+    credentials::{AccessToken, Secret, TokenCredential, TokenRequestOptions},
+    error::ErrorKind,
     http::{
         Context, Request,
         policies::{Policy, PolicyResult},
     },
+    sleep::sleep,
+    time::{Duration, OffsetDateTime},
+};
+use envconfig::Envconfig;
+use futures::lock::Mutex as AsyncMutex;
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
 };
-use std::sync::Arc;
 
-#[derive(Debug, Clone)]
-pub struct AuthorizationPolicy {
+use crate::retry::with_jitter;
+
+// Delay before retrying a failed background refresh, or refreshing again a token that is
+// already within the refresh skew when acquired.
+const REFRESH_RETRY_DELAY: Duration = Duration::seconds(30);
+
+#[derive(Clone, Debug, Envconfig)]
+pub struct TokenSettings {
+    /// Tokens expiring within this many seconds are refreshed before use.
+    #[envconfig(from = "TOKEN_REFRESH_SKEW_SECS", default = "300")]
+    pub refresh_skew_secs: i64,
+    /// Refresh tokens on a background task before they expire.
+    #[envconfig(from = "TOKEN_BACKGROUND_REFRESH", default = "false")]
+    pub background_refresh: bool,
+    /// Retries for transient token acquisition failures (429, 5xx, timeouts).
+    #[envconfig(from = "TOKEN_MAX_RETRIES", default = "3")]
+    pub max_retries: u32,
+    /// Base delay between token acquisition retries, doubled on each attempt and jittered.
+    #[envconfig(from = "TOKEN_RETRY_DELAY_MS", default = "500")]
+    pub retry_delay_ms: u64,
+}
+
+impl Default for TokenSettings {
+    fn default() -> Self {
+        Self {
+            refresh_skew_secs: 300,
+            background_refresh: false,
+            max_retries: 3,
+            retry_delay_ms: 500,
+        }
+    }
+}
+
+// Walk the error chain looking for a throttled, server side or timed out request.
+fn is_retryable(err: &azure_core::Error) -> bool {
+    let mut current: Option<&(dyn Error + 'static)> = Some(err);
+    while let Some(err) = current {
+        if let Some(err) = err.downcast_ref::<azure_core::Error>() {
+            match err.kind() {
+                ErrorKind::HttpResponse { status, .. } => {
+                    let status = u16::from(*status);
+                    return status == 408 || status == 429 || status >= 500;
+                }
+                ErrorKind::Io => return true,
+                _ => {}
+            }
+        }
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            return matches!(
+                err.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionReset
+            );
+        }
+        current = err.source();
+    }
+    false
+}
+
+/// Future returned by a [`TokenProvider::Callback`].
+pub type TokenFuture = Pin<Box<dyn Future<Output = azure_core::Result<AccessToken>> + Send>>;
+
+/// Callback invoked with the requested scope to obtain a bearer token.
+pub type TokenCallback = Arc<dyn Fn(String) -> TokenFuture + Send + Sync>;
+
+/// Source of the bearer tokens sent to Trusted Signing.
+/// Use `Static` or `Callback` when tokens are brokered outside the process (APIM, sidecars)
+/// instead of implementing [`TokenCredential`].
+#[derive(Clone)]
+pub enum TokenProvider {
+    Credential(Arc<dyn TokenCredential>),
+    /// A pre-fetched token, optionally with its expiry.
+    Static {
+        token: String,
+        expires_on: Option<OffsetDateTime>,
+    },
+    Callback(TokenCallback),
+}
+
+impl fmt::Debug for TokenProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Credential(credential) => f.debug_tuple("Credential").field(credential).finish(),
+            Self::Static { expires_on, .. } => f
+                .debug_struct("Static")
+                .field("expires_on", expires_on)
+                .finish_non_exhaustive(),
+            Self::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+impl From<Arc<dyn TokenCredential>> for TokenProvider {
+    fn from(credential: Arc<dyn TokenCredential>) -> Self {
+        Self::Credential(credential)
+    }
+}
+
+impl TokenProvider {
+    pub fn from_token(token: impl Into<String>) -> Self {
+        Self::Static {
+            token: token.into(),
+            expires_on: None,
+        }
+    }
+
+    pub fn from_callback<F, Fut>(callback: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = azure_core::Result<AccessToken>> + Send + 'static,
+    {
+        Self::Callback(Arc::new(move |scope| Box::pin(callback(scope))))
+    }
+
+    pub fn into_credential(self) -> Arc<dyn TokenCredential> {
+        match self {
+            Self::Credential(credential) => credential,
+            provider => Arc::new(ProvidedTokenCredential(provider)),
+        }
+    }
+}
+
+// Adapts a static or callback token provider to the TokenCredential trait.
+#[derive(Debug)]
+struct ProvidedTokenCredential(TokenProvider);
+
+#[async_trait]
+impl TokenCredential for ProvidedTokenCredential {
+    async fn get_token(
+        &self,
+        scopes: &[&str],
+        options: Option<TokenRequestOptions<'_>>,
+    ) -> azure_core::Result<AccessToken> {
+        match &self.0 {
+            TokenProvider::Credential(credential) => credential.get_token(scopes, options).await,
+            TokenProvider::Static { token, expires_on } => Ok(AccessToken::new(
+                Secret::new(token.clone()),
+                // without a known expiry the token is re-read from the provider every hour.
+                expires_on.unwrap_or_else(|| OffsetDateTime::now_utc() + Duration::hours(1)),
+            )),
+            TokenProvider::Callback(callback) => callback(scopes.join(" ")).await,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenCache {
     token_credential: Arc<dyn TokenCredential>,
     scope: String,
+    refresh_skew: Duration,
+    max_retries: u32,
+    retry_delay_ms: u64,
+    // The token of the one scope the cache is for.
+    token: Mutex<Option<AccessToken>>,
+    // Held while a token is acquired, so concurrent requests wait for one acquisition.
+    refreshing: AsyncMutex<()>,
+}
+
+impl TokenCache {
+    fn cached_token(&self) -> Option<String> {
+        self.token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|token| token.expires_on > OffsetDateTime::now_utc() + self.refresh_skew)
+            .map(|token| token.token.secret().to_owned())
+    }
+
+    // Time left until the cached token needs to be refreshed.
+    fn refresh_in(&self) -> Duration {
+        self.token
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|token| token.expires_on - self.refresh_skew - OffsetDateTime::now_utc())
+            .unwrap_or(Duration::ZERO)
+    }
+
+    async fn acquire(&self) -> azure_core::Result<AccessToken> {
+        let mut attempt = 0;
+        loop {
+            log::debug!("Acquiring access token for scope {}", self.scope);
+            match self.token_credential.get_token(&[&self.scope], None).await {
+                Err(err) if attempt < self.max_retries && is_retryable(&err) => {
+                    let delay =
+                        with_jitter(self.retry_delay_ms.saturating_mul(1 << attempt.min(16)));
+                    log::warn!("Token acquisition failed, retrying in {delay}ms: {err}");
+                    sleep(Duration::milliseconds(delay as i64)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn get_token(&self) -> azure_core::Result<String> {
+        if let Some(token) = self.cached_token() {
+            return Ok(token);
+        }
+        let _refreshing = self.refreshing.lock().await;
+        // Another request may have acquired the token while this one waited.
+        if let Some(token) = self.cached_token() {
+            return Ok(token);
+        }
+        let token = self.acquire().await?;
+        let secret = token.token.secret().to_owned();
+        *self.token.lock().unwrap() = Some(token);
+        Ok(secret)
+    }
+}
+
+// Keeps the token fresh until the owning policy is dropped.
+async fn refresh_loop(cache: Weak<TokenCache>) {
+    loop {
+        let Some(delay) = cache.upgrade().map(|cache| cache.refresh_in()) else {
+            return;
+        };
+        if delay.is_positive() {
+            sleep(delay).await;
+        }
+        let Some(cache) = cache.upgrade() else {
+            return;
+        };
+        match cache.get_token().await {
+            Err(err) => {
+                log::warn!("Background token refresh failed: {err:?}");
+                sleep(REFRESH_RETRY_DELAY).await;
+            }
+            // Short lived or static tokens can expire within the skew, don't spin on them.
+            Ok(_) if !cache.refresh_in().is_positive() => {
+                log::warn!("Acquired token expires within the refresh skew");
+                sleep(REFRESH_RETRY_DELAY).await;
+            }
+            Ok(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthorizationPolicy {
+    cache: Arc<TokenCache>,
 }
 
 impl AuthorizationPolicy {
-    pub fn new(token_credential: Arc<dyn TokenCredential>, scope: String) -> Self {
-        Self {
+    pub fn new(
+        token_credential: Arc<dyn TokenCredential>,
+        scope: String,
+        settings: &TokenSettings,
+    ) -> Self {
+        let cache = Arc::new(TokenCache {
             token_credential,
             scope,
+            refresh_skew: Duration::seconds(settings.refresh_skew_secs),
+            max_retries: settings.max_retries,
+            retry_delay_ms: settings.retry_delay_ms,
+            token: Mutex::default(),
+            refreshing: AsyncMutex::new(()),
+        });
+        if settings.background_refresh {
+            let _ = get_async_runtime().spawn(Box::pin(refresh_loop(Arc::downgrade(&cache))));
         }
+        Self { cache }
     }
 }
 
@@ -31,11 +292,77 @@ impl Policy for AuthorizationPolicy {
         request: &mut Request,
         next: &[Arc<dyn Policy>],
     ) -> PolicyResult {
-        let token = self
-            .token_credential
-            .get_token(&[&self.scope], None)
-            .await?;
-        request.insert_header("authorization", format!("Bearer {}", token.token.secret()));
+        let token = self.cache.get_token().await?;
+        request.insert_header("authorization", format!("Bearer {token}"));
         next[0].send(ctx, request, &next[1..]).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, Default)]
+    struct CountingCredential(AtomicU32);
+
+    #[async_trait]
+    impl TokenCredential for CountingCredential {
+        async fn get_token(
+            &self,
+            _scopes: &[&str],
+            _options: Option<TokenRequestOptions<'_>>,
+        ) -> azure_core::Result<AccessToken> {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            sleep(Duration::milliseconds(20)).await;
+            Ok(AccessToken::new(
+                Secret::new(format!("token{count}")),
+                OffsetDateTime::now_utc() + Duration::hours(1),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_one_acquisition() {
+        let credential = Arc::new(CountingCredential::default());
+        let policy = AuthorizationPolicy::new(
+            credential.clone(),
+            "scope".to_owned(),
+            &TokenSettings::default(),
+        );
+        let tokens = futures::future::join_all((0..8).map(|_| policy.cache.get_token())).await;
+        assert!(tokens.iter().all(|x| x.as_deref().ok() == Some("token1")));
+        assert_eq!(credential.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[derive(Debug)]
+    struct Counted(Arc<dyn TokenCredential>, AtomicU32);
+
+    #[async_trait]
+    impl TokenCredential for Counted {
+        async fn get_token(
+            &self,
+            scopes: &[&str],
+            options: Option<TokenRequestOptions<'_>>,
+        ) -> azure_core::Result<AccessToken> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.get_token(scopes, options).await
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_background_refresh_backs_off_within_skew() {
+        let provider = TokenProvider::Static {
+            token: "token".to_owned(),
+            expires_on: Some(OffsetDateTime::now_utc() + Duration::minutes(1)),
+        };
+        let credential = Arc::new(Counted(provider.into_credential(), AtomicU32::new(0)));
+        let settings = TokenSettings {
+            background_refresh: true,
+            ..Default::default()
+        };
+        let _policy = AuthorizationPolicy::new(credential.clone(), "scope".to_owned(), &settings);
+        sleep(Duration::milliseconds(200)).await;
+        assert_eq!(credential.1.load(Ordering::SeqCst), 1);
+    }
+}
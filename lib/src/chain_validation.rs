@@ -0,0 +1,160 @@
+/// Cryptographic validation of a sorted certificate chain.
+/// Each certificate's signature is verified with its issuer's public key and every
+/// certificate must be within its validity window, so a broken chain is reported up front
+/// instead of failing later inside COSE validation.
+use cms::cert::x509::{certificate::CertificateInner, der::Encode};
+use p256::ecdsa::{Signature as P256Signature, VerifyingKey as P256VerifyingKey};
+use p384::ecdsa::{Signature as P384Signature, VerifyingKey as P384VerifyingKey};
+use rsa::{
+    RsaPublicKey,
+    pkcs1::RsaPssParams,
+    pkcs1v15,
+    pkcs8::DecodePublicKey,
+    pss,
+    sha2::{Sha256, Sha384, Sha512},
+    signature::Verifier,
+};
+use std::{fmt, time::SystemTime};
+
+const SHA256_WITH_RSA: &str = "1.2.840.113549.1.1.11";
+const SHA384_WITH_RSA: &str = "1.2.840.113549.1.1.12";
+const SHA512_WITH_RSA: &str = "1.2.840.113549.1.1.13";
+const RSASSA_PSS: &str = "1.2.840.113549.1.1.10";
+const ECDSA_WITH_SHA256: &str = "1.2.840.10045.4.3.2";
+const ECDSA_WITH_SHA384: &str = "1.2.840.10045.4.3.3";
+const SHA256: &str = "2.16.840.1.101.3.4.2.1";
+const SHA384: &str = "2.16.840.1.101.3.4.2.2";
+const SHA512: &str = "2.16.840.1.101.3.4.2.3";
+
+/// The link in the chain that failed validation.
+#[derive(Clone, Debug)]
+pub struct ChainValidationError {
+    pub subject: String,
+    pub issuer: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ChainValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "certificate ({}) issued by ({}) is invalid: {}",
+            self.subject, self.issuer, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ChainValidationError {}
+
+fn error(cert: &CertificateInner, reason: impl Into<String>) -> ChainValidationError {
+    ChainValidationError {
+        subject: cert.tbs_certificate.subject.to_string(),
+        issuer: cert.tbs_certificate.issuer.to_string(),
+        reason: reason.into(),
+    }
+}
+
+fn check_validity(cert: &CertificateInner, now: SystemTime) -> Result<(), ChainValidationError> {
+    let validity = &cert.tbs_certificate.validity;
+    if now < validity.not_before.to_system_time() {
+        return Err(error(
+            cert,
+            format!("not valid before {}", validity.not_before),
+        ));
+    }
+    if now > validity.not_after.to_system_time() {
+        return Err(error(cert, format!("expired on {}", validity.not_after)));
+    }
+    Ok(())
+}
+
+fn pss_hash(cert: &CertificateInner) -> Option<String> {
+    let params = cert.signature_algorithm.parameters.as_ref()?;
+    let params: RsaPssParams = params.decode_as().ok()?;
+    Some(params.hash.oid.to_string())
+}
+
+fn verify_signature(
+    cert: &CertificateInner,
+    issuer: &CertificateInner,
+) -> Result<(), ChainValidationError> {
+    let message = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| error(cert, format!("failed to encode certificate: {e}")))?;
+    let public_key = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| error(cert, format!("failed to encode issuer key: {e}")))?;
+    let signature = cert.signature.raw_bytes();
+    let algorithm = cert.signature_algorithm.oid;
+    let invalid = |e: rsa::signature::Error| error(cert, format!("bad signature: {e}"));
+    let rsa_key = || {
+        RsaPublicKey::from_public_key_der(&public_key)
+            .map_err(|e| error(cert, format!("issuer key is not an RSA key: {e}")))
+    };
+    let rsa_signature = || pkcs1v15::Signature::try_from(signature).map_err(invalid);
+
+    match algorithm.to_string().as_str() {
+        SHA256_WITH_RSA => pkcs1v15::VerifyingKey::<Sha256>::new(rsa_key()?)
+            .verify(&message, &rsa_signature()?)
+            .map_err(invalid),
+        SHA384_WITH_RSA => pkcs1v15::VerifyingKey::<Sha384>::new(rsa_key()?)
+            .verify(&message, &rsa_signature()?)
+            .map_err(invalid),
+        SHA512_WITH_RSA => pkcs1v15::VerifyingKey::<Sha512>::new(rsa_key()?)
+            .verify(&message, &rsa_signature()?)
+            .map_err(invalid),
+        RSASSA_PSS => {
+            let signature = pss::Signature::try_from(signature).map_err(invalid)?;
+            match pss_hash(cert).as_deref() {
+                Some(SHA256) => pss::VerifyingKey::<Sha256>::new(rsa_key()?)
+                    .verify(&message, &signature)
+                    .map_err(invalid),
+                Some(SHA384) => pss::VerifyingKey::<Sha384>::new(rsa_key()?)
+                    .verify(&message, &signature)
+                    .map_err(invalid),
+                Some(SHA512) => pss::VerifyingKey::<Sha512>::new(rsa_key()?)
+                    .verify(&message, &signature)
+                    .map_err(invalid),
+                hash => Err(error(cert, format!("unsupported PSS hash: {hash:?}"))),
+            }
+        }
+        ECDSA_WITH_SHA256 => {
+            let key = P256VerifyingKey::from_public_key_der(&public_key)
+                .map_err(|e| error(cert, format!("issuer key is not a P-256 key: {e}")))?;
+            let signature = P256Signature::from_der(signature).map_err(invalid)?;
+            key.verify(&message, &signature).map_err(invalid)
+        }
+        ECDSA_WITH_SHA384 => {
+            let key = P384VerifyingKey::from_public_key_der(&public_key)
+                .map_err(|e| error(cert, format!("issuer key is not a P-384 key: {e}")))?;
+            let signature = P384Signature::from_der(signature).map_err(invalid)?;
+            key.verify(&message, &signature).map_err(invalid)
+        }
+        oid => Err(error(
+            cert,
+            format!("unsupported signature algorithm {oid}"),
+        )),
+    }
+}
+
+/// Validate a chain sorted from leaf to the top most certificate.
+/// The top most certificate's signature is only verified if it is self signed.
+pub(crate) fn validate_chain(
+    certs: &[&CertificateInner],
+    now: SystemTime,
+) -> Result<(), ChainValidationError> {
+    for (index, cert) in certs.iter().enumerate() {
+        check_validity(cert, now)?;
+        match certs.get(index + 1) {
+            Some(issuer) => verify_signature(cert, issuer)?,
+            None if cert.tbs_certificate.subject == cert.tbs_certificate.issuer => {
+                verify_signature(cert, cert)?
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
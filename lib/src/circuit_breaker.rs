@@ -0,0 +1,193 @@
+/// Circuit breaker for Trusted Signing outages.
+/// After a configurable number of consecutive failures the breaker opens and requests fail
+/// fast with [`CircuitOpenError`] until the cool down elapses. The next request after that is
+/// let through as the only trial: success closes the breaker, failure re-opens it. Other
+/// requests keep failing fast while the trial runs, or until it has run for another cool down.
+use envconfig::Envconfig;
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug, Envconfig)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures before the breaker opens. The breaker is disabled when unset.
+    #[envconfig(from = "CIRCUIT_BREAKER_THRESHOLD")]
+    pub failure_threshold: Option<u32>,
+    #[envconfig(from = "CIRCUIT_BREAKER_COOLDOWN_MS", default = "30000")]
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: None,
+            cooldown_ms: 30000,
+        }
+    }
+}
+
+/// Returned when a request is rejected because the circuit breaker is open.
+#[derive(Clone, Debug)]
+pub struct CircuitOpenError {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Trusted Signing circuit breaker is open, retry after {:?}",
+            self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpenError {}
+
+/// Returns true if the signing error was caused by an open circuit breaker.
+pub fn is_circuit_open(error: &c2pa::Error) -> bool {
+    match error {
+        c2pa::Error::OtherError(inner) => inner.downcast_ref::<CircuitOpenError>().is_some(),
+        _ => false,
+    }
+}
+
+/// The error a request was rejected with by the breaker, looked for in the error chain.
+pub(crate) fn rejection(error: &azure_core::Error) -> Option<CircuitOpenError> {
+    let mut current: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<CircuitOpenError>() {
+            return Some(error.clone());
+        }
+        current = error.source();
+    }
+    None
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    /// When the trial request after the cool down was let through.
+    trial_at: Option<Instant>,
+}
+
+impl State {
+    // The error requests are rejected with, if they are.
+    fn rejection(&self, cooldown: Duration) -> Option<CircuitOpenError> {
+        let waited = self.trial_at.or(self.opened_at)?.elapsed();
+        (waited < cooldown).then(|| CircuitOpenError {
+            retry_after: cooldown - waited,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: &CircuitBreakerSettings) -> Option<Self> {
+        settings.failure_threshold.map(|threshold| Self {
+            threshold: threshold.max(1),
+            cooldown: Duration::from_millis(settings.cooldown_ms),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Fails fast if the breaker is open and the cool down hasn't elapsed yet, or another
+    /// request is the trial. Otherwise an open breaker lets this request through as the trial.
+    pub fn check(&self) -> Result<(), CircuitOpenError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(err) = state.rejection(self.cooldown) {
+            return Err(err);
+        }
+        if state.opened_at.is_some() {
+            state.trial_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::check`], without starting a trial.
+    pub fn status(&self) -> Result<(), CircuitOpenError> {
+        self.state
+            .lock()
+            .unwrap()
+            .rejection(self.cooldown)
+            .map_or(Ok(()), Err)
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.opened_at.is_some() {
+            log::info!("Trusted Signing circuit breaker closed");
+        }
+        *state = State::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        // a failed trial request after the cool down re-opens the breaker immediately.
+        if state.consecutive_failures >= self.threshold || state.opened_at.is_some() {
+            log::warn!(
+                "Trusted Signing circuit breaker opened after {} consecutive failures",
+                state.consecutive_failures
+            );
+            state.opened_at = Some(Instant::now());
+            state.trial_at = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let settings = CircuitBreakerSettings {
+            failure_threshold: Some(2),
+            cooldown_ms: 60000,
+        };
+        let breaker = CircuitBreaker::new(&settings).unwrap();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_single_trial_after_cooldown() {
+        let settings = CircuitBreakerSettings {
+            failure_threshold: Some(1),
+            cooldown_ms: 20,
+        };
+        let breaker = CircuitBreaker::new(&settings).unwrap();
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.status().is_ok());
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_disabled_without_threshold() {
+        assert!(CircuitBreaker::new(&CircuitBreakerSettings::default()).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+/// Credential helpers for the environments the signer is usually deployed to.
+use azure_core::{
+    credentials::{Secret, TokenCredential},
+    error::ErrorKind,
+};
+use azure_identity::{
+    AzureCliCredential, ClientSecretCredential, ManagedIdentityCredential,
+    ManagedIdentityCredentialOptions, UserAssignedId, WorkloadIdentityCredential,
+    WorkloadIdentityCredentialOptions,
+};
+use std::{env, fmt, path::PathBuf, str::FromStr, sync::Arc};
+
+use crate::device_code::DeviceCodeCredential;
+
+pub(crate) const AZURE_CLIENT_ID: &str = "AZURE_CLIENT_ID";
+pub(crate) const AZURE_CLIENT_SECRET: &str = "AZURE_CLIENT_SECRET";
+pub(crate) const AZURE_TENANT_ID: &str = "AZURE_TENANT_ID";
+pub(crate) const AZURE_FEDERATED_TOKEN_FILE: &str = "AZURE_FEDERATED_TOKEN_FILE";
+// Set by App Service, Functions and Container Apps when a managed identity is available.
+const IDENTITY_ENDPOINT: &str = "IDENTITY_ENDPOINT";
+
+fn required_env(name: &str) -> azure_core::Result<String> {
+    env::var(name).map_err(|_| {
+        azure_core::Error::with_message(
+            ErrorKind::Credential,
+            format!("{name} is not set, is workload identity enabled for the pod?"),
+        )
+    })
+}
+
+/// Credential for AKS workload identity.
+/// Uses the client id, tenant id and federated token file injected by the workload identity
+/// webhook (`AZURE_CLIENT_ID`, `AZURE_TENANT_ID`, `AZURE_FEDERATED_TOKEN_FILE`).
+pub fn workload_identity_credential() -> azure_core::Result<Arc<dyn TokenCredential>> {
+    let options = WorkloadIdentityCredentialOptions {
+        client_id: Some(required_env(AZURE_CLIENT_ID)?),
+        tenant_id: Some(required_env(AZURE_TENANT_ID)?),
+        token_file_path: Some(PathBuf::from(required_env(AZURE_FEDERATED_TOKEN_FILE)?)),
+        ..Default::default()
+    };
+    Ok(WorkloadIdentityCredential::new(Some(options))?)
+}
+
+/// Managed identity credential, user assigned when `AZURE_CLIENT_ID` is set.
+pub fn managed_identity_credential() -> azure_core::Result<Arc<dyn TokenCredential>> {
+    let options = ManagedIdentityCredentialOptions {
+        user_assigned_id: env::var(AZURE_CLIENT_ID).ok().map(UserAssignedId::ClientId),
+        ..Default::default()
+    };
+    Ok(ManagedIdentityCredential::new(Some(options))?)
+}
+
+/// Pick the credential for the environment the process is running in:
+/// - workload identity on AKS (`AZURE_FEDERATED_TOKEN_FILE` is set),
+/// - managed identity in Azure (`IDENTITY_ENDPOINT` is set, or any release build),
+/// - the Azure CLI login for local debug builds.
+pub fn credential_for_environment() -> azure_core::Result<Arc<dyn TokenCredential>> {
+    if env::var_os(AZURE_FEDERATED_TOKEN_FILE).is_some() {
+        log::info!("Using workload identity credential");
+        workload_identity_credential()
+    } else if env::var_os(IDENTITY_ENDPOINT).is_some() || !cfg!(debug_assertions) {
+        log::info!("Using managed identity credential");
+        managed_identity_credential()
+    } else {
+        log::info!("Using Azure CLI credential");
+        Ok(AzureCliCredential::new(None)?)
+    }
+}
+
+/// An explicitly chosen credential, instead of the environment based choice of
+/// [`credential_for_environment`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CredentialKind {
+    /// The Azure CLI login (`az login`).
+    AzureCli,
+    ManagedIdentity,
+    /// A service principal secret (`AZURE_TENANT_ID`, `AZURE_CLIENT_ID`, `AZURE_CLIENT_SECRET`).
+    ClientSecret,
+    /// Interactive sign in with a code entered on another device.
+    DeviceCode,
+    WorkloadIdentity,
+}
+
+impl FromStr for CredentialKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cli" | "azure-cli" => Ok(Self::AzureCli),
+            "managed-identity" => Ok(Self::ManagedIdentity),
+            "client-secret" => Ok(Self::ClientSecret),
+            "device-code" => Ok(Self::DeviceCode),
+            "workload-identity" => Ok(Self::WorkloadIdentity),
+            _ => Err(format!("Unknown credential: {s}")),
+        }
+    }
+}
+
+/// Callback showing the device code sign in message, with the URL and code to enter.
+pub type DeviceCodePrompt = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Tenant, client and secret for [`credential`], defaulting to the `AZURE_*` variables.
+#[derive(Clone, Default)]
+pub struct CredentialOptions {
+    pub tenant_id: Option<String>,
+    /// Required for device code sign in: the app registration must allow public client flows.
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Shows the device code sign in message, which is logged as a warning when unset.
+    pub device_code_prompt: Option<DeviceCodePrompt>,
+}
+
+impl fmt::Debug for CredentialOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialOptions")
+            .field("tenant_id", &self.tenant_id)
+            .field("client_id", &self.client_id)
+            .field("device_code_prompt", &self.device_code_prompt.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CredentialOptions {
+    pub fn from_env() -> Self {
+        Self {
+            tenant_id: env::var(AZURE_TENANT_ID).ok(),
+            client_id: env::var(AZURE_CLIENT_ID).ok(),
+            client_secret: env::var(AZURE_CLIENT_SECRET).ok(),
+            device_code_prompt: None,
+        }
+    }
+}
+
+fn required(value: Option<String>, name: &str) -> azure_core::Result<String> {
+    value.ok_or_else(|| {
+        azure_core::Error::with_message(ErrorKind::Credential, format!("{name} is not set"))
+    })
+}
+
+/// Create the credential of the given kind.
+pub fn credential(
+    kind: CredentialKind,
+    options: CredentialOptions,
+) -> azure_core::Result<Arc<dyn TokenCredential>> {
+    log::info!("Using {kind:?} credential");
+    match kind {
+        CredentialKind::AzureCli => Ok(AzureCliCredential::new(None)?),
+        CredentialKind::ManagedIdentity => {
+            let options = ManagedIdentityCredentialOptions {
+                user_assigned_id: options.client_id.map(UserAssignedId::ClientId),
+                ..Default::default()
+            };
+            Ok(ManagedIdentityCredential::new(Some(options))?)
+        }
+        CredentialKind::ClientSecret => Ok(ClientSecretCredential::new(
+            &required(options.tenant_id, AZURE_TENANT_ID)?,
+            required(options.client_id, AZURE_CLIENT_ID)?,
+            Secret::new(required(options.client_secret, AZURE_CLIENT_SECRET)?),
+            None,
+        )?),
+        CredentialKind::DeviceCode => Ok(Arc::new(DeviceCodeCredential::new(
+            options.tenant_id,
+            required(options.client_id, AZURE_CLIENT_ID)?,
+            options.device_code_prompt,
+        ))),
+        CredentialKind::WorkloadIdentity => {
+            let options = WorkloadIdentityCredentialOptions {
+                client_id: Some(required(options.client_id, AZURE_CLIENT_ID)?),
+                tenant_id: Some(required(options.tenant_id, AZURE_TENANT_ID)?),
+                token_file_path: Some(PathBuf::from(required_env(AZURE_FEDERATED_TOKEN_FILE)?)),
+                ..Default::default()
+            };
+            Ok(WorkloadIdentityCredential::new(Some(options))?)
+        }
+    }
+}
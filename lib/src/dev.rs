@@ -0,0 +1,90 @@
+/// Local development signer.
+/// Signs with a PEM certificate chain and private key from disk so the examples can run end
+/// to end without any Azure resources. Manifests signed this way are not trusted by default.
+use async_trait::async_trait;
+use azure_core::http::Url;
+use c2pa::{AsyncSigner, BoxedSigner, SigningAlg, create_signer};
+use envconfig::Envconfig;
+use std::{fmt, path::PathBuf};
+
+#[derive(Clone, Debug, Envconfig)]
+pub struct DevSignerOptions {
+    /// PEM file with the signing certificate followed by its issuers.
+    #[envconfig(from = "DEV_SIGNER_CERTIFICATE")]
+    pub certificate: PathBuf,
+    /// PEM file with the private key of the signing certificate.
+    #[envconfig(from = "DEV_SIGNER_KEY")]
+    pub private_key: PathBuf,
+    #[envconfig(from = "ALGORITHM", default = "ps384")]
+    pub algorithm: SigningAlg,
+    pub time_authority_url: Option<Url>,
+}
+
+impl DevSignerOptions {
+    pub fn new(
+        certificate: impl Into<PathBuf>,
+        private_key: impl Into<PathBuf>,
+        algorithm: SigningAlg,
+    ) -> Self {
+        Self {
+            certificate: certificate.into(),
+            private_key: private_key.into(),
+            algorithm,
+            time_authority_url: None,
+        }
+    }
+}
+
+/// A C2PA signer backed by a local certificate and private key.
+pub struct DevSigner {
+    signer: BoxedSigner,
+}
+
+impl fmt::Debug for DevSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DevSigner")
+            .field("alg", &self.signer.alg())
+            .finish()
+    }
+}
+
+impl DevSigner {
+    pub fn new(options: &DevSignerOptions) -> c2pa::Result<Self> {
+        log::warn!(
+            "Signing with the local development certificate {}",
+            options.certificate.display()
+        );
+        let certificate = std::fs::read(&options.certificate)?;
+        let private_key = std::fs::read(&options.private_key)?;
+        let signer = create_signer::from_keys(
+            &certificate,
+            &private_key,
+            options.algorithm,
+            options.time_authority_url.as_ref().map(|x| x.to_string()),
+        )?;
+        Ok(Self { signer })
+    }
+}
+
+#[async_trait]
+impl AsyncSigner for DevSigner {
+    async fn sign(&self, data: Vec<u8>) -> c2pa::Result<Vec<u8>> {
+        self.signer.sign(&data)
+    }
+
+    fn alg(&self) -> SigningAlg {
+        self.signer.alg()
+    }
+
+    fn certs(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.signer.certs()
+    }
+
+    fn reserve_size(&self) -> usize {
+        self.signer.reserve_size()
+    }
+
+    fn time_authority_url(&self) -> Option<String> {
+        self.signer.time_authority_url()
+    }
+}
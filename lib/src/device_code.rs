@@ -0,0 +1,216 @@
+/// Device code flow for interactive sign in where no browser or Azure CLI is available,
+/// e.g. over SSH. The verification URL and code are passed to the prompt callback, or logged
+/// without one.
+use async_trait::async_trait;
+use azure_core::{
+    Result,
+    credentials::{AccessToken, Secret, TokenCredential, TokenRequestOptions},
+    error::ErrorKind,
+    http::{ClientOptions, Context, Method, Pipeline, Request, Url},
+    sleep::sleep,
+    time::{Duration, OffsetDateTime},
+};
+use futures::lock::Mutex;
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{collections::HashMap, fmt};
+
+use crate::credential::DeviceCodePrompt;
+
+const AUTHORITY: &str = "https://login.microsoftonline.com";
+
+#[derive(Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    message: String,
+    expires_in: i64,
+    interval: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+pub(crate) struct DeviceCodeCredential {
+    tenant_id: String,
+    client_id: String,
+    prompt: Option<DeviceCodePrompt>,
+    pipeline: Pipeline,
+    tokens: Mutex<HashMap<String, AccessToken>>,
+    // Tokens for further scopes are redeemed with the refresh token instead of prompting again.
+    refresh_token: Mutex<Option<String>>,
+    // Held while a token is refreshed or signed in for, so concurrent callers wait for the
+    // one prompt instead of starting a device code flow each.
+    acquiring: Mutex<()>,
+}
+
+impl fmt::Debug for DeviceCodeCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceCodeCredential")
+            .field("tenant_id", &self.tenant_id)
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeviceCodeCredential {
+    /// `client_id` is the app registration signed in to, which must allow public client flows.
+    pub(crate) fn new(
+        tenant_id: Option<String>,
+        client_id: String,
+        prompt: Option<DeviceCodePrompt>,
+    ) -> Self {
+        Self {
+            tenant_id: tenant_id.unwrap_or_else(|| "organizations".to_owned()),
+            client_id,
+            prompt,
+            pipeline: Pipeline::new(
+                option_env!("CARGO_PKG_NAME"),
+                option_env!("CARGO_PKG_VERSION"),
+                ClientOptions::default(),
+                vec![],
+                vec![],
+                None,
+            ),
+            tokens: Mutex::new(HashMap::new()),
+            refresh_token: Mutex::new(None),
+            acquiring: Mutex::new(()),
+        }
+    }
+
+    async fn cached(&self, scope: &str) -> Option<AccessToken> {
+        let cached = self.tokens.lock().await.get(scope).cloned();
+        cached.filter(|token| token.expires_on > OffsetDateTime::now_utc() + Duration::minutes(1))
+    }
+
+    // POST a form to the tenant's OAuth endpoint, returning the error response on failure.
+    async fn post<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        form: &[(&str, &str)],
+    ) -> Result<std::result::Result<T, ErrorResponse>> {
+        let url = Url::parse(&format!(
+            "{AUTHORITY}/{}/oauth2/v2.0/{endpoint}",
+            self.tenant_id
+        ))?;
+        let mut encoded = Url::parse("http://localhost")?;
+        encoded.query_pairs_mut().extend_pairs(form);
+        let mut request = Request::new(url, Method::Post);
+        request.insert_header("content-type", "application/x-www-form-urlencoded");
+        request.set_body(encoded.query().unwrap_or_default().to_owned());
+        let response = self
+            .pipeline
+            .send(&Context::new(), &mut request, None)
+            .await?;
+        let success = response.status().is_success();
+        let body = response.into_body();
+        Ok(if success {
+            Ok(body.json()?)
+        } else {
+            Err(body.json()?)
+        })
+    }
+
+    async fn refresh(&self, scope: &str) -> Result<Option<TokenResponse>> {
+        let Some(refresh_token) = self.refresh_token.lock().await.clone() else {
+            return Ok(None);
+        };
+        let form = [
+            ("grant_type", "refresh_token"),
+            ("client_id", self.client_id.as_str()),
+            ("refresh_token", refresh_token.as_str()),
+            ("scope", scope),
+        ];
+        Ok(self.post("token", &form).await?.ok())
+    }
+
+    async fn sign_in(&self, scope: &str) -> Result<TokenResponse> {
+        let scope = format!("{scope} offline_access");
+        let code: DeviceCode = self
+            .post(
+                "devicecode",
+                &[
+                    ("client_id", self.client_id.as_str()),
+                    ("scope", scope.as_str()),
+                ],
+            )
+            .await?
+            .map_err(error)?;
+        match &self.prompt {
+            Some(prompt) => prompt(&code.message),
+            None => log::warn!("{}", code.message),
+        }
+
+        let expires = OffsetDateTime::now_utc() + Duration::seconds(code.expires_in);
+        let mut interval = code.interval.unwrap_or(5);
+        let form = [
+            ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ("client_id", self.client_id.as_str()),
+            ("device_code", code.device_code.as_str()),
+        ];
+        while OffsetDateTime::now_utc() < expires {
+            sleep(Duration::seconds(interval)).await;
+            match self.post("token", &form).await? {
+                Ok(token) => return Ok(token),
+                Err(response) if response.error == "authorization_pending" => {}
+                Err(response) if response.error == "slow_down" => interval += 5,
+                Err(response) => return Err(error(response)),
+            }
+        }
+        Err(azure_core::Error::with_message(
+            ErrorKind::Credential,
+            "the device code expired before sign in completed",
+        ))
+    }
+}
+
+fn error(response: ErrorResponse) -> azure_core::Error {
+    azure_core::Error::with_message(
+        ErrorKind::Credential,
+        format!(
+            "device code sign in failed: {} {}",
+            response.error,
+            response.error_description.unwrap_or_default()
+        ),
+    )
+}
+
+#[async_trait]
+impl TokenCredential for DeviceCodeCredential {
+    async fn get_token(
+        &self,
+        scopes: &[&str],
+        _options: Option<TokenRequestOptions<'_>>,
+    ) -> Result<AccessToken> {
+        let scope = scopes.join(" ");
+        if let Some(token) = self.cached(&scope).await {
+            return Ok(token);
+        }
+        let _acquiring = self.acquiring.lock().await;
+        // Another caller may have got the token while this one waited.
+        if let Some(token) = self.cached(&scope).await {
+            return Ok(token);
+        }
+        let response = match self.refresh(&scope).await? {
+            Some(response) => response,
+            None => self.sign_in(&scope).await?,
+        };
+        if response.refresh_token.is_some() {
+            *self.refresh_token.lock().await = response.refresh_token;
+        }
+        let token = AccessToken::new(
+            Secret::new(response.access_token),
+            OffsetDateTime::now_utc() + Duration::seconds(response.expires_in),
+        );
+        self.tokens.lock().await.insert(scope, token.clone());
+        Ok(token)
+    }
+}
@@ -0,0 +1,168 @@
+/// Differences between the active manifests of two assets, e.g. an original and an edited
+/// copy, or the same asset signed by two services.
+use c2pa::Reader;
+use serde::Serialize;
+
+use crate::{
+    ingredients::{IngredientNode, Relationship},
+    verify::{SignatureDetails, VerificationResult},
+};
+
+/// A signer field that differs between the two manifests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ManifestDiff {
+    /// Signature fields (algorithm, issuer, common name, serial number) that changed.
+    /// The signing time is not compared since it always differs.
+    pub signer: Vec<FieldChange>,
+    /// Assertion labels only in the second manifest.
+    pub assertions_added: Vec<String>,
+    /// Assertion labels only in the first manifest.
+    pub assertions_removed: Vec<String>,
+    /// Ingredients, as `title (relationship)`, only in the second manifest.
+    pub ingredients_added: Vec<String>,
+    /// Ingredients only in the first manifest.
+    pub ingredients_removed: Vec<String>,
+}
+
+impl ManifestDiff {
+    /// Compare the active manifest of `before` with the active manifest of `after`.
+    pub fn between(before: &Reader, after: &Reader) -> c2pa::Result<Self> {
+        Self::from_json(&before.json(), &after.json())
+    }
+
+    pub(crate) fn from_json(before: &str, after: &str) -> c2pa::Result<Self> {
+        let (a, b) = (
+            VerificationResult::from_json(before)?,
+            VerificationResult::from_json(after)?,
+        );
+        let (ingredients_a, ingredients_b) = (ingredients(before)?, ingredients(after)?);
+        Ok(Self {
+            signer: signer_changes(
+                &a.signature.unwrap_or_default(),
+                &b.signature.unwrap_or_default(),
+            ),
+            assertions_added: difference(&b.assertions, &a.assertions),
+            assertions_removed: difference(&a.assertions, &b.assertions),
+            ingredients_added: difference(&ingredients_b, &ingredients_a),
+            ingredients_removed: difference(&ingredients_a, &ingredients_b),
+        })
+    }
+
+    /// Whether the manifests have the same signer, assertions and ingredients.
+    pub fn is_empty(&self) -> bool {
+        self.signer.is_empty()
+            && self.assertions_added.is_empty()
+            && self.assertions_removed.is_empty()
+            && self.ingredients_added.is_empty()
+            && self.ingredients_removed.is_empty()
+    }
+}
+
+fn signer_changes(a: &SignatureDetails, b: &SignatureDetails) -> Vec<FieldChange> {
+    [
+        ("alg", &a.alg, &b.alg),
+        ("issuer", &a.issuer, &b.issuer),
+        ("common_name", &a.common_name, &b.common_name),
+        (
+            "cert_serial_number",
+            &a.cert_serial_number,
+            &b.cert_serial_number,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, a, b)| a != b)
+    .map(|(field, a, b)| FieldChange {
+        field,
+        before: a.clone(),
+        after: b.clone(),
+    })
+    .collect()
+}
+
+fn ingredients(json: &str) -> c2pa::Result<Vec<String>> {
+    let Some(tree) = IngredientNode::from_json(json)? else {
+        return Ok(Vec::new());
+    };
+    Ok(tree
+        .ingredients
+        .iter()
+        .map(|x| {
+            let title = x.title.as_deref().unwrap_or("(untitled)");
+            match x.relationship {
+                Some(Relationship::ParentOf) => format!("{title} (parentOf)"),
+                Some(Relationship::ComponentOf) => format!("{title} (componentOf)"),
+                Some(Relationship::InputTo) => format!("{title} (inputTo)"),
+                None => title.to_owned(),
+            }
+        })
+        .collect())
+}
+
+// The items of `a` not in `b`, counting repeated items, e.g. several actions assertions.
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    let mut remaining = b.to_vec();
+    a.iter()
+        .filter(|x| match remaining.iter().position(|y| y == *x) {
+            Some(index) => {
+                remaining.swap_remove(index);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_diff() {
+        let before = r#"{
+            "active_manifest": "urn:c2pa:a",
+            "manifests": {
+                "urn:c2pa:a": {
+                    "signature_info": { "alg": "Ps384", "issuer": "Contoso" },
+                    "assertions": [{ "label": "c2pa.actions" }, { "label": "c2pa.hash.data" }],
+                    "ingredients": [{ "title": "a.jpg", "relationship": "parentOf" }]
+                }
+            }
+        }"#;
+        let after = r#"{
+            "active_manifest": "urn:c2pa:b",
+            "manifests": {
+                "urn:c2pa:b": {
+                    "signature_info": { "alg": "Ps384", "issuer": "Fabrikam" },
+                    "assertions": [
+                        { "label": "c2pa.actions" },
+                        { "label": "c2pa.actions" },
+                        { "label": "c2pa.hash.data" }
+                    ],
+                    "ingredients": [{ "title": "b.jpg", "relationship": "componentOf" }]
+                }
+            }
+        }"#;
+        let diff = ManifestDiff::from_json(before, after).unwrap();
+        assert_eq!(
+            diff.signer,
+            vec![FieldChange {
+                field: "issuer",
+                before: Some("Contoso".to_owned()),
+                after: Some("Fabrikam".to_owned()),
+            }]
+        );
+        assert_eq!(diff.assertions_added, vec!["c2pa.actions"]);
+        assert!(diff.assertions_removed.is_empty());
+        assert_eq!(diff.ingredients_added, vec!["b.jpg (componentOf)"]);
+        assert_eq!(diff.ingredients_removed, vec!["a.jpg (parentOf)"]);
+        assert!(ManifestDiff::from_json(before, before).unwrap().is_empty());
+    }
+}
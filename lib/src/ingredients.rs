@@ -0,0 +1,274 @@
+/// Ingredient graph of a manifest store as a typed tree, so provenance UIs don't have to
+/// walk the manifest store JSON themselves.
+use c2pa::Reader;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use crate::verify::{ValidationCode, ValidationCodes, ValidationResultsJson};
+
+// A crafted store can list a manifest several times at each level, so the tree would grow
+// exponentially with its depth. Expansion stops at these limits.
+const MAX_NODES: usize = 10_000;
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Relationship {
+    ParentOf,
+    ComponentOf,
+    InputTo,
+}
+
+impl FromStr for Relationship {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parentOf" => Ok(Self::ParentOf),
+            "componentOf" => Ok(Self::ComponentOf),
+            "inputTo" => Ok(Self::InputTo),
+            _ => Err(format!("Unknown relationship: {s}")),
+        }
+    }
+}
+
+/// A manifest or ingredient in the provenance graph.
+#[derive(Clone, Debug, Serialize)]
+pub struct IngredientNode {
+    /// Label of the manifest describing this node, if it has one.
+    pub label: Option<String>,
+    pub title: Option<String>,
+    pub format: Option<String>,
+    /// How this node relates to its parent. `None` for the active manifest.
+    pub relationship: Option<Relationship>,
+    pub codes: ValidationCodes,
+    pub ingredients: Vec<IngredientNode>,
+    /// The ingredients of this node were left out because the tree reached its size or
+    /// depth limit.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct StoreJson {
+    active_manifest: Option<String>,
+    #[serde(default)]
+    manifests: HashMap<String, ManifestJson>,
+}
+
+#[derive(Deserialize)]
+struct ManifestJson {
+    title: Option<String>,
+    format: Option<String>,
+    #[serde(default)]
+    ingredients: Vec<IngredientJson>,
+}
+
+#[derive(Deserialize)]
+struct IngredientJson {
+    title: Option<String>,
+    format: Option<String>,
+    relationship: Option<Relationship>,
+    active_manifest: Option<String>,
+    #[serde(default)]
+    validation_status: Vec<ValidationCode>,
+    validation_results: Option<ValidationResultsJson>,
+}
+
+impl IngredientNode {
+    /// Build the ingredient tree rooted at the active manifest.
+    /// Returns `None` when the store has no active manifest.
+    pub fn from_reader(reader: &Reader) -> c2pa::Result<Option<Self>> {
+        Self::from_json(&reader.json())
+    }
+
+    pub(crate) fn from_json(json: &str) -> c2pa::Result<Option<Self>> {
+        let store: StoreJson = serde_json::from_str(json).map_err(c2pa::Error::JsonError)?;
+        let Some(label) = store.active_manifest.as_deref() else {
+            return Ok(None);
+        };
+        let manifest = store.manifests.get(label);
+        let mut path = HashSet::from([label.to_owned()]);
+        let mut remaining = MAX_NODES;
+        Ok(Some(Self {
+            label: Some(label.to_owned()),
+            title: manifest.and_then(|m| m.title.clone()),
+            format: manifest.and_then(|m| m.format.clone()),
+            relationship: None,
+            codes: ValidationCodes::default(),
+            ingredients: Self::children(&store, manifest, &mut path, &mut remaining),
+            truncated: false,
+        }))
+    }
+
+    // `path` holds the manifests from the active one down to this one, so a manifest that is
+    // an ingredient of several others is expanded under each of them, until `remaining`
+    // nodes have been added or the path is MAX_DEPTH long.
+    fn children(
+        store: &StoreJson,
+        manifest: Option<&ManifestJson>,
+        path: &mut HashSet<String>,
+        remaining: &mut usize,
+    ) -> Vec<Self> {
+        let Some(manifest) = manifest else {
+            return Vec::new();
+        };
+        manifest
+            .ingredients
+            .iter()
+            .map(|ingredient| {
+                let codes = match ingredient
+                    .validation_results
+                    .as_ref()
+                    .and_then(|r| r.active_manifest.clone())
+                {
+                    Some(codes) => codes,
+                    None => ValidationCodes {
+                        failure: ingredient.validation_status.clone(),
+                        ..Default::default()
+                    },
+                };
+                *remaining = remaining.saturating_sub(1);
+                let manifest = ingredient
+                    .active_manifest
+                    .as_ref()
+                    .and_then(|label| Some((label, store.manifests.get(label)?)))
+                    .filter(|(_, m)| !m.ingredients.is_empty());
+                let (mut ingredients, mut truncated) = (Vec::new(), false);
+                // Guard against manifests that reference each other.
+                if let Some((label, manifest)) = manifest
+                    && !path.contains(label)
+                {
+                    if *remaining == 0 || path.len() >= MAX_DEPTH {
+                        truncated = true;
+                    } else {
+                        path.insert(label.clone());
+                        ingredients = Self::children(store, Some(manifest), path, remaining);
+                        path.remove(label);
+                    }
+                }
+                Self {
+                    label: ingredient.active_manifest.clone(),
+                    title: ingredient.title.clone(),
+                    format: ingredient.format.clone(),
+                    relationship: ingredient.relationship,
+                    codes,
+                    ingredients,
+                    truncated,
+                }
+            })
+            .collect()
+    }
+
+    /// The ingredient with the `parentOf` relationship, if any.
+    pub fn parent(&self) -> Option<&IngredientNode> {
+        self.ingredients
+            .iter()
+            .find(|x| x.relationship == Some(Relationship::ParentOf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ingredient_tree() {
+        let json = r#"{
+            "active_manifest": "urn:c2pa:b",
+            "manifests": {
+                "urn:c2pa:a": { "title": "a.jpg", "ingredients": [] },
+                "urn:c2pa:b": {
+                    "title": "b.jpg",
+                    "format": "image/jpeg",
+                    "ingredients": [
+                        { "title": "a.jpg", "relationship": "parentOf", "active_manifest": "urn:c2pa:a" },
+                        { "title": "c.png", "relationship": "componentOf",
+                          "validation_status": [{ "code": "claimSignature.mismatch" }] }
+                    ]
+                }
+            }
+        }"#;
+        let tree = IngredientNode::from_json(json).unwrap().unwrap();
+        assert_eq!(tree.title.as_deref(), Some("b.jpg"));
+        assert_eq!(tree.ingredients.len(), 2);
+        assert_eq!(tree.parent().unwrap().label.as_deref(), Some("urn:c2pa:a"));
+        assert_eq!(
+            tree.ingredients[1].codes.failure[0].code,
+            "claimSignature.mismatch"
+        );
+    }
+
+    #[test]
+    fn test_shared_and_cyclic_ingredients() {
+        // b and c both use a, and a claims d as an ingredient, which claims a back.
+        let json = r#"{
+            "active_manifest": "urn:c2pa:top",
+            "manifests": {
+                "urn:c2pa:a": { "title": "a.jpg", "ingredients": [
+                    { "title": "d.jpg", "active_manifest": "urn:c2pa:d" }
+                ] },
+                "urn:c2pa:d": { "title": "d.jpg", "ingredients": [
+                    { "title": "a.jpg", "active_manifest": "urn:c2pa:a" }
+                ] },
+                "urn:c2pa:b": { "title": "b.jpg", "ingredients": [
+                    { "title": "a.jpg", "active_manifest": "urn:c2pa:a" }
+                ] },
+                "urn:c2pa:c": { "title": "c.jpg", "ingredients": [
+                    { "title": "a.jpg", "active_manifest": "urn:c2pa:a" }
+                ] },
+                "urn:c2pa:top": { "title": "top.jpg", "ingredients": [
+                    { "title": "b.jpg", "active_manifest": "urn:c2pa:b" },
+                    { "title": "c.jpg", "active_manifest": "urn:c2pa:c" }
+                ] }
+            }
+        }"#;
+        let tree = IngredientNode::from_json(json).unwrap().unwrap();
+        for child in &tree.ingredients {
+            let a = &child.ingredients[0];
+            assert_eq!(a.label.as_deref(), Some("urn:c2pa:a"));
+            let d = &a.ingredients[0];
+            assert_eq!(d.label.as_deref(), Some("urn:c2pa:d"));
+            // The cycle stops at a, which is already on the path.
+            assert_eq!(d.ingredients[0].label.as_deref(), Some("urn:c2pa:a"));
+            assert!(d.ingredients[0].ingredients.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_diamond_chain_is_truncated() {
+        // Each manifest lists the next one twice, 2^64 paths down to the last one.
+        let mut manifests = serde_json::Map::new();
+        for i in 0..64 {
+            let next = serde_json::json!({ "title": "x.jpg", "active_manifest": format!("urn:c2pa:{}", i + 1) });
+            let ingredients = if i < 63 {
+                vec![next.clone(), next]
+            } else {
+                Vec::new()
+            };
+            manifests.insert(
+                format!("urn:c2pa:{i}"),
+                serde_json::json!({ "title": "x.jpg", "ingredients": ingredients }),
+            );
+        }
+        let json = serde_json::json!({ "active_manifest": "urn:c2pa:0", "manifests": manifests });
+        let tree = IngredientNode::from_json(&json.to_string())
+            .unwrap()
+            .unwrap();
+        fn count(node: &IngredientNode) -> (usize, bool, usize) {
+            node.ingredients.iter().map(count).fold(
+                (1, node.truncated, 0),
+                |(nodes, truncated, depth), (n, t, d)| {
+                    (nodes + n, truncated || t, depth.max(d + 1))
+                },
+            )
+        }
+        let (nodes, truncated, depth) = count(&tree);
+        // Siblings of the last expanded nodes are still listed.
+        assert!(nodes <= MAX_NODES + MAX_DEPTH + 1);
+        assert!(depth <= MAX_DEPTH);
+        assert!(truncated);
+    }
+}
@@ -7,9 +7,14 @@
 //!
 //! ## Using `TrustedSigner`
 //!
-//! - `TokenCredential`: supply any Azure credential (for example `DefaultAzureCredential`).
+//! - `TokenCredential`: supply any Azure credential (for example `DefaultAzureCredential`), or a
+//!   [`TokenProvider`] with a pre-fetched token or token callback via [`TrustedSigner::with_token_provider`].
+//!   On AKS, [`TrustedSigner::with_workload_identity`] wires up the federated workload identity,
+//!   and [`credential_for_environment`] picks the right credential for local, Azure and AKS runs.
+//!   [`credential`] creates an explicitly chosen [`CredentialKind`], including device code sign in.
 //! - [`SigningOptions`]: describe the Trusted Signing account, certificate profile, and optional timestamping authority.
 //! - [`Context`](c2pa::Context) + [`Builder`](c2pa::Builder): supply TOML settings and your manifest definition before invoking the signer.
+//! - [`resolve_media_type`]: check the extension or content type against the file signature before signing.
 //!
 //! ### Minimal example
 //!
@@ -52,6 +57,36 @@
 //! }
 //! ```
 //!
+//! ### Telemetry
+//!
+//! Implement [`TelemetryHandler`] and set it on the client options to observe the method,
+//! URL, status and latency of every request sent to Trusted Signing.
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use azure_core::credentials::TokenCredential;
+//! # use c2pa_azure::{RequestTelemetry, SigningOptions, TelemetryHandler, TrustedSigner};
+//! #[derive(Debug)]
+//! struct LogTelemetry;
+//!
+//! impl TelemetryHandler for LogTelemetry {
+//!     fn on_request(&self, telemetry: &RequestTelemetry) {
+//!         println!("{:?} {} {:?} in {:?}", telemetry.method, telemetry.url, telemetry.status, telemetry.latency);
+//!     }
+//! }
+//!
+//! # async fn example(credential: Arc<dyn TokenCredential>, options: SigningOptions) -> azure_core::Result<()> {
+//! let mut client_options = options.client_options();
+//! client_options.telemetry = Some(Arc::new(LogTelemetry));
+//! let signer = TrustedSigner::with_client_options(credential, options, client_options).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests to Trusted Signing made inside [`TraceParent::scope`] carry the W3C `traceparent`
+//! of the caller, and its trace id as `x-ms-client-request-id` unless the signer has a
+//! correlation id, so distributed traces connect to the Azure dependency calls.
+//!
 //! ### Environment variables
 //!
 //! - `SIGNING_ENDPOINT`: Trusted Signing endpoint (for example `https://eus.codesigning.azure.net`).
@@ -59,34 +94,132 @@
 //! - `CERTIFICATE_PROFILE`: certificate profile used for signing.
 //! - `ALGORITHM` *(optional)*: override the default signature algorithm (`ps384`).
 //! - `TIME_AUTHORITY_URL` *(optional)*: RFC3161 timestamp authority.
+//! - `INCLUDE_ROOT_CERTIFICATE` *(optional)*: keep the root CA at the end of the certificate chain (default `false`).
+//! - `LEAF_CERTIFICATE` *(optional)*: subject or `keyid:<hex>` of the leaf to use when the chain bundle holds several chains.
+//! - `KEY_VAULT_URL` / `KEY_VAULT_CERTIFICATE` *(optional)*: embed the chain of this Key Vault certificate instead of the one issued by Trusted Signing, which still signs.
+//! - `RETRY_MODE` *(optional)*: `exponential` (default), `fixed` or `none`.
+//! - `MAX_RETRIES` *(optional)*: maximum number of retries per request (default `5`).
+//! - `RETRY_DELAY_MS` / `RETRY_MAX_DELAY_MS` *(optional)*: initial and maximum retry delay.
+//! - `RETRY_JITTER` *(optional)*: randomize retry delays (default `true`).
+//! - `RETRY_STATUS_CODES` *(optional)*: comma separated list of retryable HTTP status codes.
+//! - `CIRCUIT_BREAKER_THRESHOLD` *(optional)*: consecutive failures before signing fails fast.
+//! - `CIRCUIT_BREAKER_COOLDOWN_MS` *(optional)*: how long the breaker stays open (default `30000`).
+//! - `TOKEN_REFRESH_SKEW_SECS` *(optional)*: refresh access tokens this long before expiry (default `300`).
+//! - `TOKEN_BACKGROUND_REFRESH` *(optional)*: refresh access tokens on a background task (default `false`).
+//! - `TOKEN_MAX_RETRIES` / `TOKEN_RETRY_DELAY_MS` *(optional)*: retries for transient token acquisition failures.
+//!
+//! ### Azure Key Vault
+//!
+//! Without a Trusted Signing account, [`KeyVaultSigner`] signs with a Key Vault key and takes
+//! the certificate chain from a Key Vault certificate. [`KeyVaultOptions`] can be loaded from
+//! `KEY_VAULT_URL`, `KEY_VAULT_KEY`, `KEY_VAULT_KEY_VERSION` *(optional)*,
+//! `KEY_VAULT_CERTIFICATE` *(optional, defaults to the key name)*,
+//! `KEY_VAULT_CERTIFICATE_FILE` *(optional PEM chain)* and `ALGORITHM` *(optional, derived
+//! from the key type)*. Managed HSM URLs (`https://<name>.managedhsm.azure.net`) are supported
+//! too; they have no certificates, so `KEY_VAULT_CERTIFICATE_FILE` is required.
+//!
+//! ### Local development
+//!
+//! With the `dev-signer` feature, [`DevSigner`] signs with a PEM certificate chain and private
+//! key from disk (`DEV_SIGNER_CERTIFICATE`, `DEV_SIGNER_KEY`). The examples forward the same
+//! feature and use it whenever those variables are set, so they can run without Azure.
+//!
+//! ### Testing
+//!
+//! The `mock` feature provides [`MockTrustedSigning`], an in-process fake of the Trusted
+//! Signing service with a fixed certificate chain, so signing flows can be tested offline.
+//!
+//! ```ignore
+//! let mock = MockTrustedSigning::new();
+//! let signer = mock.signer().await?;
+//! builder.sign_async(&signer, "image/png", &mut input, &mut output).await?;
+//! assert_eq!(mock.sign_requests(), 1);
+//! ```
 //!
 //! ### Verifying a signed file
 //!
-//! The library exposes a thin wrapper around [`c2pa::Reader`] so you can inspect
-//! manifests produced by `TrustedSigner` or any other C2PA producer.
+//! [`verify_file`] returns the manifest store as JSON, like [`c2pa::Reader::json`], while
+//! [`verify_file_detailed`] returns typed validation results so services can make allow/deny
+//! decisions programmatically. Both work for manifests produced by `TrustedSigner` or any
+//! other C2PA producer.
 //!
 //! ```no_run
 //! use std::fs::File;
 //!
-//! use c2pa::Reader;
+//! use c2pa_azure::{ValidationState, verify_file_detailed};
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let signed = File::open("signed.png")?;
-//!     let reader = Reader::from_stream_async("png", signed).await?;
-//!     println!("Manifest Store: {}", reader.json());
+//!     let result = verify_file_detailed("png", signed).await?;
+//!     println!("Active manifest: {:?}", result.active_manifest);
+//!     if result.validation_state == ValidationState::Invalid {
+//!         for failure in result.failures() {
+//!             println!("{}: {:?}", failure.code, failure.explanation);
+//!         }
+//!     }
 //!     Ok(())
 //! }
 //! ```
 //!
+//! [`ManifestDiff::between`] compares the signer, assertions and ingredients of the active
+//! manifests of two readers.
+//!
 mod acs;
 mod auth;
+mod chain_validation;
+mod circuit_breaker;
+mod credential;
+#[cfg(feature = "dev-signer")]
+mod dev;
+mod device_code;
+mod diff;
+mod ingredients;
+mod keyvault;
+mod media_type;
+#[cfg(feature = "mock")]
+mod mock;
 mod p7b;
+mod report;
+mod retry;
 mod sign;
+mod telemetry;
+mod trace;
+mod verify;
 
+pub use acs::TrustedSigningClientOptions;
+pub use auth::{TokenCallback, TokenFuture, TokenProvider, TokenSettings};
 pub use c2pa::Error;
+pub use chain_validation::ChainValidationError;
+pub use circuit_breaker::{CircuitBreakerSettings, CircuitOpenError, is_circuit_open};
+pub use credential::{
+    CredentialKind, CredentialOptions, DeviceCodePrompt, credential, credential_for_environment,
+    managed_identity_credential, workload_identity_credential,
+};
+#[cfg(feature = "dev-signer")]
+pub use dev::{DevSigner, DevSignerOptions};
+pub use diff::{FieldChange, ManifestDiff};
 pub use envconfig::Envconfig;
+pub use ingredients::{IngredientNode, Relationship};
+pub use keyvault::{KeyVaultClient, KeyVaultOptions, KeyVaultSigner};
+pub use media_type::{
+    MediaTypeError, SIGNABLE_MEDIA_TYPES, SNIFF_LENGTH, detect_media_type, media_type_of,
+    resolve_media_type,
+};
+#[cfg(feature = "mock")]
+pub use mock::MockTrustedSigning;
+pub use p7b::{CertificateChain, CertificateInfo, ChainInfo, LeafSelector};
+pub use report::{ReportFormat, render_report};
+pub use retry::{RetryMode, RetrySettings, StatusCodes};
 pub use sign::{SigningOptions, TrustedSigner};
+pub use telemetry::{RequestTelemetry, TelemetryHandler};
+pub use trace::TraceParent;
+pub use verify::{
+    IdentityAssertion, IdentityTrust, ManifestValidation, PolicyError, RemoteManifestSettings,
+    SignatureDetails, TimestampDetails, ValidationCode, ValidationCodes, ValidationState,
+    ValidationSummary, Verdict, VerificationResult, VerifyMode, VerifySettings, extract_manifest,
+    verify_bytes, verify_file, verify_file_detailed, verify_file_with_settings, verify_url,
+};
 
 #[cfg(test)]
 mod tests {
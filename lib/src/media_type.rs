@@ -0,0 +1,281 @@
+/// Media type detection from file signatures (magic bytes), so a file is not signed as a
+/// format it isn't just because of its extension or a declared content type.
+use std::fmt;
+
+/// Number of leading bytes [`detect_media_type`] looks at.
+pub const SNIFF_LENGTH: usize = 512;
+
+/// The media types c2pa can sign, as returned by [`media_type_of`].
+pub const SIGNABLE_MEDIA_TYPES: [&str; 20] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/tiff",
+    "image/x-adobe-dng",
+    "image/x-sony-arw",
+    "image/x-nikon-nef",
+    "image/webp",
+    "image/heic",
+    "image/heif",
+    "image/avif",
+    "image/svg+xml",
+    "audio/wav",
+    "audio/mpeg",
+    "audio/mp4",
+    "video/mp4",
+    "video/quicktime",
+    "video/x-msvideo",
+    "application/pdf",
+    "application/c2pa",
+];
+
+// ISO BMFF brands, which c2pa signs with the same handler whatever the brand says.
+const BMFF: &[&str] = &[
+    "video/mp4",
+    "audio/mp4",
+    "video/quicktime",
+    "image/heic",
+    "image/heif",
+    "image/avif",
+];
+
+// Camera raw formats are TIFF files.
+const TIFF: &[&str] = &[
+    "image/tiff",
+    "image/x-adobe-dng",
+    "image/x-sony-arw",
+    "image/x-nikon-nef",
+];
+
+/// The media type of the file starting with `header`, `None` when no signature matches.
+pub fn detect_media_type(header: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    if at(0, b"\xFF\xD8\xFF") {
+        Some("image/jpeg")
+    } else if at(0, b"\x89PNG\r\n\x1A\n") {
+        Some("image/png")
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Some("image/gif")
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        Some("image/tiff")
+    } else if at(0, b"%PDF-") {
+        Some("application/pdf")
+    } else if at(0, b"RIFF") {
+        match header.get(8..12)? {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        }
+    } else if at(4, b"ftyp") {
+        // The major brand, or the first known one of the compatible brands that follow it.
+        let size = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        brand(header.get(8..12)?).or_else(|| {
+            header
+                .get(16..size.min(header.len()))?
+                .chunks_exact(4)
+                .find_map(brand)
+        })
+    } else if at(4, b"jumb") {
+        Some("application/c2pa")
+    } else if at(0, b"ID3") || is_mpeg_audio_frame(header) {
+        Some("audio/mpeg")
+    } else if is_svg(header) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+fn brand(brand: &[u8]) -> Option<&'static str> {
+    match brand {
+        b"avif" | b"avis" => Some("image/avif"),
+        b"heic" | b"heix" | b"hevc" | b"heim" | b"heis" => Some("image/heic"),
+        b"mif1" | b"msf1" => Some("image/heif"),
+        b"qt  " => Some("video/quicktime"),
+        b"M4A " | b"M4B " => Some("audio/mp4"),
+        b"isom" | b"iso2" | b"iso3" | b"iso4" | b"iso5" | b"iso6" | b"iso8" | b"iso9" | b"mp41"
+        | b"mp42" | b"mp71" | b"avc1" | b"dash" | b"cmfc" | b"cmf2" | b"msdh" | b"msix"
+        | b"M4V " | b"M4VH" | b"M4VP" | b"f4v " | b"3gp4" | b"3gp5" | b"3gp6" | b"3g2a"
+        | b"mmp4" | b"XAVC" | b"MSNV" => Some("video/mp4"),
+        _ => None,
+    }
+}
+
+// The header of an MPEG audio Layer III frame: the 11 bit sync, a version other than the
+// reserved one and layer III, then a valid bitrate and sample rate. Requiring layer III keeps
+// the UTF-16 LE byte order mark `FF FE` of text files from passing as MP3.
+fn is_mpeg_audio_frame(header: &[u8]) -> bool {
+    let [0xFF, second, third, ..] = *header else {
+        return false;
+    };
+    second & 0xE0 == 0xE0
+        && (second >> 3) & 0x03 != 0x01
+        && (second >> 1) & 0x03 == 0x01
+        && third >> 4 != 0x0F
+        && (third >> 2) & 0x03 != 0x03
+}
+
+fn is_svg(header: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(header);
+    let text = text.trim_start_matches('\u{FEFF}').trim_start();
+    text.starts_with('<') && text.contains("<svg")
+}
+
+/// The media type for a file extension or MIME type, `None` if c2pa can't sign it.
+pub fn media_type_of(format: &str) -> Option<&'static str> {
+    let format = format.trim_start_matches('.').to_ascii_lowercase();
+    let media_type = match format.as_str() {
+        "jpg" | "jpeg" | "image/jpeg" => "image/jpeg",
+        "png" | "image/png" => "image/png",
+        "gif" | "image/gif" => "image/gif",
+        "tif" | "tiff" | "image/tiff" => "image/tiff",
+        "dng" | "image/x-adobe-dng" => "image/x-adobe-dng",
+        "arw" | "image/x-sony-arw" => "image/x-sony-arw",
+        "nef" | "image/x-nikon-nef" => "image/x-nikon-nef",
+        "webp" | "image/webp" => "image/webp",
+        "wav" | "audio/wav" | "audio/wave" | "audio/vnd.wave" | "audio/x-wav" => "audio/wav",
+        "avi" | "video/avi" | "video/msvideo" | "video/x-msvideo" => "video/x-msvideo",
+        "mp4" | "video/mp4" => "video/mp4",
+        "m4a" | "audio/mp4" | "audio/x-m4a" => "audio/mp4",
+        "mov" | "video/quicktime" => "video/quicktime",
+        "heic" | "image/heic" => "image/heic",
+        "heif" | "image/heif" => "image/heif",
+        "avif" | "image/avif" => "image/avif",
+        "pdf" | "application/pdf" => "application/pdf",
+        "mp3" | "audio/mpeg" => "audio/mpeg",
+        "svg" | "image/svg+xml" => "image/svg+xml",
+        "c2pa" | "application/c2pa" => "application/c2pa",
+        _ => return None,
+    };
+    Some(media_type)
+}
+
+fn compatible(declared: &str, detected: &str) -> bool {
+    declared == detected
+        || (BMFF.contains(&declared) && BMFF.contains(&detected))
+        || (TIFF.contains(&declared) && detected == "image/tiff")
+}
+
+/// Returned when the content of a file does not match its declared format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaTypeError {
+    /// Neither the content nor the declared format is one c2pa can sign.
+    Unsupported { declared: Option<String> },
+    /// The content is a different format than the extension or content type says.
+    Mismatch {
+        declared: String,
+        detected: &'static str,
+    },
+}
+
+impl fmt::Display for MediaTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported { declared: None } => {
+                write!(f, "the content is not in a format that can be signed")
+            }
+            Self::Unsupported {
+                declared: Some(declared),
+            } => write!(f, "{declared} is not a format that can be signed"),
+            Self::Mismatch { declared, detected } => write!(
+                f,
+                "the content is {detected}, which does not match the declared format {declared}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MediaTypeError {}
+
+/// Check the declared format (extension or MIME type) against the file signature and return
+/// the media type to sign with. Without a declared format the detected type is used. Formats
+/// without a reliable signature (e.g. SVG with a long prologue) fall back to the declared type.
+pub fn resolve_media_type(
+    declared: Option<&str>,
+    header: &[u8],
+) -> Result<&'static str, MediaTypeError> {
+    let detected = detect_media_type(header);
+    // An ISO BMFF file of a brand c2pa doesn't know isn't signed, whatever it is declared as.
+    if detected.is_none() && header.get(4..8) == Some(b"ftyp") {
+        return Err(MediaTypeError::Unsupported {
+            declared: declared.map(str::to_owned),
+        });
+    }
+    let Some(declared) = declared else {
+        return detected.ok_or(MediaTypeError::Unsupported { declared: None });
+    };
+    let declared_type = media_type_of(declared).ok_or_else(|| MediaTypeError::Unsupported {
+        declared: Some(declared.to_owned()),
+    })?;
+    match detected {
+        Some(detected) if !compatible(declared_type, detected) => Err(MediaTypeError::Mismatch {
+            declared: declared.to_owned(),
+            detected,
+        }),
+        _ => Ok(declared_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_media_type() {
+        let png = include_bytes!("../../test_data/signed.png");
+        assert_eq!(detect_media_type(png), Some("image/png"));
+        assert_eq!(resolve_media_type(None, png), Ok("image/png"));
+        assert_eq!(resolve_media_type(Some("PNG"), png), Ok("image/png"));
+        assert_eq!(
+            resolve_media_type(Some("jpg"), png),
+            Err(MediaTypeError::Mismatch {
+                declared: "jpg".to_owned(),
+                detected: "image/png"
+            })
+        );
+        assert_eq!(
+            resolve_media_type(Some("dng"), b"II*\0\x08\0\0\0"),
+            Ok("image/x-adobe-dng")
+        );
+        assert_eq!(
+            resolve_media_type(Some("mov"), b"\0\0\0\x18ftypmp42"),
+            Ok("video/quicktime")
+        );
+        assert!(resolve_media_type(None, b"hello").is_err());
+    }
+
+    #[test]
+    fn test_bmff_brands() {
+        assert_eq!(
+            detect_media_type(b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2"),
+            Some("video/mp4")
+        );
+        // An unknown major brand with a known compatible brand.
+        assert_eq!(
+            detect_media_type(b"\0\0\0\x18ftypabcd\0\0\0\0abcdM4A "),
+            Some("audio/mp4")
+        );
+        let unknown = b"\0\0\0\x14ftypcrx \0\0\0\x01crx ";
+        assert_eq!(detect_media_type(unknown), None);
+        assert!(resolve_media_type(Some("mp4"), unknown).is_err());
+    }
+
+    #[test]
+    fn test_mpeg_audio_frames() {
+        // MPEG-1 layer III, 128 kbit/s, 44.1 kHz.
+        assert_eq!(detect_media_type(b"\xFF\xFB\x90\x64"), Some("audio/mpeg"));
+        // The UTF-16 LE byte order mark.
+        assert_eq!(detect_media_type(b"\xFF\xFEh\0i\0"), None);
+        // Reserved version, and a bad bitrate.
+        assert_eq!(detect_media_type(b"\xFF\xEB\x90\x64"), None);
+        assert_eq!(detect_media_type(b"\xFF\xFB\xF0\x64"), None);
+    }
+
+    #[test]
+    fn test_signable_media_types() {
+        for media_type in SIGNABLE_MEDIA_TYPES {
+            assert_eq!(media_type_of(media_type), Some(media_type));
+        }
+    }
+}
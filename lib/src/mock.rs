@@ -0,0 +1,183 @@
+/// In-process fake of the Trusted Signing service for offline tests.
+/// [`MockTrustedSigning`] plugs into the HTTP transport of the Trusted Signing client and
+/// answers certificate chain and sign requests from a fixed test chain and ES256 key, so the
+/// whole signing flow runs without an account or network access.
+use async_trait::async_trait;
+use azure_core::{
+    Bytes, Result, base64,
+    error::ErrorKind,
+    http::{
+        AsyncRawResponse, Body, HttpClient, Method, Request, StatusCode, Transport, Url,
+        headers::Headers,
+    },
+};
+use c2pa::SigningAlg;
+use p256::{
+    ecdsa::{Signature, SigningKey, signature::hazmat::PrehashSigner},
+    pkcs8::DecodePrivateKey,
+};
+use serde::Deserialize;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    acs::TrustedSigningClientOptions,
+    auth::TokenProvider,
+    sign::{SigningOptions, TrustedSigner},
+};
+
+const ENDPOINT: &str = "https://mock.codesigning.azure.net";
+const ACCOUNT: &str = "mock-account";
+const CERTIFICATE_PROFILE: &str = "mock-profile";
+const CERTIFICATE_CHAIN: &[u8] = include_bytes!("mock/chain.p7b");
+const SIGNING_KEY: &str = include_str!("mock/signer.key");
+const ROOT_CERTIFICATE: &str = include_str!("mock/root.pem");
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignRequest {
+    signature_algorithm: String,
+    digest: String,
+}
+
+/// A fake Trusted Signing service. Every request is answered in-process: the certificate
+/// chain is a fixed two level chain under [`MockTrustedSigning::root_certificate`] and sign
+/// requests succeed immediately with a deterministic ES256 signature.
+#[derive(Debug)]
+pub struct MockTrustedSigning {
+    key: SigningKey,
+    sign_requests: AtomicUsize,
+}
+
+impl MockTrustedSigning {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            key: SigningKey::from_pkcs8_pem(SIGNING_KEY).expect("invalid mock signing key"),
+            sign_requests: AtomicUsize::new(0),
+        })
+    }
+
+    /// The PEM root certificate of the mock chain, to add to the trust anchors of a verifier.
+    pub fn root_certificate() -> &'static str {
+        ROOT_CERTIFICATE
+    }
+
+    /// Number of sign requests answered so far.
+    pub fn sign_requests(&self) -> usize {
+        self.sign_requests.load(Ordering::Relaxed)
+    }
+
+    /// Signing options pointing at the mock service, using ES256 and no timestamp authority.
+    pub fn signing_options(&self) -> SigningOptions {
+        SigningOptions::new(
+            Url::parse(ENDPOINT).expect("invalid mock endpoint"),
+            ACCOUNT.to_owned(),
+            CERTIFICATE_PROFILE.to_owned(),
+            None,
+        )
+        .with_algorithm(SigningAlg::Es256)
+        .with_time_authority_url(None)
+    }
+
+    /// Transport that routes client requests to this mock.
+    pub fn transport(self: &Arc<Self>) -> Transport {
+        Transport::new(self.clone())
+    }
+
+    /// Client options derived from `options` with requests routed to this mock.
+    pub fn client_options(
+        self: &Arc<Self>,
+        options: &SigningOptions,
+    ) -> TrustedSigningClientOptions {
+        let mut client_options = options.client_options();
+        client_options.client_options.transport = Some(self.transport());
+        client_options
+    }
+
+    /// A [`TrustedSigner`] backed by this mock.
+    pub async fn signer(self: &Arc<Self>) -> Result<TrustedSigner> {
+        let options = self.signing_options();
+        let client_options = self.client_options(&options);
+        let credential = TokenProvider::from_token("mock").into_credential();
+        TrustedSigner::with_client_options(credential, options, client_options).await
+    }
+
+    fn sign(&self, request: &Request) -> Result<serde_json::Value> {
+        let Body::Bytes(body) = request.body() else {
+            return Err(azure_core::Error::new(
+                ErrorKind::DataConversion,
+                "sign request body must be JSON",
+            ));
+        };
+        let request: SignRequest = serde_json::from_slice(body)?;
+        let id = self.sign_requests.fetch_add(1, Ordering::Relaxed) + 1;
+        if !request.signature_algorithm.eq_ignore_ascii_case("ES256") {
+            return Ok(serde_json::json!({
+                "operationId": id.to_string(),
+                "status": "Failed",
+            }));
+        }
+        let digest = base64::decode(request.digest)?;
+        let signature: Signature = self
+            .key
+            .sign_prehash(&digest)
+            .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))?;
+        Ok(serde_json::json!({
+            "operationId": id.to_string(),
+            "status": "Succeeded",
+            "signature": base64::encode(signature.to_bytes()),
+        }))
+    }
+}
+
+fn response(status: StatusCode, content_type: &'static str, body: Bytes) -> AsyncRawResponse {
+    let mut headers = Headers::new();
+    headers.insert("content-type", content_type);
+    AsyncRawResponse::from_bytes(status, headers, body)
+}
+
+#[async_trait]
+impl HttpClient for MockTrustedSigning {
+    async fn execute_request(&self, request: &Request) -> Result<AsyncRawResponse> {
+        let path = request.url().path();
+        let response = match (request.method(), path) {
+            (Method::Get, path) if path.ends_with("/sign/certchain") => response(
+                StatusCode::Ok,
+                "application/pkcs7-mime",
+                Bytes::from_static(CERTIFICATE_CHAIN),
+            ),
+            (Method::Post, path) if path.ends_with("/sign") => {
+                let body = serde_json::to_vec(&self.sign(request)?)?;
+                response(StatusCode::Ok, "application/json", body.into())
+            }
+            _ => response(StatusCode::NotFound, "text/plain", Bytes::new()),
+        };
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_signer() {
+        let mock = MockTrustedSigning::new();
+        let signer = mock.signer().await.unwrap();
+        let chain = signer.certificate_chain().unwrap();
+        assert_eq!(chain.certificates.len(), 1);
+        assert!(
+            chain.certificates[0]
+                .subject
+                .contains("c2pa-azure Mock Signer")
+        );
+
+        let signature = c2pa::AsyncSigner::sign(&signer, b"data".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(signature.len(), 64);
+        assert_eq!(mock.sign_requests(), 1);
+    }
+}
@@ -1,105 +1,644 @@
 // PRSS retursn in p7b format. This converts to PEM format.
+// PEM bundles, DER certificate lists and PKCS#12 files are also accepted so other
+// backends can reuse the same chain sorting.
+use azure_core::http::{ClientOptions, Context, Method, Pipeline, Request, Url};
+use azure_core::sleep::sleep;
 use bytes::Bytes;
+use c2pa::SigningAlg;
 use cms::cert::CertificateChoices;
 use cms::cert::x509::certificate::CertificateInner;
-use cms::cert::x509::der::{Decode, Encode};
+use cms::cert::x509::der::{Decode, Encode, EncodePem, pem::LineEnding};
+use cms::cert::x509::ext::pkix::{
+    AuthorityInfoAccessSyntax, ExtendedKeyUsage, SubjectKeyIdentifier, name::GeneralName,
+};
+use cms::cert::x509::spki::{AlgorithmIdentifierOwned, ObjectIdentifier};
 use cms::{content_info::ContentInfo, signed_data::SignedData};
-use std::collections::HashMap;
+use futures::{
+    StreamExt,
+    future::{Either, select},
+};
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use serde::Serialize;
 
-pub struct CertificateChain(Bytes);
+use crate::chain_validation::validate_chain;
+use std::{
+    collections::{HashMap, HashSet},
+    pin::pin,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
-impl CertificateChain {
-    pub fn from_cert_chain(cert_chain: Bytes) -> Self {
-        Self(cert_chain)
+// id-ad-caIssuers
+const CA_ISSUERS: &str = "1.3.6.1.5.5.7.48.2";
+const MAX_AIA_FETCHES: usize = 4;
+// Issuer certificates are small, a larger or slow response is not one.
+const MAX_AIA_BYTES: usize = 1024 * 1024;
+const AIA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Identifies the leaf certificate of the signing profile when a bundle has several chains.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LeafSelector {
+    /// The leaf whose subject contains this value, e.g. `CN=Contoso`.
+    Subject(String),
+    /// The leaf with this subject key identifier.
+    KeyId(Vec<u8>),
+}
+
+impl LeafSelector {
+    fn matches(&self, cert: &CertificateInner) -> bool {
+        match self {
+            Self::Subject(subject) => cert
+                .tbs_certificate
+                .subject
+                .to_string()
+                .contains(subject.as_str()),
+            Self::KeyId(key_id) => cert
+                .tbs_certificate
+                .get::<SubjectKeyIdentifier>()
+                .ok()
+                .flatten()
+                .is_some_and(|(_, ski)| ski.0.as_bytes() == key_id.as_slice()),
+        }
     }
+}
 
-    fn sort_certificates(certs: Vec<&CertificateInner>) -> c2pa::Result<Vec<Vec<u8>>> {
-        if certs.is_empty() {
-            return Err(c2pa::Error::CoseX5ChainMissing);
+/// `keyid:<hex>` selects by subject key identifier, anything else by subject.
+impl FromStr for LeafSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(hex) = s.strip_prefix("keyid:") else {
+            return Ok(Self::Subject(s.to_owned()));
+        };
+        let hex: String = hex.chars().filter(|c| *c != ':').collect();
+        if !hex.is_ascii() || hex.len() % 2 != 0 {
+            return Err(format!("Invalid key id: {s}"));
         }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::KeyId)
+            .map_err(|_| format!("Invalid key id: {s}"))
+    }
+}
 
-        let max_list_length = certs.len();
-        let mut list = Vec::with_capacity(max_list_length);
+#[derive(Clone, Debug)]
+pub struct CertificateChain {
+    certificates: Vec<CertificateInner>,
+    include_root: bool,
+    leaf: Option<LeafSelector>,
+}
 
-        if certs.len() == 1 {
-            list.push(certs.first().unwrap());
-        } else {
-            let mut subject_of_cert_map = HashMap::new();
-            let mut issuer_of_cert_map = HashMap::new();
-
-            // Map subjects and issuers of the certificates for quick lookup
-            for cert in certs.iter() {
-                let subject = cert.tbs_certificate.subject.to_string();
-                let issuer = cert.tbs_certificate.issuer.to_string();
-
-                // Do not include the root CA.
-                // https://c2pa.org/specifications/specifications/2.0/specs/C2PA_Specification.html#x509_certificates
-                if subject != issuer {
-                    issuer_of_cert_map.insert(issuer, cert);
-                } else {
-                    list.push(cert);
-                }
+impl CertificateChain {
+    fn new(certificates: Vec<CertificateInner>) -> Self {
+        Self {
+            certificates,
+            include_root: false,
+            leaf: None,
+        }
+    }
 
-                subject_of_cert_map.insert(subject, cert);
-            }
+    /// Select the chain whose leaf matches when the bundle contains more than one chain.
+    /// Without a selector the most recently issued leaf is used.
+    pub fn with_leaf(mut self, leaf: Option<LeafSelector>) -> Self {
+        self.leaf = leaf;
+        self
+    }
+
+    /// Keep the self-signed root at the end of the chain.
+    /// The C2PA specification says the root should not be included, but some private trust
+    /// list workflows need it present.
+    pub fn with_root(mut self, include_root: bool) -> Self {
+        self.include_root = include_root;
+        self
+    }
+
+    /// Parse a PKCS#7 SignedData (p7b) certificate bundle.
+    pub fn from_cert_chain(cert_chain: Bytes) -> c2pa::Result<Self> {
+        let info = ContentInfo::from_der(&cert_chain)
+            .inspect_err(|x| log::error!("{x:?}"))
+            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
+        let data: SignedData = info
+            .content
+            .decode_as()
+            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
+        let certs = data
+            .certificates
+            .ok_or(c2pa::Error::CoseX5ChainMissing)?
+            .0
+            .into_vec()
+            .into_iter()
+            .filter_map(|c| match c {
+                CertificateChoices::Certificate(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        Ok(Self::new(certs))
+    }
+
+    /// Parse a bundle of PEM encoded certificates.
+    pub fn from_pem(pem: &[u8]) -> c2pa::Result<Self> {
+        let certs = CertificateInner::load_pem_chain(pem)
+            .inspect_err(|x| log::error!("{x:?}"))
+            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
+        Ok(Self::new(certs))
+    }
+
+    /// Parse a list of DER encoded certificates.
+    pub fn from_der<I, T>(certs: I) -> c2pa::Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        certs
+            .into_iter()
+            .map(|c| {
+                CertificateInner::from_der(c.as_ref()).map_err(|_| c2pa::Error::CoseInvalidCert)
+            })
+            .collect::<c2pa::Result<Vec<_>>>()
+            .map(Self::new)
+    }
 
-            // Find the top most cert
-            if list.is_empty() {
-                // For each issuer, look to see if it is found as a subject of certificate. If it is not found it is the top most cert
-                if let Some((_, &cert)) = issuer_of_cert_map
-                    .iter()
-                    .find(|(issuer, _)| !subject_of_cert_map.contains_key(issuer.as_str()))
-                {
-                    list.push(cert);
+    /// Read all certificates from a password protected PKCS#12 (pfx) file.
+    pub fn from_pkcs12(data: &[u8], password: &str) -> c2pa::Result<Self> {
+        let store = KeyStore::from_pkcs12(data, password)
+            .inspect_err(|x| log::error!("{x:?}"))
+            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
+        let mut certs = Vec::new();
+        for (_, entry) in store.entries() {
+            match entry {
+                KeyStoreEntry::PrivateKeyChain(chain) => {
+                    certs.extend(chain.chain().iter().map(|c| c.as_der().to_vec()))
                 }
+                KeyStoreEntry::Certificate(c) => certs.push(c.as_der().to_vec()),
             }
+        }
+        Self::from_der(certs)
+    }
+
+    // Partition the certificates into chains, each ordered from its leaf to the top most
+    // certificate. A bundle may carry several chains, e.g. while a profile's certificate rolls.
+    fn partition<'a>(certs: &[&'a CertificateInner]) -> Vec<Vec<&'a CertificateInner>> {
+        let subject = |c: &CertificateInner| c.tbs_certificate.subject.to_string();
+        let issuers: HashSet<_> = certs
+            .iter()
+            .filter(|c| !is_self_signed(c))
+            .map(|c| c.tbs_certificate.issuer.to_string())
+            .collect();
+        let subjects: HashMap<_, _> = certs.iter().map(|&c| (subject(c), c)).collect();
 
-            // Build out the certificate chain from root to leaf
-            for _i in 1..max_list_length {
-                let last = list.last().unwrap();
-                let subject = last.tbs_certificate.subject.to_string();
+        certs
+            .iter()
+            .filter(|c| certs.len() == 1 || (!is_self_signed(c) && !issuers.contains(&subject(c))))
+            .map(|&leaf| {
+                let mut chain = vec![leaf];
+                loop {
+                    let last = chain[chain.len() - 1];
+                    if is_self_signed(last) {
+                        break;
+                    }
+                    match subjects.get(&last.tbs_certificate.issuer.to_string()) {
+                        Some(&next) if !chain.contains(&next) => chain.push(next),
+                        _ => break,
+                    }
+                }
+                chain
+            })
+            .collect()
+    }
 
-                if let Some(&cert) = issuer_of_cert_map.get(&subject) {
-                    list.push(cert);
-                } else {
-                    return Err(c2pa::Error::CoseInvalidCert);
+    fn select_chain<'a>(
+        &self,
+        chains: Vec<Vec<&'a CertificateInner>>,
+    ) -> c2pa::Result<Vec<&'a CertificateInner>> {
+        match &self.leaf {
+            Some(selector) => chains
+                .into_iter()
+                .find(|chain| selector.matches(chain[0]))
+                .ok_or_else(|| {
+                    log::error!("No leaf certificate matches {selector:?}");
+                    c2pa::Error::CoseInvalidCert
+                }),
+            None => {
+                if chains.len() > 1 {
+                    log::warn!(
+                        "Found {} certificate chains, using the most recently issued leaf",
+                        chains.len()
+                    );
                 }
+                chains
+                    .into_iter()
+                    .max_by_key(|chain| {
+                        chain[0]
+                            .tbs_certificate
+                            .validity
+                            .not_before
+                            .to_system_time()
+                    })
+                    .ok_or(c2pa::Error::CoseInvalidCert)
             }
         }
+    }
 
-        // Reverse iterate and convert the certifcates
-        list.into_iter()
-            .rev()
-            .inspect(|c| {
-                log::debug!(
-                    "cert: Subject= ({}) Issuer= ({})",
-                    c.tbs_certificate.subject,
-                    c.tbs_certificate.issuer
-                )
-            })
+    // Select the chain for the configured leaf, ordered from leaf to the top most certificate.
+    fn sort_certificates(&self) -> c2pa::Result<Vec<&CertificateInner>> {
+        if self.certificates.is_empty() {
+            return Err(c2pa::Error::CoseX5ChainMissing);
+        }
+
+        let certs: Vec<_> = self.certificates.iter().collect();
+        let chains = Self::partition(&certs);
+
+        // A root that no chain reaches means an intermediate certificate is missing.
+        if let Some(root) = certs
+            .iter()
+            .find(|&&c| is_self_signed(c) && !chains.iter().any(|chain| chain.contains(&c)))
+        {
+            log::debug!(
+                "Root ({}) is not reachable from any leaf",
+                root.tbs_certificate.subject
+            );
+            return Err(c2pa::Error::CoseInvalidCert);
+        }
+
+        let chain = self.select_chain(chains)?;
+        for c in chain.iter() {
+            log::debug!(
+                "cert: Subject= ({}) Issuer= ({})",
+                c.tbs_certificate.subject,
+                c.tbs_certificate.issuer
+            )
+        }
+        Ok(chain)
+    }
+
+    // The sorted chain returned to callers. The root CA is dropped unless asked for.
+    // https://c2pa.org/specifications/specifications/2.0/specs/C2PA_Specification.html#x509_certificates
+    fn chain(&self) -> c2pa::Result<Vec<&CertificateInner>> {
+        let mut certs = self.sort_certificates()?;
+        if !self.include_root && certs.len() > 1 && certs.last().is_some_and(|c| is_self_signed(c))
+        {
+            certs.pop();
+        }
+        Ok(certs)
+    }
+
+    #[deprecated(note = "returns DER encoded certificates, use `to_der_chain` instead")]
+    pub fn get_pem_certificates(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.to_der_chain()
+    }
+
+    /// The sorted chain as DER encoded certificates, leaf certificate first.
+    pub fn to_der_chain(&self) -> c2pa::Result<Vec<Vec<u8>>> {
+        self.chain()?
+            .into_iter()
             .map(|c| c.to_der().map_err(|_| c2pa::Error::CoseInvalidCert))
             .collect()
     }
 
-    pub fn get_pem_certificates(&self) -> c2pa::Result<Vec<Vec<u8>>> {
-        let info = ContentInfo::from_der(&self.0)
-            .inspect_err(|x| log::error!("{x:?}"))
-            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
-        let data: SignedData = info
-            .content
-            .decode_as()
-            .map_err(|_| c2pa::Error::CoseInvalidCert)?;
-        if let Some(certs) = data.certificates {
-            let certs: Vec<_> = certs
-                .0
-                .iter()
-                .filter_map(|c| match c {
-                    CertificateChoices::Certificate(c) => Some(c),
-                    _ => None,
-                })
-                .collect();
-            return Self::sort_certificates(certs);
+    /// The sorted chain as a PEM bundle, leaf certificate first.
+    pub fn to_pem_chain(&self) -> c2pa::Result<String> {
+        self.chain()?
+            .into_iter()
+            .map(|c| {
+                c.to_pem(LineEnding::LF)
+                    .map_err(|_| c2pa::Error::CoseInvalidCert)
+            })
+            .collect()
+    }
+
+    /// Verify every certificate's signature against its issuer and check validity windows.
+    /// The error names the first broken link in the chain.
+    pub fn validate(&self) -> c2pa::Result<()> {
+        let certs = self.sort_certificates()?;
+        validate_chain(&certs, SystemTime::now())
+            .inspect_err(|x| log::error!("Certificate chain validation failed: {x}"))
+            .map_err(|x| c2pa::Error::OtherError(Box::new(x)))
+    }
+
+    /// The earliest expiry of the certificates of the sorted chain, used to decide when to
+    /// re-fetch it. Certificates of other chains in the bundle don't count.
+    pub fn earliest_not_after(&self) -> c2pa::Result<Option<SystemTime>> {
+        Ok(self
+            .chain()?
+            .into_iter()
+            .map(|c| c.tbs_certificate.validity.not_after.to_system_time())
+            .min())
+    }
+
+    /// The DER encoded subject public key info of the leaf of the sorted chain.
+    pub fn leaf_public_key(&self) -> c2pa::Result<Vec<u8>> {
+        let chain = self.chain()?;
+        let leaf = chain.first().ok_or(c2pa::Error::CoseInvalidCert)?;
+        leaf.tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .map_err(|_| c2pa::Error::CoseInvalidCert)
+    }
+
+    /// Describe the sorted chain, leaf certificate first.
+    pub fn info(&self) -> c2pa::Result<ChainInfo> {
+        let certificates = self
+            .chain()?
+            .into_iter()
+            .map(CertificateInfo::from)
+            .collect();
+        Ok(ChainInfo { certificates })
+    }
+
+    /// Fetch intermediate certificates missing from the chain using the CA issuers URL in
+    /// the Authority Information Access extension of the certificate whose issuer is missing,
+    /// until the chain ends in a self-signed root or has no such URL.
+    pub async fn complete_chain(&mut self) -> c2pa::Result<()> {
+        let pipeline = Pipeline::new(
+            option_env!("CARGO_PKG_NAME"),
+            option_env!("CARGO_PKG_VERSION"),
+            ClientOptions::default(),
+            vec![],
+            vec![],
+            None,
+        );
+        let mut fetched = HashSet::new();
+        for _ in 0..MAX_AIA_FETCHES {
+            // Stop when a fetched certificate did not continue the chain.
+            let Some(url) = self
+                .missing_issuer_url()
+                .filter(|x| fetched.insert(x.clone()))
+            else {
+                break;
+            };
+            log::info!("Fetching missing issuer certificate from {url}");
+            let certs = match fetch_certificates(&pipeline, url).await {
+                Ok(certs) => certs,
+                // A chain that sorts without the issuer may still be trusted by the verifier.
+                Err(err) if self.sort_certificates().is_ok() => {
+                    log::warn!("Could not fetch the issuer certificate: {err}");
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            for cert in certs {
+                if !self.certificates.contains(&cert) {
+                    self.certificates.push(cert);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The CA issuers URL of the top most certificate of the selected chain, unless it is a root.
+    fn missing_issuer_url(&self) -> Option<Url> {
+        let certs: Vec<_> = self.certificates.iter().collect();
+        let chain = self.select_chain(Self::partition(&certs)).ok()?;
+        let top = chain.last()?;
+        if is_self_signed(top) {
+            None
+        } else {
+            ca_issuers_url(top)
+        }
+    }
+}
+
+fn is_self_signed(cert: &CertificateInner) -> bool {
+    cert.tbs_certificate.subject == cert.tbs_certificate.issuer
+}
+
+fn ca_issuers_url(cert: &CertificateInner) -> Option<Url> {
+    let (_, aia) = cert
+        .tbs_certificate
+        .get::<AuthorityInfoAccessSyntax>()
+        .ok()
+        .flatten()?;
+    aia.0
+        .iter()
+        .filter(|access| access.access_method.to_string() == CA_ISSUERS)
+        .find_map(|access| match &access.access_location {
+            GeneralName::UniformResourceIdentifier(uri) => Url::parse(uri.as_str()).ok(),
+            _ => None,
+        })
+}
+
+// Download an issuer certificate, either a single DER certificate or a PKCS#7 bundle.
+async fn fetch_certificates(pipeline: &Pipeline, url: Url) -> c2pa::Result<Vec<CertificateInner>> {
+    let timeout = sleep(azure_core::time::Duration::try_from(AIA_TIMEOUT).unwrap_or_default());
+    let bytes = match select(pin!(download(pipeline, url)), pin!(timeout)).await {
+        Either::Left((result, _)) => result?,
+        Either::Right(_) => {
+            log::error!("Timed out fetching issuer certificate");
+            return Err(c2pa::Error::CoseX5ChainMissing);
+        }
+    };
+    match CertificateInner::from_der(&bytes) {
+        Ok(cert) => Ok(vec![cert]),
+        Err(_) => CertificateChain::from_cert_chain(bytes).map(|chain| chain.certificates),
+    }
+}
+
+async fn download(pipeline: &Pipeline, url: Url) -> c2pa::Result<Bytes> {
+    let mut request = Request::new(url, Method::Get);
+    let response = pipeline
+        .stream(&Context::new(), &mut request, None)
+        .await
+        .map_err(|x| c2pa::Error::OtherError(Box::new(x)))?;
+    let status = u16::from(response.status());
+    if !(200..300).contains(&status) {
+        log::error!("Failed to fetch issuer certificate, status: {status}");
+        return Err(c2pa::Error::CoseX5ChainMissing);
+    }
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|x| c2pa::Error::OtherError(Box::new(x)))?;
+        if bytes.len() + chunk.len() > MAX_AIA_BYTES {
+            log::error!("Issuer certificate is larger than {MAX_AIA_BYTES} bytes");
+            return Err(c2pa::Error::CoseX5ChainMissing);
         }
-        Err(c2pa::Error::CoseX5ChainMissing)?
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+/// Details of a single certificate in the chain.
+#[derive(Clone, Debug, Serialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub serial_number: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    /// Public key algorithm, e.g. `RSA` or `EC P-384`.
+    pub key_algorithm: String,
+    /// Extended key usage OIDs in dotted notation.
+    pub extended_key_usages: Vec<String>,
+}
+
+impl CertificateInfo {
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer
+    }
+
+    /// True if the certificate is valid at the given time.
+    pub fn is_valid_at(&self, time: SystemTime) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+
+    /// True if the certificate key can sign with the algorithm. RSA keys sign with any PS
+    /// algorithm, EC keys only with the ES algorithm of their curve.
+    pub fn supports(&self, algorithm: SigningAlg) -> bool {
+        match algorithm {
+            SigningAlg::Ps256 | SigningAlg::Ps384 | SigningAlg::Ps512 => {
+                self.key_algorithm.starts_with("RSA")
+            }
+            SigningAlg::Es256 => self.key_algorithm == "EC P-256",
+            SigningAlg::Es384 => self.key_algorithm == "EC P-384",
+            SigningAlg::Es512 => self.key_algorithm == "EC P-521",
+            _ => false,
+        }
+    }
+}
+
+impl From<&CertificateInner> for CertificateInfo {
+    fn from(cert: &CertificateInner) -> Self {
+        let tbs = &cert.tbs_certificate;
+        let extended_key_usages = tbs
+            .get::<ExtendedKeyUsage>()
+            .ok()
+            .flatten()
+            .map(|(_, eku)| eku.0.iter().map(|oid| oid.to_string()).collect())
+            .unwrap_or_default();
+        Self {
+            subject: tbs.subject.to_string(),
+            issuer: tbs.issuer.to_string(),
+            serial_number: tbs.serial_number.to_string(),
+            not_before: tbs.validity.not_before.to_system_time(),
+            not_after: tbs.validity.not_after.to_system_time(),
+            key_algorithm: key_algorithm(&tbs.subject_public_key_info.algorithm),
+            extended_key_usages,
+        }
+    }
+}
+
+fn key_algorithm(algorithm: &AlgorithmIdentifierOwned) -> String {
+    let curve = algorithm
+        .parameters
+        .as_ref()
+        .and_then(|p| p.decode_as::<ObjectIdentifier>().ok());
+    match (
+        algorithm.oid.to_string().as_str(),
+        curve.map(|c| c.to_string()),
+    ) {
+        ("1.2.840.113549.1.1.1", _) => "RSA".to_owned(),
+        ("1.2.840.113549.1.1.10", _) => "RSA-PSS".to_owned(),
+        ("1.2.840.10045.2.1", Some(curve)) => match curve.as_str() {
+            "1.2.840.10045.3.1.7" => "EC P-256".to_owned(),
+            "1.3.132.0.34" => "EC P-384".to_owned(),
+            "1.3.132.0.35" => "EC P-521".to_owned(),
+            _ => format!("EC {curve}"),
+        },
+        ("1.3.101.112", _) => "Ed25519".to_owned(),
+        (oid, _) => oid.to_owned(),
+    }
+}
+
+/// Details of a sorted certificate chain.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChainInfo {
+    pub certificates: Vec<CertificateInfo>,
+}
+
+impl ChainInfo {
+    pub fn leaf(&self) -> Option<&CertificateInfo> {
+        self.certificates.first()
+    }
+
+    /// The earliest expiry of any certificate in the chain.
+    pub fn not_after(&self) -> Option<SystemTime> {
+        self.certificates.iter().map(|c| c.not_after).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The certificates of the trust anchors in the test settings.
+    fn test_chain() -> CertificateChain {
+        let settings = include_str!("../../test_data/settings.toml");
+        let start = settings.find("-----BEGIN CERTIFICATE-----").unwrap();
+        let end = settings.rfind("-----END CERTIFICATE-----").unwrap();
+        let pem = &settings[start..end + "-----END CERTIFICATE-----".len()];
+        CertificateChain::from_pem(pem.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_chain_info_from_pem() {
+        let chain = test_chain();
+        let info: Vec<_> = chain
+            .certificates
+            .iter()
+            .map(CertificateInfo::from)
+            .collect();
+        assert_eq!(info.len(), 2);
+        assert!(info[0].is_self_signed());
+        assert_eq!(info[0].key_algorithm, "RSA");
+        assert!(info[0].supports(SigningAlg::Ps384));
+        assert!(!info[0].supports(SigningAlg::Es256));
+        assert!(!info[1].is_self_signed());
+        assert!(
+            info[1]
+                .extended_key_usages
+                .contains(&"1.3.6.1.5.5.7.3.8".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_root_is_optional() {
+        let chain = test_chain();
+        assert_eq!(chain.to_der_chain().unwrap().len(), 1);
+        let chain = chain.with_root(true);
+        assert_eq!(chain.to_der_chain().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_earliest_not_after_of_the_chain() {
+        let chain = test_chain();
+        assert_eq!(
+            chain.earliest_not_after().unwrap(),
+            chain.info().unwrap().not_after()
+        );
+        let chain = chain.with_root(true);
+        assert_eq!(
+            chain.earliest_not_after().unwrap(),
+            chain.info().unwrap().not_after()
+        );
+    }
+
+    #[test]
+    fn test_leaf_public_key() {
+        let chain = test_chain();
+        let key = chain.leaf_public_key().unwrap();
+        let root = chain
+            .certificates
+            .iter()
+            .find(|c| is_self_signed(c))
+            .unwrap();
+        assert_ne!(
+            key,
+            root.tbs_certificate
+                .subject_public_key_info
+                .to_der()
+                .unwrap()
+        );
+        assert_eq!(key, chain.with_root(true).leaf_public_key().unwrap());
+    }
+
+    #[test]
+    fn test_parse_leaf_selector() {
+        let selector: LeafSelector = "keyid:0a:FF".parse().unwrap();
+        assert_eq!(selector, LeafSelector::KeyId(vec![0x0a, 0xff]));
+        let selector: LeafSelector = "CN=Contoso".parse().unwrap();
+        assert_eq!(selector, LeafSelector::Subject("CN=Contoso".to_owned()));
+        assert!("keyid:abc".parse::<LeafSelector>().is_err());
     }
 }
@@ -0,0 +1,226 @@
+/// Human readable verification reports for review tools and emails.
+use azure_core::time::{OffsetDateTime, to_rfc3339};
+use std::{fmt::Write, str::FromStr, time::SystemTime};
+
+use crate::verify::{ValidationCode, VerificationResult};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            _ => Err(format!("Unknown report format: {s}")),
+        }
+    }
+}
+
+enum Block {
+    Heading(String),
+    Field(&'static str, String),
+    Item(String),
+}
+
+fn time(time: SystemTime) -> String {
+    to_rfc3339(&OffsetDateTime::from(time))
+}
+
+fn code(code: &ValidationCode) -> String {
+    match &code.explanation {
+        Some(explanation) => format!("{}: {explanation}", code.code),
+        None => code.code.clone(),
+    }
+}
+
+fn blocks(result: &VerificationResult) -> Vec<Block> {
+    let mut blocks = vec![
+        Block::Heading("Verification".to_owned()),
+        Block::Field("Validation state", format!("{:?}", result.validation_state)),
+    ];
+    if let Some(label) = &result.active_manifest {
+        blocks.push(Block::Field("Active manifest", label.clone()));
+    }
+
+    if let Some(signature) = &result.signature {
+        blocks.push(Block::Heading("Signer".to_owned()));
+        let fields = [
+            ("Common name", &signature.common_name),
+            ("Issuer", &signature.issuer),
+            ("Serial number", &signature.cert_serial_number),
+            ("Algorithm", &signature.alg),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                blocks.push(Block::Field(name, value.clone()));
+            }
+        }
+    }
+
+    if !result.certificates.is_empty() {
+        blocks.push(Block::Heading("Certificate chain".to_owned()));
+        blocks.extend(result.certificates.iter().map(|c| {
+            Block::Item(format!(
+                "{} (issued by {}, valid {} to {})",
+                c.subject,
+                c.issuer,
+                time(c.not_before),
+                time(c.not_after)
+            ))
+        }));
+    }
+
+    if let Some(timestamp) = &result.timestamp {
+        blocks.push(Block::Heading("Timestamp".to_owned()));
+        if let Some(authority) = &timestamp.authority {
+            blocks.push(Block::Field("Authority", authority.clone()));
+        }
+        if let Some(time) = &timestamp.time {
+            blocks.push(Block::Field("Signing time", time.clone()));
+        }
+        blocks.push(Block::Field("Validated", timestamp.validated.to_string()));
+        blocks.push(Block::Field("Trusted", timestamp.trusted.to_string()));
+    }
+
+    if !result.identities.is_empty() {
+        blocks.push(Block::Heading("Identity assertions".to_owned()));
+        blocks.extend(result.identities.iter().map(|identity| {
+            Block::Item(format!(
+                "{} ({:?}): {}",
+                identity.label, identity.trust, identity.claims
+            ))
+        }));
+    }
+
+    if !result.assertions.is_empty() {
+        blocks.push(Block::Heading("Assertions".to_owned()));
+        blocks.extend(result.assertions.iter().cloned().map(Block::Item));
+    }
+
+    let warnings: Vec<_> = result
+        .manifests
+        .iter()
+        .flat_map(|m| m.codes.failure.iter().chain(&m.codes.informational))
+        .collect();
+    if !warnings.is_empty() {
+        blocks.push(Block::Heading("Validation warnings".to_owned()));
+        blocks.extend(warnings.into_iter().map(|c| Block::Item(code(c))));
+    }
+    blocks
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Manifest data is untrusted, so it can't add links, images, HTML or lines to the report.
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '*' | '_' | '[' | ']' | '<' | '>' | '`' | '|' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\r' | '\n' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn markdown(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    for (index, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Heading(title) => {
+                let separator = if index == 0 { "" } else { "\n" };
+                _ = writeln!(out, "{separator}## {}\n", escape_markdown(title));
+            }
+            Block::Field(name, value) => {
+                _ = writeln!(out, "- **{name}**: {}", escape_markdown(value))
+            }
+            Block::Item(value) => _ = writeln!(out, "- {}", escape_markdown(value)),
+        }
+    }
+    out
+}
+
+fn html(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    let mut in_list = false;
+    for block in blocks {
+        let item = match block {
+            Block::Heading(title) => {
+                if in_list {
+                    out.push_str("</ul>\n");
+                    in_list = false;
+                }
+                _ = writeln!(out, "<h2>{}</h2>", escape(title));
+                continue;
+            }
+            Block::Field(name, value) => format!("<strong>{name}</strong>: {}", escape(value)),
+            Block::Item(value) => escape(value),
+        };
+        if !in_list {
+            out.push_str("<ul>\n");
+            in_list = true;
+        }
+        _ = writeln!(out, "<li>{item}</li>");
+    }
+    if in_list {
+        out.push_str("</ul>\n");
+    }
+    out
+}
+
+/// Render verification results as a Markdown or HTML fragment covering the signer,
+/// certificate chain, timestamp, assertions and validation warnings.
+pub fn render_report(result: &VerificationResult, format: ReportFormat) -> String {
+    let blocks = blocks(result);
+    match format {
+        ReportFormat::Markdown => markdown(&blocks),
+        ReportFormat::Html => html(&blocks),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_report() {
+        let result =
+            VerificationResult::from_json(include_str!("../../test_data/manifest.json")).unwrap();
+        let report = render_report(&result, ReportFormat::Markdown);
+        assert!(report.starts_with("## Verification\n"));
+        assert!(report.contains("- **Validation state**: Trusted"));
+        assert!(report.contains("- c2pa.actions.v2"));
+        let report = render_report(&result, ReportFormat::Html);
+        assert!(report.contains("<h2>Assertions</h2>\n<ul>\n<li>c2pa.training-mining</li>"));
+        assert!(report.ends_with("</ul>\n"));
+    }
+
+    #[test]
+    fn test_markdown_escapes_manifest_data() {
+        let mut result =
+            VerificationResult::from_json(include_str!("../../test_data/manifest.json")).unwrap();
+        result.signature.as_mut().unwrap().common_name =
+            Some("[click](https://evil.example) <img src=x>\n## Trusted".to_owned());
+        let report = render_report(&result, ReportFormat::Markdown);
+        assert!(report.contains(
+            "- **Common name**: \\[click\\](https://evil.example) \\<img src=x\\> ## Trusted\n"
+        ));
+        assert!(!report.contains("[click]"));
+    }
+}
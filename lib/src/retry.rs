@@ -0,0 +1,264 @@
+/// Retry configuration for requests sent to Trusted Signing.
+/// [`RetrySettings`] can be loaded from the environment along with the rest of the
+/// signing options and is turned into a pipeline retry policy by the client.
+use async_trait::async_trait;
+use azure_core::{
+    error::ErrorKind,
+    http::{
+        Context, RawResponse, Request, RetryOptions,
+        headers::HeaderName,
+        policies::{Policy, PolicyResult},
+    },
+    sleep::sleep,
+    time::Duration,
+};
+use envconfig::Envconfig;
+use futures::future::{Either, select};
+use std::{
+    pin::pin,
+    str::FromStr,
+    sync::Arc,
+    time::{self, SystemTime, UNIX_EPOCH},
+};
+
+const RETRY_AFTER: HeaderName = HeaderName::from_static("retry-after");
+
+/// Backoff strategy used between attempts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RetryMode {
+    Exponential,
+    Fixed,
+    None,
+}
+
+impl FromStr for RetryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "exponential" => Ok(Self::Exponential),
+            "fixed" => Ok(Self::Fixed),
+            "none" => Ok(Self::None),
+            _ => Err(format!("Unknown retry mode: {s}")),
+        }
+    }
+}
+
+/// A comma separated list of HTTP status codes, e.g. `429,500,503`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StatusCodes(pub Vec<u16>);
+
+impl FromStr for StatusCodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                x.parse::<u16>()
+                    .map_err(|_| format!("Invalid status code: {x}"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Clone, Debug, Envconfig)]
+pub struct RetrySettings {
+    #[envconfig(from = "RETRY_MODE", default = "exponential")]
+    pub mode: RetryMode,
+    #[envconfig(from = "MAX_RETRIES", default = "5")]
+    pub max_retries: u32,
+    /// Delay before the first retry (exponential) or between all retries (fixed).
+    #[envconfig(from = "RETRY_DELAY_MS", default = "800")]
+    pub delay_ms: u64,
+    #[envconfig(from = "RETRY_MAX_DELAY_MS", default = "10000")]
+    pub max_delay_ms: u64,
+    /// Randomize each delay by +/- 20% so concurrent clients don't retry in lockstep.
+    #[envconfig(from = "RETRY_JITTER", default = "true")]
+    pub jitter: bool,
+    #[envconfig(from = "RETRY_STATUS_CODES", default = "408,429,500,502,503,504")]
+    pub status_codes: StatusCodes,
+    /// Timeout of each attempt, timed out attempts are retried. No timeout when unset.
+    #[envconfig(from = "REQUEST_TIMEOUT_MS")]
+    pub request_timeout_ms: Option<u64>,
+    /// How long a sign operation may take, including polling for its result.
+    #[envconfig(from = "SIGN_TIMEOUT_MS", default = "30000")]
+    pub sign_timeout_ms: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            mode: RetryMode::Exponential,
+            max_retries: 5,
+            delay_ms: 800,
+            max_delay_ms: 10000,
+            jitter: true,
+            status_codes: StatusCodes(vec![408, 429, 500, 502, 503, 504]),
+            request_timeout_ms: None,
+            sign_timeout_ms: 30000,
+        }
+    }
+}
+
+impl RetrySettings {
+    pub fn retry_options(&self) -> RetryOptions {
+        match self.mode {
+            RetryMode::None => RetryOptions::none(),
+            _ => RetryOptions::custom(Arc::new(RetryPolicy::new(self.clone()))),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Option<time::Duration> {
+        self.request_timeout_ms.map(time::Duration::from_millis)
+    }
+
+    pub fn sign_timeout(&self) -> time::Duration {
+        time::Duration::from_millis(self.sign_timeout_ms)
+    }
+
+    /// Per-try pipeline policies enforcing the request timeout.
+    pub(crate) fn timeout_policies(timeout: Option<time::Duration>) -> Vec<Arc<dyn Policy>> {
+        timeout
+            .map(|timeout| Arc::new(TimeoutPolicy { timeout }) as Arc<dyn Policy>)
+            .into_iter()
+            .collect()
+    }
+
+    fn delay(&self, attempt: u32) -> u64 {
+        let delay = match self.mode {
+            RetryMode::Exponential => self
+                .delay_ms
+                .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX)),
+            _ => self.delay_ms,
+        }
+        .min(self.max_delay_ms);
+        if self.jitter {
+            with_jitter(delay)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Scale the delay to a pseudo random value between 80% and 120% of it.
+pub(crate) fn with_jitter(delay: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or_default();
+    delay * (80 + u64::from(nanos % 41)) / 100
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RetryPolicy {
+    settings: RetrySettings,
+}
+
+impl RetryPolicy {
+    pub fn new(settings: RetrySettings) -> Self {
+        Self { settings }
+    }
+
+    fn should_retry(&self, result: &PolicyResult) -> bool {
+        match result {
+            Ok(response) => self
+                .settings
+                .status_codes
+                .0
+                .contains(&u16::from(response.status())),
+            Err(err) => matches!(err.kind(), ErrorKind::Io),
+        }
+    }
+
+    fn retry_after(response: &RawResponse) -> Option<u64> {
+        response
+            .headers()
+            .get_optional_str(&RETRY_AFTER)
+            .and_then(|x| x.parse::<u64>().ok())
+            .map(|x| x * 1000)
+    }
+}
+
+#[async_trait]
+impl Policy for RetryPolicy {
+    async fn send(
+        &self,
+        ctx: &Context,
+        request: &mut Request,
+        next: &[Arc<dyn Policy>],
+    ) -> PolicyResult {
+        let mut attempt = 0;
+        loop {
+            let result = next[0].send(ctx, request, &next[1..]).await;
+            if attempt >= self.settings.max_retries || !self.should_retry(&result) {
+                return result;
+            }
+            let delay = result
+                .as_ref()
+                .ok()
+                .and_then(Self::retry_after)
+                .map(|x| x.min(self.settings.max_delay_ms))
+                .unwrap_or_else(|| self.settings.delay(attempt));
+            log::warn!(
+                "Request to {} failed (attempt {}), retrying in {delay}ms",
+                request.url(),
+                attempt + 1
+            );
+            sleep(Duration::milliseconds(delay as i64)).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Fails an attempt that takes longer than the timeout with an I/O error, so it is retried.
+#[derive(Debug, Clone)]
+pub(crate) struct TimeoutPolicy {
+    timeout: time::Duration,
+}
+
+#[async_trait]
+impl Policy for TimeoutPolicy {
+    async fn send(
+        &self,
+        ctx: &Context,
+        request: &mut Request,
+        next: &[Arc<dyn Policy>],
+    ) -> PolicyResult {
+        let url = request.url().clone();
+        let timeout = sleep(Duration::try_from(self.timeout).unwrap_or_default());
+        match select(pin!(next[0].send(ctx, request, &next[1..])), pin!(timeout)).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(azure_core::Error::with_message(
+                ErrorKind::Io,
+                format!("Request to {url} timed out after {:?}", self.timeout),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status_codes() {
+        let codes: StatusCodes = "429, 500,503".parse().unwrap();
+        assert_eq!(codes, StatusCodes(vec![429, 500, 503]));
+        assert!("429,abc".parse::<StatusCodes>().is_err());
+    }
+
+    #[test]
+    fn test_exponential_delay_is_capped() {
+        let settings = RetrySettings {
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(settings.delay(0), 800);
+        assert_eq!(settings.delay(2), 3200);
+        assert_eq!(settings.delay(10), 10000);
+        assert_eq!(settings.delay(100), 10000);
+    }
+}
@@ -3,9 +3,20 @@ use azure_core::{credentials::TokenCredential, error::ErrorKind, http::Url};
 use c2pa::{AsyncSigner, SigningAlg};
 use envconfig::Envconfig;
 use sha2::{Digest, Sha256, Sha384, Sha512};
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use crate::acs::{TrustedSigningClient, TrustedSigningClientOptions};
+use crate::{
+    acs::{TrustedSigningClient, TrustedSigningClientOptions},
+    auth::{TokenProvider, TokenSettings},
+    circuit_breaker::{CircuitBreakerSettings, rejection},
+    credential::workload_identity_credential,
+    keyvault::{KeyVaultClient, KeyVaultOptions},
+    p7b::{CertificateChain, ChainInfo, LeafSelector},
+    retry::RetrySettings,
+};
 
 const TIME_AUTHORITY_URL: &str = "http://timestamp.acs.microsoft.com";
 // const TIME_AUTHORITY_URL: &str = "http://timestamp.digicert.com";
@@ -22,6 +33,23 @@ pub struct SigningOptions {
     time_authority_url: Option<Url>,
     #[envconfig(from = "ALGORITHM", default = "ps384")]
     algorithm: c2pa::SigningAlg,
+    /// Keep the root CA at the end of the certificate chain embedded in the manifest.
+    #[envconfig(from = "INCLUDE_ROOT_CERTIFICATE", default = "false")]
+    include_root: bool,
+    /// Subject (or `keyid:<hex>`) of the leaf to use when the chain endpoint returns several chains.
+    #[envconfig(from = "LEAF_CERTIFICATE")]
+    leaf_certificate: Option<LeafSelector>,
+    /// Vault holding a pinned certificate chain, see [`SigningOptions::with_key_vault_certificate`].
+    #[envconfig(from = "KEY_VAULT_URL")]
+    key_vault_url: Option<Url>,
+    #[envconfig(from = "KEY_VAULT_CERTIFICATE")]
+    key_vault_certificate: Option<String>,
+    #[envconfig(nested)]
+    retry: RetrySettings,
+    #[envconfig(nested)]
+    circuit_breaker: CircuitBreakerSettings,
+    #[envconfig(nested)]
+    token: TokenSettings,
 }
 
 impl SigningOptions {
@@ -37,15 +65,107 @@ impl SigningOptions {
             certificate_profile,
             time_authority_url: Url::parse(time_authority_url.unwrap_or(TIME_AUTHORITY_URL)).ok(),
             algorithm: DEFAULT_ALGORITHM,
+            include_root: false,
+            leaf_certificate: None,
+            key_vault_url: None,
+            key_vault_certificate: None,
+            retry: RetrySettings::default(),
+            circuit_breaker: CircuitBreakerSettings::default(),
+            token: TokenSettings::default(),
         }
     }
+
+    /// Sign with a different algorithm than the default PS384.
+    pub fn with_algorithm(mut self, algorithm: SigningAlg) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Override the RFC3161 timestamp authority, `None` disables timestamping.
+    pub fn with_time_authority_url(mut self, time_authority_url: Option<Url>) -> Self {
+        self.time_authority_url = time_authority_url;
+        self
+    }
+
+    /// Override the retry policy used for requests to Trusted Signing.
+    pub fn with_retry(mut self, retry: RetrySettings) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Enable a circuit breaker that fails fast after repeated Trusted Signing failures.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreakerSettings) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// Include the root CA in the certificate chain, for private trust list workflows.
+    pub fn with_root_certificate(mut self, include_root: bool) -> Self {
+        self.include_root = include_root;
+        self
+    }
+
+    /// Select the leaf certificate when the certificate chain bundle holds several chains.
+    pub fn with_leaf_certificate(mut self, leaf: LeafSelector) -> Self {
+        self.leaf_certificate = Some(leaf);
+        self
+    }
+
+    /// Embed the certificate chain of a Key Vault certificate instead of the chain issued by
+    /// Trusted Signing, which still signs. For organizations managing their own intermediates
+    /// while using Trusted Signing for key custody; the certificate must be issued for the
+    /// certificate profile's key, creating or refreshing the signer fails otherwise.
+    pub fn with_key_vault_certificate(mut self, vault_url: Url, certificate: &str) -> Self {
+        self.key_vault_url = Some(vault_url);
+        self.key_vault_certificate = Some(certificate.to_owned());
+        self
+    }
+
+    fn key_vault_options(&self) -> Option<KeyVaultOptions> {
+        let (Some(url), Some(certificate)) = (&self.key_vault_url, &self.key_vault_certificate)
+        else {
+            return None;
+        };
+        let mut options = KeyVaultOptions::new(url.clone(), certificate);
+        options.retry = self.retry.clone();
+        options.token = self.token.clone();
+        Some(options)
+    }
+
+    /// Configure token caching and background refresh.
+    pub fn with_token_settings(mut self, token: TokenSettings) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Options for the underlying Trusted Signing client derived from these settings.
+    /// Customize the returned value and pass it to [`TrustedSigner::with_client_options`].
+    pub fn client_options(&self) -> TrustedSigningClientOptions {
+        let mut options = TrustedSigningClientOptions::new(
+            &self.account,
+            &self.certificate_profile,
+            self.algorithm,
+        );
+        options.client_options.retry = self.retry.retry_options();
+        options.circuit_breaker = self.circuit_breaker.clone();
+        options.token = self.token.clone();
+        options.include_root = self.include_root;
+        options.leaf = self.leaf_certificate.clone();
+        options.request_timeout = self.retry.request_timeout();
+        options.sign_timeout = self.retry.sign_timeout();
+        options
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct TrustedSigner {
     options: SigningOptions,
     client: TrustedSigningClient,
+    /// Set when the certificate chain is pinned in Key Vault.
+    key_vault: Option<KeyVaultClient>,
+    chain: CertificateChain,
     certificates: Vec<Vec<u8>>,
+    correlation_id: Option<String>,
 }
 
 impl TrustedSigner {
@@ -53,45 +173,175 @@ impl TrustedSigner {
         credential: Arc<dyn TokenCredential>,
         options: SigningOptions,
     ) -> azure_core::Result<Self> {
-        let client_options = TrustedSigningClientOptions::new(
-            &options.account,
-            &options.certificate_profile,
-            options.algorithm,
-        );
+        let client_options = options.client_options();
+        Self::with_client_options(credential, options, client_options).await
+    }
+
+    /// Create a signer using a pre-fetched token or a token callback instead of a credential.
+    pub async fn with_token_provider(
+        provider: TokenProvider,
+        options: SigningOptions,
+    ) -> azure_core::Result<Self> {
+        Self::new(provider.into_credential(), options).await
+    }
+
+    /// Create a signer authenticated with AKS workload identity.
+    pub async fn with_workload_identity(options: SigningOptions) -> azure_core::Result<Self> {
+        Self::new(workload_identity_credential()?, options).await
+    }
+
+    /// Create a signer using customized client options (telemetry hooks, pipeline policies, etc).
+    pub async fn with_client_options(
+        credential: Arc<dyn TokenCredential>,
+        options: SigningOptions,
+        client_options: TrustedSigningClientOptions,
+    ) -> azure_core::Result<Self> {
+        let key_vault = options
+            .key_vault_options()
+            .map(|vault| KeyVaultClient::new(credential.clone(), vault));
         let client =
             TrustedSigningClient::new(options.endpoint.clone(), credential, client_options);
-        let certificates = client.get_certificatechain().await?;
+        let chain =
+            Self::fetch_certificatechain(&client, key_vault.as_ref(), &options, None).await?;
+        Self::check_algorithm(&chain, options.algorithm)?;
+        let certificates = Self::der_chain(&chain)?;
 
         Ok(Self {
             options,
             client,
+            key_vault,
+            chain,
             certificates,
+            correlation_id: None,
         })
     }
 
-    fn get_digest(&self, data: Vec<u8>) -> azure_core::Result<Vec<u8>> {
-        match self.options.algorithm {
-            SigningAlg::Ps256 => {
-                let mut hasher = Sha256::new();
-                hasher.update(&data);
-                Ok(hasher.finalize().to_vec())
-            }
-            SigningAlg::Ps384 => {
-                let mut hasher = Sha384::new();
-                hasher.update(&data);
-                Ok(hasher.finalize().to_vec())
-            }
-            SigningAlg::Ps512 => {
-                let mut hasher = Sha512::new();
-                hasher.update(&data);
-                Ok(hasher.finalize().to_vec())
-            }
-            _ => Err(azure_core::Error::new(
+    async fn fetch_certificatechain(
+        client: &TrustedSigningClient,
+        key_vault: Option<&KeyVaultClient>,
+        options: &SigningOptions,
+        correlation_id: Option<&str>,
+    ) -> azure_core::Result<CertificateChain> {
+        let issued = client.get_certificatechain(correlation_id).await?;
+        let Some(key_vault) = key_vault else {
+            return Ok(issued);
+        };
+        let pinned = key_vault
+            .get_certificatechain()
+            .await?
+            .with_root(options.include_root);
+        Self::check_key(&pinned, &issued)?;
+        Ok(pinned)
+    }
+
+    // A pinned certificate for another key would make every signature fail validation, so the
+    // signer isn't created with one.
+    fn check_key(pinned: &CertificateChain, issued: &CertificateChain) -> azure_core::Result<()> {
+        let key = |chain: &CertificateChain| {
+            chain
+                .leaf_public_key()
+                .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))
+        };
+        if key(pinned)? != key(issued)? {
+            return Err(azure_core::Error::with_message(
+                ErrorKind::Other,
+                "The Key Vault certificate is not issued for the key of the certificate profile",
+            ));
+        }
+        Ok(())
+    }
+
+    // Fail early instead of on every sign request when the algorithm doesn't match the key.
+    fn check_algorithm(chain: &CertificateChain, algorithm: SigningAlg) -> azure_core::Result<()> {
+        let info = chain
+            .info()
+            .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))?;
+        match info.leaf() {
+            Some(leaf) if !leaf.supports(algorithm) => Err(azure_core::Error::with_message(
                 ErrorKind::Other,
-                "Unsupported algorithm",
+                format!(
+                    "{algorithm} can't be used with the {} key of the signing certificate",
+                    leaf.key_algorithm
+                ),
             )),
+            _ => Ok(()),
+        }
+    }
+
+    fn der_chain(chain: &CertificateChain) -> azure_core::Result<Vec<Vec<u8>>> {
+        chain
+            .to_der_chain()
+            .map_err(|x| azure_core::Error::new(ErrorKind::DataConversion, x))
+    }
+
+    /// Details of the certificate chain used for signing, leaf certificate first.
+    pub fn certificate_chain(&self) -> c2pa::Result<ChainInfo> {
+        self.chain.info()
+    }
+
+    /// The certificate chain used for signing as PEM, leaf certificate first.
+    pub fn certificate_chain_pem(&self) -> c2pa::Result<String> {
+        self.chain.to_pem_chain()
+    }
+
+    /// The earliest expiry of any certificate in the signing chain, none when it can't be
+    /// sorted.
+    pub fn earliest_not_after(&self) -> Option<SystemTime> {
+        self.chain.earliest_not_after().ok().flatten()
+    }
+
+    /// True if any certificate in the chain expires within the given window.
+    /// Trusted Signing certificates are short lived, so long running services should check
+    /// this periodically and call [`TrustedSigner::refresh_certificates`].
+    pub fn needs_certificate_refresh(&self, window: Duration) -> bool {
+        self.earliest_not_after()
+            .is_none_or(|not_after| SystemTime::now() + window >= not_after)
+    }
+
+    /// Fetch the certificate chain from Trusted Signing (or the pinning Key Vault) again.
+    pub async fn refresh_certificates(&mut self) -> azure_core::Result<()> {
+        let chain = Self::fetch_certificatechain(
+            &self.client,
+            self.key_vault.as_ref(),
+            &self.options,
+            self.correlation_id.as_deref(),
+        )
+        .await?;
+        self.certificates = Self::der_chain(&chain)?;
+        self.chain = chain;
+        Ok(())
+    }
+
+    /// A copy of this signer that tags every request to Trusted Signing with the given
+    /// correlation id (sent as `x-ms-client-request-id` and included in logs and errors).
+    pub fn with_correlation_id(&self, correlation_id: impl Into<String>) -> Self {
+        Self {
+            correlation_id: Some(correlation_id.into()),
+            ..self.clone()
         }
     }
+
+    /// True while the circuit breaker is open and sign requests fail fast.
+    pub fn is_circuit_open(&self) -> bool {
+        self.client.is_circuit_open()
+    }
+
+    fn get_digest(&self, data: Vec<u8>) -> azure_core::Result<Vec<u8>> {
+        digest(self.options.algorithm, &data)
+    }
+}
+
+/// Hash the data with the digest algorithm of the signing algorithm.
+pub(crate) fn digest(algorithm: SigningAlg, data: &[u8]) -> azure_core::Result<Vec<u8>> {
+    match algorithm {
+        SigningAlg::Ps256 | SigningAlg::Es256 => Ok(Sha256::digest(data).to_vec()),
+        SigningAlg::Ps384 | SigningAlg::Es384 => Ok(Sha384::digest(data).to_vec()),
+        SigningAlg::Ps512 | SigningAlg::Es512 => Ok(Sha512::digest(data).to_vec()),
+        _ => Err(azure_core::Error::new(
+            ErrorKind::Other,
+            "Unsupported algorithm",
+        )),
+    }
 }
 
 #[async_trait]
@@ -105,10 +355,19 @@ impl AsyncSigner for TrustedSigner {
             .map_err(|_| c2pa::Error::CoseSignatureAlgorithmNotSupported)?;
         let result = self
             .client
-            .sign(&digest)
+            .sign(&digest, self.correlation_id.as_deref())
             .await
-            .inspect_err(|x| log::error!("Error signing data: {x:?}"))
-            .map_err(|_| c2pa::Error::CoseSignature)?;
+            .inspect_err(|x| {
+                log::error!(
+                    "Error signing data (correlation id: {}): {x:?}",
+                    self.correlation_id.as_deref().unwrap_or("none")
+                )
+            })
+            // Keep the status, correlation id and failure of the signing operation.
+            .map_err(|err| match rejection(&err) {
+                Some(err) => c2pa::Error::OtherError(Box::new(err)),
+                None => c2pa::Error::OtherError(Box::new(err)),
+            })?;
         Ok(result)
     }
 
@@ -0,0 +1,62 @@
+/// Request/response telemetry hooks for the Trusted Signing client pipeline.
+/// Register a [`TelemetryHandler`] on the client options to forward request
+/// metadata to an existing telemetry stack.
+use async_trait::async_trait;
+use azure_core::http::{
+    Context, Method, Request, StatusCode, Url,
+    policies::{Policy, PolicyResult},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Metadata describing a single request sent to Trusted Signing.
+#[derive(Clone, Debug)]
+pub struct RequestTelemetry {
+    pub method: Method,
+    pub url: Url,
+    /// HTTP status of the response, `None` if no response was received.
+    pub status: Option<StatusCode>,
+    pub latency: Duration,
+    /// Error message when the request failed before a response was received.
+    pub error: Option<String>,
+}
+
+/// Callback invoked once for every request the client sends.
+pub trait TelemetryHandler: std::fmt::Debug + Send + Sync {
+    fn on_request(&self, telemetry: &RequestTelemetry);
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TelemetryPolicy {
+    handler: Arc<dyn TelemetryHandler>,
+}
+
+impl TelemetryPolicy {
+    pub fn new(handler: Arc<dyn TelemetryHandler>) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl Policy for TelemetryPolicy {
+    async fn send(
+        &self,
+        ctx: &Context,
+        request: &mut Request,
+        next: &[Arc<dyn Policy>],
+    ) -> PolicyResult {
+        let start = Instant::now();
+        let result = next[0].send(ctx, request, &next[1..]).await;
+        let telemetry = RequestTelemetry {
+            method: request.method(),
+            url: request.url().clone(),
+            status: result.as_ref().ok().map(|response| response.status()),
+            latency: start.elapsed(),
+            error: result.as_ref().err().map(|err| err.to_string()),
+        };
+        self.handler.on_request(&telemetry);
+        result
+    }
+}
@@ -0,0 +1,107 @@
+//! W3C trace context of the operation that is signing, carried to the requests to Trusted
+//! Signing so distributed traces connect the caller's request to the Azure dependency calls.
+use std::{fmt, future::Future};
+
+tokio::task_local! {
+    static CURRENT: TraceParent;
+}
+
+fn is_hex(value: &str, length: usize) -> bool {
+    value.len() == length
+        && value
+            .bytes()
+            .all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A W3C `traceparent` header value, `version-trace_id-parent_id-flags`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceParent {
+    trace_id: String,
+    parent_id: String,
+    flags: String,
+}
+
+impl TraceParent {
+    /// Parse a `traceparent` header. Invalid values, including all-zero ids, are ignored as the
+    /// specification asks. Fields a later version appends are dropped.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.trim().split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+        if !is_hex(version, 2)
+            || version == "ff"
+            || (version == "00" && fields.next().is_some())
+            || !is_hex(trace_id, 32)
+            || !is_hex(parent_id, 16)
+            || !is_hex(flags, 2)
+            || trace_id.bytes().all(|x| x == b'0')
+            || parent_id.bytes().all(|x| x == b'0')
+        {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.to_owned(),
+            parent_id: parent_id.to_owned(),
+            flags: flags.to_owned(),
+        })
+    }
+
+    /// The id of the whole trace, sent as `x-ms-client-request-id` when there is no
+    /// correlation id.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Run the future in this trace, the requests to Trusted Signing it makes carry it.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    /// The trace of the running task, set by [`TraceParent::scope`].
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{}-{}-{}", self.trace_id, self.parent_id, self.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUE: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn test_parse() {
+        let trace = TraceParent::parse(VALUE).unwrap();
+        assert_eq!(trace.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(trace.to_string(), VALUE);
+        assert_eq!(
+            TraceParent::parse(&format!("01{}-extra", &VALUE[2..]))
+                .unwrap()
+                .to_string(),
+            VALUE
+        );
+        assert!(TraceParent::parse(&format!("{VALUE}-extra")).is_none());
+        assert!(TraceParent::parse(&VALUE.to_uppercase()).is_none());
+        assert!(TraceParent::parse(&format!("ff{}", &VALUE[2..])).is_none());
+        assert!(
+            TraceParent::parse("00-00000000000000000000000000000000-b7ad6b7169203331-01").is_none()
+        );
+        assert!(TraceParent::parse("00-0af7651916cd43dd8448eb211c80319c-01").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(TraceParent::current().is_none());
+        let trace = TraceParent::parse(VALUE).unwrap();
+        let current = trace.clone().scope(async { TraceParent::current() }).await;
+        assert_eq!(current, Some(trace));
+    }
+}
@@ -0,0 +1,935 @@
+/// Verification helpers built on [`c2pa::Reader`].
+/// The detailed results mirror the validation output of the reader as typed values so
+/// services can make allow/deny decisions without walking the manifest store JSON.
+use azure_core::http::{
+    ClientOptions, Context as RequestContext, Method, Pipeline, Request, Url,
+    headers::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+use azure_core::{sleep::sleep, time::parse_rfc3339};
+use c2pa::{Context, Reader};
+use futures::{
+    StreamExt,
+    future::{Either, select},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::p7b::{CertificateChain, CertificateInfo};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    pin::pin,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+// OCSP status could not be determined for the signing certificate.
+const OCSP_FAILURES: [&str; 2] = [
+    "signingCredential.ocsp.inaccessible",
+    "signingCredential.ocsp.unknown",
+];
+
+/// Overall validation state of the manifest store.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ValidationState {
+    Invalid,
+    Valid,
+    Trusted,
+}
+
+/// A single validation status entry, e.g. `claimSignature.validated`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ValidationCode {
+    pub code: String,
+    pub url: Option<String>,
+    pub explanation: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ValidationCodes {
+    pub success: Vec<ValidationCode>,
+    pub informational: Vec<ValidationCode>,
+    pub failure: Vec<ValidationCode>,
+}
+
+/// Validation codes reported for one manifest.
+/// For ingredient manifests the label is the URI of the ingredient assertion.
+#[derive(Clone, Debug, Serialize)]
+pub struct ManifestValidation {
+    pub label: String,
+    pub codes: ValidationCodes,
+}
+
+/// Claim signature details of the active manifest.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SignatureDetails {
+    pub alg: Option<String>,
+    pub issuer: Option<String>,
+    pub common_name: Option<String>,
+    pub cert_serial_number: Option<String>,
+    /// Signing time from the timestamp, if the signature was timestamped.
+    pub time: Option<String>,
+}
+
+/// RFC3161 timestamp of the active manifest's claim signature.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TimestampDetails {
+    /// Name of the timestamp authority certificate. c2pa does not report the certificate, so
+    /// this is `None` until it does; the validation codes only describe it in free text.
+    pub authority: Option<String>,
+    /// Signing time asserted by the timestamp authority (RFC3339).
+    pub time: Option<String>,
+    /// The timestamp matched the signature.
+    pub validated: bool,
+    /// The timestamp authority's certificate chain is trusted.
+    pub trusted: bool,
+    pub codes: Vec<ValidationCode>,
+}
+
+impl TimestampDetails {
+    fn from_codes(codes: &ValidationCodes, time: Option<String>) -> Option<Self> {
+        let is_timestamp = |c: &&ValidationCode| c.code.starts_with("timeStamp.");
+        let succeeded = |code: &str| {
+            codes.success.iter().any(|c| c.code == code)
+                && !codes.failure.iter().any(|c| is_timestamp(&c))
+        };
+        let validated = succeeded("timeStamp.validated");
+        let trusted = succeeded("timeStamp.trusted");
+        let codes: Vec<_> = codes
+            .success
+            .iter()
+            .chain(&codes.informational)
+            .chain(&codes.failure)
+            .filter(is_timestamp)
+            .cloned()
+            .collect();
+        if codes.is_empty() && time.is_none() {
+            return None;
+        }
+        Some(Self {
+            authority: None,
+            time,
+            validated,
+            trusted,
+            codes,
+        })
+    }
+
+    /// The signing time asserted by the timestamp authority.
+    pub fn signing_time(&self) -> Option<SystemTime> {
+        let time = parse_rfc3339(self.time.as_deref()?).ok()?;
+        Some(time.into())
+    }
+
+    /// True if a validated timestamp proves the signature existed before the given time.
+    pub fn signed_before(&self, time: SystemTime) -> bool {
+        self.validated && self.signing_time().is_some_and(|x| x < time)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum IdentityTrust {
+    Trusted,
+    /// Well-formed and the signature is valid, but the credential is not trusted.
+    Untrusted,
+    Invalid,
+}
+
+/// A CAWG identity assertion of the active manifest.
+#[derive(Clone, Debug, Serialize)]
+pub struct IdentityAssertion {
+    pub label: String,
+    /// The identity claims as reported by the reader, e.g. the verified identities of an
+    /// identity claims aggregation credential or the X.509 signer.
+    pub claims: serde_json::Value,
+    pub trust: IdentityTrust,
+    pub codes: Vec<ValidationCode>,
+}
+
+impl IdentityAssertion {
+    fn new(label: &str, claims: serde_json::Value, codes: &ValidationCodes) -> Self {
+        let for_assertion = |c: &&ValidationCode| {
+            c.code.starts_with("cawg.")
+                && c.url
+                    .as_deref()
+                    .is_none_or(|url| url.ends_with(&format!("/{label}")))
+        };
+        let failed = codes.failure.iter().any(|c| for_assertion(&c));
+        let trusted = codes
+            .success
+            .iter()
+            .filter(for_assertion)
+            .any(|c| c.code.ends_with(".trusted"));
+        let trust = if failed {
+            IdentityTrust::Invalid
+        } else if trusted {
+            IdentityTrust::Trusted
+        } else {
+            IdentityTrust::Untrusted
+        };
+        Self {
+            label: label.to_owned(),
+            claims,
+            trust,
+            codes: codes
+                .success
+                .iter()
+                .chain(&codes.informational)
+                .chain(&codes.failure)
+                .filter(for_assertion)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct VerificationResult {
+    pub validation_state: ValidationState,
+    pub active_manifest: Option<String>,
+    pub manifests: Vec<ManifestValidation>,
+    pub signature: Option<SignatureDetails>,
+    pub timestamp: Option<TimestampDetails>,
+    /// Assertion labels of the active manifest.
+    pub assertions: Vec<String>,
+    pub identities: Vec<IdentityAssertion>,
+    /// Signing certificate chain of the active manifest, leaf certificate first.
+    pub certificates: Vec<CertificateInfo>,
+}
+
+// The subset of the reader JSON used to build the typed results.
+#[derive(Deserialize)]
+struct ReaderJson {
+    active_manifest: Option<String>,
+    #[serde(default)]
+    manifests: HashMap<String, ManifestJson>,
+    validation_results: Option<ValidationResultsJson>,
+    #[serde(default)]
+    validation_status: Vec<ValidationCode>,
+    validation_state: Option<ValidationState>,
+}
+
+#[derive(Deserialize)]
+struct ManifestJson {
+    signature_info: Option<SignatureDetails>,
+    #[serde(default)]
+    assertions: Vec<AssertionJson>,
+}
+
+#[derive(Deserialize)]
+struct AssertionJson {
+    label: String,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ValidationResultsJson {
+    pub(crate) active_manifest: Option<ValidationCodes>,
+    #[serde(default)]
+    ingredient_deltas: Vec<IngredientDeltaJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IngredientDeltaJson {
+    #[serde(rename = "ingredientAssertionURI")]
+    ingredient_assertion_uri: String,
+    validation_deltas: ValidationCodes,
+}
+
+impl VerificationResult {
+    pub fn from_reader(reader: &Reader) -> c2pa::Result<Self> {
+        let mut result = Self::from_json(&reader.json())?;
+        // The certificate chain is not part of the reader JSON.
+        if let Some(signature) = reader.active_manifest().and_then(|m| m.signature_info()) {
+            result.certificates = CertificateChain::from_pem(signature.cert_chain().as_bytes())
+                .and_then(|chain| chain.with_root(true).info())
+                .map(|info| info.certificates)
+                .inspect_err(|x| log::warn!("Unable to parse the signing certificate chain: {x}"))
+                .unwrap_or_default();
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn from_json(json: &str) -> c2pa::Result<Self> {
+        let reader: ReaderJson = serde_json::from_str(json).map_err(c2pa::Error::JsonError)?;
+        let mut manifests = Vec::new();
+        if let Some(label) = &reader.active_manifest {
+            let codes = match reader
+                .validation_results
+                .as_ref()
+                .and_then(|r| r.active_manifest.clone())
+            {
+                Some(codes) => codes,
+                // Older readers only report failures.
+                None => ValidationCodes {
+                    failure: reader.validation_status.clone(),
+                    ..Default::default()
+                },
+            };
+            manifests.push(ManifestValidation {
+                label: label.clone(),
+                codes,
+            });
+        }
+        if let Some(results) = reader.validation_results {
+            manifests.extend(results.ingredient_deltas.into_iter().map(|delta| {
+                ManifestValidation {
+                    label: delta.ingredient_assertion_uri,
+                    codes: delta.validation_deltas,
+                }
+            }));
+        }
+        let active = reader
+            .active_manifest
+            .as_ref()
+            .and_then(|label| reader.manifests.get(label));
+        let signature = active.and_then(|m| m.signature_info.clone());
+        let no_codes = ValidationCodes::default();
+        let codes = manifests.first().map(|m| &m.codes).unwrap_or(&no_codes);
+        let timestamp =
+            TimestampDetails::from_codes(codes, signature.as_ref().and_then(|s| s.time.clone()));
+        let identities = active
+            .map(|m| {
+                m.assertions
+                    .iter()
+                    .filter(|a| a.label.starts_with("cawg.identity"))
+                    .map(|a| IdentityAssertion::new(&a.label, a.data.clone(), codes))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            validation_state: reader.validation_state.unwrap_or(ValidationState::Invalid),
+            active_manifest: reader.active_manifest.clone(),
+            manifests,
+            signature,
+            timestamp,
+            assertions: active
+                .map(|m| m.assertions.iter().map(|a| a.label.clone()).collect())
+                .unwrap_or_default(),
+            identities,
+            certificates: Vec::new(),
+        })
+    }
+
+    /// Validation failures across the active and ingredient manifests.
+    pub fn failures(&self) -> impl Iterator<Item = &ValidationCode> {
+        self.manifests.iter().flat_map(|m| m.codes.failure.iter())
+    }
+
+    /// Reduce the results to a single verdict.
+    pub fn summary(&self) -> ValidationSummary {
+        let verdict = match self.validation_state {
+            ValidationState::Trusted => Verdict::Trusted,
+            ValidationState::Valid => Verdict::ValidUntrusted,
+            ValidationState::Invalid => Verdict::Invalid,
+        };
+        let mut failures: Vec<String> = Vec::new();
+        for failure in self.failures() {
+            if !failures.contains(&failure.code) {
+                failures.push(failure.code.clone());
+            }
+        }
+        ValidationSummary { verdict, failures }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Verdict {
+    Trusted,
+    ValidUntrusted,
+    Invalid,
+    NoManifest,
+}
+
+/// Compact verification outcome for HTTP responses.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ValidationSummary {
+    pub verdict: Verdict,
+    /// Failing validation status codes.
+    pub failures: Vec<String>,
+}
+
+impl ValidationSummary {
+    /// Summarize the outcome of a verification. Assets without a manifest store produce
+    /// [`Verdict::NoManifest`], other errors are returned as is.
+    pub fn from_result(result: c2pa::Result<VerificationResult>) -> c2pa::Result<Self> {
+        match result {
+            Ok(result) => Ok(result.summary()),
+            Err(c2pa::Error::JumbfNotFound | c2pa::Error::ProvenanceMissing) => Ok(Self {
+                verdict: Verdict::NoManifest,
+                failures: Vec::new(),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// How verification treats untrusted signers and unavailable revocation information.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum VerifyMode {
+    /// Report trust and OCSP problems in the results without failing.
+    #[default]
+    Permissive,
+    /// Fail unless the signer is trusted and its OCSP status could be checked.
+    Strict,
+}
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "permissive" => Ok(Self::Permissive),
+            "strict" => Ok(Self::Strict),
+            _ => Err(format!("Unknown verify mode: {s}")),
+        }
+    }
+}
+
+/// Returned by strict verification when the asset does not meet the policy.
+#[derive(Clone, Debug)]
+pub struct PolicyError {
+    pub reason: String,
+    pub codes: Vec<ValidationCode>,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "verification policy failed: {}", self.reason)?;
+        for code in &self.codes {
+            write!(f, " [{}]", code.code)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+/// Remote manifests larger than this are not downloaded by default.
+pub const DEFAULT_MAX_MANIFEST_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Where remote (cloud) manifests may be fetched from during verification.
+#[derive(Clone, Debug)]
+pub struct RemoteManifestSettings {
+    /// Hosts manifests may be fetched from, e.g. `manifests.contoso.com` or `*.contoso.com`.
+    /// No host is allowed when empty, so assets can't make the verifier fetch any URL.
+    pub allowed_hosts: Vec<String>,
+    pub timeout: Duration,
+    /// Downloads of larger manifests are aborted.
+    pub max_bytes: u64,
+}
+
+impl Default for RemoteManifestSettings {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            timeout: Duration::from_secs(30),
+            max_bytes: DEFAULT_MAX_MANIFEST_BYTES,
+        }
+    }
+}
+
+impl RemoteManifestSettings {
+    fn is_allowed(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain.to_ascii_lowercase().as_str())
+                    .is_some_and(|x| x.ends_with('.')),
+                None => host.eq_ignore_ascii_case(allowed),
+            })
+    }
+}
+
+/// Trust anchors, allowed lists and verify flags applied to a single verification call,
+/// instead of whatever settings the process installed globally.
+/// Typed fields override the same keys in the TOML passed to [`VerifySettings::from_toml`].
+#[derive(Clone, Debug, Default)]
+pub struct VerifySettings {
+    /// PEM encoded trust anchors.
+    pub trust_anchors: Option<String>,
+    /// PEM encoded end-entity certificates trusted regardless of their anchors.
+    pub allowed_list: Option<String>,
+    /// Allowed extended key usage OIDs, one per line.
+    pub trust_config: Option<String>,
+    pub verify_trust: Option<bool>,
+    pub verify_timestamp_trust: Option<bool>,
+    pub ocsp_fetch: Option<bool>,
+    /// Strict mode turns trust checks and OCSP fetching on unless set explicitly above.
+    pub mode: VerifyMode,
+    /// Fetch remote manifests from the allowed hosts. Assets referencing a remote manifest
+    /// fail with [`c2pa::Error::RemoteManifestUrl`] when unset.
+    pub remote_manifests: Option<RemoteManifestSettings>,
+    base: toml::Table,
+}
+
+impl VerifySettings {
+    /// Start from c2pa TOML settings, e.g. the contents of a `settings.toml` file.
+    pub fn from_toml(settings: &str) -> c2pa::Result<Self> {
+        let base = settings
+            .parse::<toml::Table>()
+            .map_err(|x| c2pa::Error::BadParam(format!("invalid settings: {x}")))?;
+        Ok(Self {
+            base,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) fn to_toml(&self) -> String {
+        let mut table = self.base.clone();
+        let trust = [
+            ("trust_anchors", &self.trust_anchors),
+            ("allowed_list", &self.allowed_list),
+            ("trust_config", &self.trust_config),
+        ];
+        for (key, value) in trust {
+            if let Some(value) = value {
+                section(&mut table, "trust").insert(key.to_owned(), value.clone().into());
+            }
+        }
+        let strict = (self.mode == VerifyMode::Strict).then_some(true);
+        let verify = [
+            ("verify_trust", self.verify_trust.or(strict)),
+            (
+                "verify_timestamp_trust",
+                self.verify_timestamp_trust.or(strict),
+            ),
+            ("ocsp_fetch", self.ocsp_fetch.or(strict)),
+        ];
+        for (key, value) in verify {
+            if let Some(value) = value {
+                section(&mut table, "verify").insert(key.to_owned(), value.into());
+            }
+        }
+        // Remote manifests are fetched here so the host allow-list and timeout apply.
+        section(&mut table, "verify").insert("remote_manifest_fetch".to_owned(), false.into());
+        table.to_string()
+    }
+
+    fn context(&self) -> c2pa::Result<Context> {
+        Context::new().with_settings(self.to_toml().as_str())
+    }
+
+    /// Apply the verify mode to the results of a verification.
+    pub fn check(&self, result: &VerificationResult) -> Result<(), PolicyError> {
+        if self.mode == VerifyMode::Permissive {
+            return Ok(());
+        }
+        if result.validation_state != ValidationState::Trusted {
+            return Err(PolicyError {
+                reason: format!("manifest is {:?}", result.validation_state),
+                codes: result.failures().cloned().collect(),
+            });
+        }
+        let ocsp: Vec<_> = result
+            .manifests
+            .iter()
+            .flat_map(|m| m.codes.failure.iter().chain(&m.codes.informational))
+            .filter(|c| OCSP_FAILURES.contains(&c.code.as_str()))
+            .cloned()
+            .collect();
+        if !ocsp.is_empty() {
+            return Err(PolicyError {
+                reason: "OCSP status is unavailable".to_owned(),
+                codes: ocsp,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn section<'a>(table: &'a mut toml::Table, name: &str) -> &'a mut toml::Table {
+    let value = table
+        .entry(name)
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if !value.is_table() {
+        *value = toml::Value::Table(toml::Table::new());
+    }
+    value.as_table_mut().unwrap()
+}
+
+async fn read(
+    format: &str,
+    stream: impl Read + Seek + Send,
+    context: Context,
+) -> c2pa::Result<Reader> {
+    Reader::from_context(context)
+        .with_stream_async(format, stream)
+        .await
+}
+
+/// Read and validate the manifest store of an asset, returning it as JSON.
+/// Remote manifests are not fetched, use [`verify_file_with_settings`] to allow hosts.
+pub async fn verify_file(format: &str, stream: impl Read + Seek + Send) -> c2pa::Result<String> {
+    let settings = VerifySettings::default();
+    Ok(verify_file_with_settings(format, stream, &settings)
+        .await?
+        .json())
+}
+
+/// Read and validate the manifest store of an asset, returning typed validation results.
+/// Remote manifests are not fetched, use [`verify_file_with_settings`] to allow hosts.
+pub async fn verify_file_detailed(
+    format: &str,
+    stream: impl Read + Seek + Send,
+) -> c2pa::Result<VerificationResult> {
+    let settings = VerifySettings::default();
+    VerificationResult::from_reader(&verify_file_with_settings(format, stream, &settings).await?)
+}
+
+/// Read and validate the manifest store of an asset with per call trust settings.
+/// Use [`Reader::json`] or [`VerificationResult::from_reader`] on the returned reader.
+/// In [`VerifyMode::Strict`] a policy failure is returned as a [`PolicyError`] wrapped in
+/// [`c2pa::Error::OtherError`].
+pub async fn verify_file_with_settings(
+    format: &str,
+    mut stream: impl Read + Seek + Send,
+    settings: &VerifySettings,
+) -> c2pa::Result<Reader> {
+    let reader = match (
+        read(format, &mut stream, settings.context()?).await,
+        &settings.remote_manifests,
+    ) {
+        (Err(c2pa::Error::RemoteManifestUrl(url)), Some(remote)) => {
+            let manifest = fetch_remote_manifest(&url, remote).await?;
+            stream.rewind()?;
+            Reader::from_context(settings.context()?)
+                .with_manifest_data_and_stream_async(&manifest, format, &mut stream)
+                .await?
+        }
+        (result, _) => result?,
+    };
+    if settings.mode == VerifyMode::Strict {
+        settings
+            .check(&VerificationResult::from_reader(&reader)?)
+            .map_err(|x| c2pa::Error::OtherError(Box::new(x)))?;
+    }
+    Ok(reader)
+}
+
+/// Extract the raw `.c2pa` manifest store embedded in an asset, for archival or to attach it
+/// again, e.g. as a sidecar. The manifest store is returned as is, without validation.
+pub fn extract_manifest(
+    format: &str,
+    mut stream: impl Read + Seek + Send,
+) -> c2pa::Result<Vec<u8>> {
+    c2pa::jumbf_io::load_jumbf_from_stream(format, &mut stream)
+}
+
+/// Verify an asset held in memory.
+pub async fn verify_bytes(format: &str, data: &[u8]) -> c2pa::Result<VerificationResult> {
+    verify_file_detailed(format, Cursor::new(data)).await
+}
+
+/// Download an asset of at most `max_bytes` over HTTP(S) to a temporary file within `timeout`
+/// and verify it. The format defaults to the `content-type` of the response.
+pub async fn verify_url(
+    url: Url,
+    format: Option<&str>,
+    max_bytes: u64,
+    timeout: Duration,
+) -> c2pa::Result<VerificationResult> {
+    let (file, content_type) = download(url, max_bytes, timeout).await?;
+    let format = format
+        .map(str::to_owned)
+        .or(content_type)
+        .ok_or(c2pa::Error::UnsupportedType)?;
+    verify_file_detailed(&format, file).await
+}
+
+async fn fetch_remote_manifest(
+    url: &str,
+    settings: &RemoteManifestSettings,
+) -> c2pa::Result<Vec<u8>> {
+    let url = Url::parse(url).map_err(|_| c2pa::Error::RemoteManifestUrl(url.to_owned()))?;
+    if !settings.is_allowed(&url) {
+        log::warn!("Remote manifest host is not allowed: {url}");
+        return Err(c2pa::Error::RemoteManifestUrl(url.to_string()));
+    }
+    log::info!("Fetching remote manifest from {url}");
+    let (mut file, _) = download(url.clone(), settings.max_bytes, settings.timeout)
+        .await
+        .map_err(|x| match x {
+            c2pa::Error::IoError(x) if x.kind() == ErrorKind::TimedOut => {
+                c2pa::Error::RemoteManifestFetch(url.to_string())
+            }
+            x => x,
+        })?;
+    let mut manifest = Vec::new();
+    file.read_to_end(&mut manifest)?;
+    Ok(manifest)
+}
+
+fn too_large(url: &Url, max_bytes: u64) -> c2pa::Error {
+    log::error!("{url} is larger than {max_bytes} bytes");
+    c2pa::Error::BadParam(format!("{url} is larger than {max_bytes} bytes"))
+}
+
+// Stream the response body to an anonymous temporary file, aborting once it is larger than
+// `max_bytes` or takes longer than `timeout`.
+async fn download(
+    url: Url,
+    max_bytes: u64,
+    timeout: Duration,
+) -> c2pa::Result<(std::fs::File, Option<String>)> {
+    let timer = sleep(azure_core::time::Duration::try_from(timeout).unwrap_or_default());
+    match select(pin!(stream_to_file(&url, max_bytes)), pin!(timer)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => {
+            log::error!("Timed out downloading {url}");
+            let message = format!("timed out downloading {url}");
+            Err(std::io::Error::new(ErrorKind::TimedOut, message).into())
+        }
+    }
+}
+
+async fn stream_to_file(
+    url: &Url,
+    max_bytes: u64,
+) -> c2pa::Result<(std::fs::File, Option<String>)> {
+    let pipeline = Pipeline::new(
+        option_env!("CARGO_PKG_NAME"),
+        option_env!("CARGO_PKG_VERSION"),
+        ClientOptions::default(),
+        vec![],
+        vec![],
+        None,
+    );
+    let mut request = Request::new(url.clone(), Method::Get);
+    let response = pipeline
+        .stream(&RequestContext::new(), &mut request, None)
+        .await
+        .map_err(|x| c2pa::Error::OtherError(Box::new(x)))?;
+    let status = u16::from(response.status());
+    if !(200..300).contains(&status) {
+        log::error!("Failed to download {url}, status: {status}");
+        return Err(c2pa::Error::ResourceNotFound(url.to_string()));
+    }
+    let content_type = response
+        .headers()
+        .get_optional_str(&CONTENT_TYPE)
+        .map(|x| x.split(';').next().unwrap_or(x).trim().to_owned());
+    let length = response
+        .headers()
+        .get_optional_str(&CONTENT_LENGTH)
+        .and_then(|x| x.parse::<u64>().ok());
+    if length.is_some_and(|x| x > max_bytes) {
+        return Err(too_large(url, max_bytes));
+    }
+
+    let mut file = tempfile::tempfile()?;
+    let mut body = response.into_body();
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|x| c2pa::Error::OtherError(Box::new(x)))?;
+        size += chunk.len() as u64;
+        if size > max_bytes {
+            return Err(too_large(url, max_bytes));
+        }
+        file.write_all(&chunk)?;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, content_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detailed_result_from_json() {
+        let result =
+            VerificationResult::from_json(include_str!("../../test_data/manifest.json")).unwrap();
+        assert_eq!(result.validation_state, ValidationState::Trusted);
+        assert_eq!(result.manifests.len(), 1);
+        assert_eq!(result.failures().count(), 0);
+        assert!(
+            result.manifests[0]
+                .codes
+                .success
+                .iter()
+                .any(|c| c.code == "claimSignature.validated")
+        );
+        let signature = result.signature.unwrap();
+        assert_eq!(signature.alg.as_deref(), Some("Ps384"));
+        assert!(signature.time.is_some());
+        let timestamp = result.timestamp.unwrap();
+        assert!(timestamp.validated && timestamp.trusted);
+        assert!(timestamp.authority.is_none());
+        assert!(timestamp.signed_before(SystemTime::now()));
+        assert!(!timestamp.signed_before(SystemTime::UNIX_EPOCH));
+    }
+
+    #[tokio::test]
+    async fn test_verify_bytes() {
+        let data = include_bytes!("../../test_data/signed.png");
+        let result = verify_bytes("png", data).await.unwrap();
+        assert!(result.active_manifest.is_some());
+        assert!(result.signature.is_some());
+    }
+
+    // A PNG without a manifest whose XMP references a remote one on `url`.
+    fn remote_reference(url: &str) -> Vec<u8> {
+        fn crc32(bytes: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for byte in bytes {
+                crc ^= u32::from(*byte);
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+                }
+            }
+            !crc
+        }
+        let xmp = format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description rdf:about="" xmlns:dcterms="http://purl.org/dc/terms/" dcterms:provenance="{url}"/></rdf:RDF></x:xmpmeta>"#
+        );
+        let mut chunk = b"iTXtXML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        chunk.extend_from_slice(xmp.as_bytes());
+        let data = include_bytes!("../../test_data/sample1.png");
+        // The 8 byte signature and the 25 byte IHDR chunk come first.
+        let mut png = data[..33].to_vec();
+        png.extend_from_slice(&(chunk.len() as u32 - 4).to_be_bytes());
+        png.extend_from_slice(&chunk);
+        png.extend_from_slice(&crc32(&chunk).to_be_bytes());
+        png.extend_from_slice(&data[33..]);
+        png
+    }
+
+    #[tokio::test]
+    async fn test_unlisted_remote_manifest_is_not_fetched() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/manifest.c2pa", listener.local_addr().unwrap());
+        let png = remote_reference(&url);
+
+        assert!(verify_bytes("png", &png).await.is_err());
+        assert!(verify_file("png", Cursor::new(&png)).await.is_err());
+        let settings = VerifySettings {
+            remote_manifests: Some(RemoteManifestSettings {
+                allowed_hosts: vec!["manifests.contoso.com".to_owned()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let result = verify_file_with_settings("png", Cursor::new(&png), &settings).await;
+        assert!(matches!(result, Err(c2pa::Error::RemoteManifestUrl(x)) if x == url));
+        // Nothing connected to the host of the reference.
+        let err = listener.accept().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_extract_manifest() {
+        let data = include_bytes!("../../test_data/signed.png");
+        let manifest = extract_manifest("png", Cursor::new(data)).unwrap();
+        // A JUMBF superbox.
+        assert_eq!(&manifest[4..8], b"jumb");
+        let data = include_bytes!("../../test_data/sample1.png");
+        assert!(extract_manifest("png", Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_identity_assertions() {
+        let json = r#"{
+            "active_manifest": "urn:c2pa:a",
+            "manifests": {
+                "urn:c2pa:a": {
+                    "assertions": [
+                        { "label": "cawg.identity", "data": { "verifiedIdentities": [{ "name": "Contoso" }] } },
+                        { "label": "c2pa.actions.v2", "data": {} }
+                    ]
+                }
+            },
+            "validation_results": {
+                "activeManifest": {
+                    "success": [
+                        { "code": "cawg.identity.well-formed", "url": "self#jumbf=/c2pa/urn:c2pa:a/c2pa.assertions/cawg.identity" },
+                        { "code": "cawg.ica.credential.trusted", "url": "self#jumbf=/c2pa/urn:c2pa:a/c2pa.assertions/cawg.identity" }
+                    ]
+                }
+            },
+            "validation_state": "Valid"
+        }"#;
+        let result = VerificationResult::from_json(json).unwrap();
+        assert_eq!(result.identities.len(), 1);
+        let identity = &result.identities[0];
+        assert_eq!(identity.trust, IdentityTrust::Trusted);
+        assert_eq!(identity.codes.len(), 2);
+        assert_eq!(identity.claims["verifiedIdentities"][0]["name"], "Contoso");
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut result =
+            VerificationResult::from_json(include_str!("../../test_data/manifest.json")).unwrap();
+        assert_eq!(result.summary().verdict, Verdict::Trusted);
+        result.validation_state = ValidationState::Invalid;
+        result.manifests[0].codes.failure.push(ValidationCode {
+            code: "assertion.dataHash.mismatch".to_owned(),
+            url: None,
+            explanation: None,
+        });
+        let summary = result.summary();
+        assert_eq!(summary.verdict, Verdict::Invalid);
+        assert_eq!(summary.failures, vec!["assertion.dataHash.mismatch"]);
+        let summary = ValidationSummary::from_result(Err(c2pa::Error::JumbfNotFound)).unwrap();
+        assert_eq!(summary.verdict, Verdict::NoManifest);
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let mut result =
+            VerificationResult::from_json(include_str!("../../test_data/manifest.json")).unwrap();
+        let settings = VerifySettings {
+            mode: VerifyMode::Strict,
+            ..Default::default()
+        };
+        assert!(settings.check(&result).is_ok());
+        assert!(settings.to_toml().contains("ocsp_fetch = true"));
+        result.validation_state = ValidationState::Valid;
+        assert!(settings.check(&result).is_err());
+        assert!(VerifySettings::default().check(&result).is_ok());
+    }
+
+    #[test]
+    fn test_remote_manifest_hosts() {
+        let settings = RemoteManifestSettings {
+            allowed_hosts: vec![
+                "manifests.contoso.com".to_owned(),
+                "*.fabrikam.com".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let allowed = |url: &str| settings.is_allowed(&Url::parse(url).unwrap());
+        assert!(allowed("https://manifests.contoso.com/a.c2pa"));
+        assert!(allowed("https://cdn.fabrikam.com/a.c2pa"));
+        assert!(!allowed("https://fabrikam.com/a.c2pa"));
+        assert!(!allowed("https://evilfabrikam.com/a.c2pa"));
+        assert!(!allowed("https://contoso.com/a.c2pa"));
+        assert!(
+            !RemoteManifestSettings::default().is_allowed(&Url::parse("https://x.y/z").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_typed_settings_override_toml() {
+        let mut settings =
+            VerifySettings::from_toml("[verify]\nverify_trust = true\nverify_after_sign = true")
+                .unwrap();
+        settings.verify_trust = Some(false);
+        settings.trust_anchors = Some("anchors".to_owned());
+        let table: toml::Table = settings.to_toml().parse().unwrap();
+        assert_eq!(table["verify"]["verify_trust"].as_bool(), Some(false));
+        assert_eq!(table["verify"]["verify_after_sign"].as_bool(), Some(true));
+        assert_eq!(table["trust"]["trust_anchors"].as_str(), Some("anchors"));
+        assert!(VerifySettings::from_toml("[verify").is_err());
+    }
+}